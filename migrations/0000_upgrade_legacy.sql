@@ -0,0 +1,45 @@
+-- Brings databases created by hand from the old inventory.sql up to what
+-- 0001_initial_schema.sql expects, as its CREATE TABLE IF NOT EXISTS leaves the
+-- existing tables as they are. Does nothing on new databases (where there are
+-- no tables yet) nor on migrated ones (where sqlx may still apply it, after the
+-- rest, as it wasn't there when they were migrated)
+
+ALTER TABLE IF EXISTS parts ADD COLUMN IF NOT EXISTS reorder_point INTEGER;
+ALTER TABLE IF EXISTS parts ADD COLUMN IF NOT EXISTS deleted_at TIMESTAMPTZ;
+
+-- Values were REAL, the old view is dropped to change their type (0001 creates
+-- the new one). Only done while they're still REAL, as the view would be lost
+-- otherwise
+DO $$
+BEGIN
+    IF EXISTS (
+        SELECT 1 FROM information_schema.columns
+        WHERE table_schema = current_schema() AND table_name = 'parts'
+            AND column_name = 'value' AND data_type = 'real'
+    ) THEN
+        DROP VIEW IF EXISTS inventory;
+        ALTER TABLE parts ALTER COLUMN value TYPE NUMERIC USING value::NUMERIC;
+    END IF;
+END
+$$;
+
+-- (Likewise for the ratings)
+DO $$
+BEGIN
+    IF EXISTS (
+        SELECT 1 FROM information_schema.columns
+        WHERE table_schema = current_schema() AND table_name = 'parts'
+            AND column_name IN ('volt_rating', 'watt_rating') AND data_type = 'real'
+    ) THEN
+        DROP VIEW IF EXISTS inventory;
+        ALTER TABLE parts
+            ALTER COLUMN volt_rating TYPE NUMERIC USING volt_rating::NUMERIC,
+            ALTER COLUMN watt_rating TYPE NUMERIC USING watt_rating::NUMERIC;
+    END IF;
+END
+$$;
+
+-- (Being generated, this also fills it in for the existing parts)
+ALTER TABLE IF EXISTS parts ADD COLUMN IF NOT EXISTS search_vector tsvector GENERATED ALWAYS AS (
+    to_tsvector('simple', COALESCE(mpn, '') || ' ' || COALESCE(comments, ''))
+) STORED;
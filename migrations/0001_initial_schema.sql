@@ -0,0 +1,174 @@
+-- Run at startup by sqlx::migrate!(). This first migration is written so that
+-- it can also be applied to databases created by hand from the old inventory.sql
+-- (hence the IF NOT EXISTS everywhere)
+
+-- Argon2 hashes in PHC string format. If the table is empty on startup, an
+-- "admin" user is created from LOGIN_PASSWORD
+CREATE TABLE IF NOT EXISTS users (
+    id SERIAL PRIMARY KEY,
+    username TEXT UNIQUE NOT NULL,
+    argon2_hash TEXT NOT NULL,
+    role TEXT NOT NULL DEFAULT 'user',
+    -- Base32, two-factor authentication is disabled if NULL
+    totp_secret TEXT
+);
+
+-- Keys for scripts, which authenticate with "Authorization: Bearer <key>".
+-- Only the SHA-256 of the key is stored, it's shown to the user once
+CREATE TABLE IF NOT EXISTS api_keys (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    key_hash TEXT UNIQUE NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_used_at TIMESTAMPTZ
+);
+
+CREATE TABLE IF NOT EXISTS categories (
+    id SERIAL PRIMARY KEY,
+    name TEXT UNIQUE NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS footprints (
+    id SERIAL PRIMARY KEY,
+    name TEXT UNIQUE NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS parts (
+    id SERIAL PRIMARY KEY,
+    category_id INTEGER NOT NULL REFERENCES categories(id),
+    footprint_id INTEGER REFERENCES footprints(id),
+    
+    mpn TEXT UNIQUE,            
+    
+    -- Exact, so equal values compare equal. Existing tables are converted with
+    -- (the view has to be dropped and recreated around it):
+    --   ALTER TABLE parts ALTER COLUMN value TYPE NUMERIC USING value::NUMERIC;
+    value NUMERIC,
+    -- (Converted like value above, exposed as voltage_rating and power_rating)
+    volt_rating NUMERIC,
+    watt_rating NUMERIC,
+    amp_rating REAL,
+    percent_tol REAL,
+    stats TEXT,
+    comments TEXT,
+    reorder_point INTEGER,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    -- Soft-deletion, so that parts can be restored
+    deleted_at TIMESTAMPTZ,
+    -- Full-text search, the category is matched separately as it lives in its
+    -- own table. Being generated, adding it to an existing table backfills it:
+    --   ALTER TABLE parts ADD COLUMN search_vector tsvector GENERATED ALWAYS AS (...) STORED;
+    search_vector tsvector GENERATED ALWAYS AS (
+        to_tsvector('simple', COALESCE(mpn, '') || ' ' || COALESCE(comments, ''))
+    ) STORED
+);
+
+CREATE TABLE IF NOT EXISTS locations (
+    id SERIAL PRIMARY KEY,
+    name TEXT UNIQUE NOT NULL,
+    description TEXT
+);
+
+CREATE TABLE IF NOT EXISTS stock (
+    id SERIAL PRIMARY KEY,
+    part_id INTEGER NOT NULL REFERENCES parts(id) ON DELETE CASCADE,
+    location_id INTEGER REFERENCES locations(id) ON DELETE RESTRICT,
+    
+    quantity INTEGER DEFAULT 0 CHECK (quantity >= 0),
+    updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    staged INTEGER CHECK (staged >= 0),
+
+    UNIQUE(part_id, location_id)
+);
+
+-- Audit log of changes to parts and their stock
+CREATE TABLE IF NOT EXISTS stock_log (
+    id SERIAL PRIMARY KEY,
+    part_id INTEGER NOT NULL REFERENCES parts(id) ON DELETE CASCADE,
+    field TEXT NOT NULL,
+    delta INTEGER,
+    old_value TEXT,
+    new_value TEXT,
+    timestamp TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- A photo of the part (or its reel / package), to help identify it
+CREATE TABLE IF NOT EXISTS part_image (
+    part_id INTEGER PRIMARY KEY REFERENCES parts(id) ON DELETE CASCADE,
+    content_type TEXT NOT NULL,
+    data BYTEA NOT NULL
+);
+
+-- Unit shown next to the values of each category, which may be set before any
+-- part of the category exists
+CREATE TABLE IF NOT EXISTS category_unit (
+    category TEXT PRIMARY KEY,
+    unit TEXT NOT NULL,
+    use_si_prefix BOOLEAN NOT NULL DEFAULT TRUE
+);
+
+INSERT INTO category_unit (category, unit, use_si_prefix) VALUES
+    ('CapCeramic', 'F', TRUE),
+    ('CapElectro', 'F', TRUE),
+    ('Resistor', 'Ω', TRUE),
+    ('Inductor', 'H', TRUE)
+ON CONFLICT DO NOTHING;
+
+CREATE INDEX IF NOT EXISTS idx_stock_part_id ON stock(part_id);
+CREATE INDEX IF NOT EXISTS idx_stock_location_id ON stock(location_id);
+CREATE INDEX IF NOT EXISTS idx_parts_category_id ON parts(category_id);
+CREATE INDEX IF NOT EXISTS idx_parts_footprint_id ON parts(footprint_id);
+CREATE INDEX IF NOT EXISTS idx_stock_log_part_id ON stock_log(part_id);
+CREATE INDEX IF NOT EXISTS idx_parts_search_vector ON parts USING GIN (search_vector);
+
+CREATE OR REPLACE FUNCTION update_updated_at()
+RETURNS TRIGGER AS $$
+BEGIN
+    NEW.updated_at = CURRENT_TIMESTAMP;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE TRIGGER stock_updated_at
+    BEFORE UPDATE ON stock
+    FOR EACH ROW
+    EXECUTE FUNCTION update_updated_at();
+
+-- One row per part, with its stock summed across all locations. The location
+-- shown is the one holding the most stock
+DROP VIEW IF EXISTS inventory;
+CREATE VIEW inventory AS
+SELECT
+    p.id,
+    p.mpn,
+    c.name AS category,
+    f.name AS footprint,
+    p.value,
+    p.volt_rating AS voltage_rating,
+    p.watt_rating AS power_rating,
+    (
+        SELECT l.name FROM stock ls
+        LEFT JOIN locations l ON ls.location_id = l.id
+        WHERE ls.part_id = p.id
+        ORDER BY ls.quantity DESC NULLS LAST, ls.id
+        LIMIT 1
+    ) AS location,
+    s.quantity,
+    s.staged,
+    p.comments,
+    p.reorder_point,
+    p.deleted_at,
+    p.search_vector,
+    EXISTS (SELECT 1 FROM part_image pi WHERE pi.part_id = p.id) AS has_image
+FROM parts p
+LEFT JOIN (
+    SELECT
+        part_id,
+        SUM(quantity)::INTEGER AS quantity,
+        SUM(staged)::INTEGER AS staged
+    FROM stock
+    GROUP BY part_id
+) s ON p.id = s.part_id
+LEFT JOIN categories c ON p.category_id = c.id
+LEFT JOIN footprints f ON p.footprint_id = f.id;
@@ -0,0 +1,57 @@
+-- Named projects (boards which get built repeatedly) and the parts they use
+CREATE TABLE projects (
+    id SERIAL PRIMARY KEY,
+    name TEXT UNIQUE NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Stock may be reserved for a project, so that it isn't claimed by another.
+-- Unlike staged, it's kept per part and not per location
+CREATE TABLE project_parts (
+    project_id INTEGER NOT NULL REFERENCES projects(id) ON DELETE CASCADE,
+    part_id INTEGER NOT NULL REFERENCES parts(id) ON DELETE CASCADE,
+    qty_per_board INTEGER NOT NULL CHECK (qty_per_board > 0),
+    reserved INTEGER NOT NULL DEFAULT 0 CHECK (reserved >= 0),
+
+    PRIMARY KEY (project_id, part_id)
+);
+
+CREATE INDEX idx_project_parts_part_id ON project_parts(part_id);
+
+-- (Same as before, plus the stock reserved across all projects)
+DROP VIEW IF EXISTS inventory;
+CREATE VIEW inventory AS
+SELECT
+    p.id,
+    p.mpn,
+    c.name AS category,
+    f.name AS footprint,
+    p.value,
+    p.volt_rating AS voltage_rating,
+    p.watt_rating AS power_rating,
+    (
+        SELECT l.name FROM stock ls
+        LEFT JOIN locations l ON ls.location_id = l.id
+        WHERE ls.part_id = p.id
+        ORDER BY ls.quantity DESC NULLS LAST, ls.id
+        LIMIT 1
+    ) AS location,
+    s.quantity,
+    s.staged,
+    p.comments,
+    p.reorder_point,
+    p.deleted_at,
+    p.search_vector,
+    EXISTS (SELECT 1 FROM part_image pi WHERE pi.part_id = p.id) AS has_image,
+    (SELECT SUM(pp.reserved) FROM project_parts pp WHERE pp.part_id = p.id)::INTEGER AS reserved
+FROM parts p
+LEFT JOIN (
+    SELECT
+        part_id,
+        SUM(quantity)::INTEGER AS quantity,
+        SUM(staged)::INTEGER AS staged
+    FROM stock
+    GROUP BY part_id
+) s ON p.id = s.part_id
+LEFT JOIN categories c ON p.category_id = c.id
+LEFT JOIN footprints f ON p.footprint_id = f.id;
@@ -0,0 +1,11 @@
+-- Interchangeable parts (e.g. the same capacitor from different vendors). Each
+-- link is stored once, with the lower id first
+CREATE TABLE alternates (
+    part_a INTEGER NOT NULL REFERENCES parts(id) ON DELETE CASCADE,
+    part_b INTEGER NOT NULL REFERENCES parts(id) ON DELETE CASCADE,
+
+    PRIMARY KEY (part_a, part_b),
+    CHECK (part_a < part_b)
+);
+
+CREATE INDEX idx_alternates_part_b ON alternates(part_b);
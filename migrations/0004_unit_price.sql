@@ -0,0 +1,3 @@
+-- Price paid per unit, in no particular currency. Parts without one don't
+-- count towards the stock value
+ALTER TABLE parts ADD COLUMN unit_price NUMERIC;
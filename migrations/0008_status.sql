@@ -0,0 +1,48 @@
+-- Lifecycle of the part, NRND (not recommended for new designs) and obsolete
+-- parts are still stocked but shouldn't be designed in
+CREATE TYPE part_status AS ENUM ('active', 'nrnd', 'obsolete', 'sample');
+
+ALTER TABLE parts ADD COLUMN status part_status NOT NULL DEFAULT 'active';
+
+-- (Same as before, plus the status)
+DROP VIEW IF EXISTS inventory;
+CREATE VIEW inventory AS
+SELECT
+    p.id,
+    p.mpn,
+    c.name AS category,
+    f.name AS footprint,
+    p.value,
+    p.volt_rating AS voltage_rating,
+    p.watt_rating AS power_rating,
+    (
+        SELECT l.name FROM stock ls
+        LEFT JOIN locations l ON ls.location_id = l.id
+        WHERE ls.part_id = p.id
+        ORDER BY ls.quantity DESC NULLS LAST, ls.id
+        LIMIT 1
+    ) AS location,
+    s.quantity,
+    s.staged,
+    p.comments,
+    p.reorder_point,
+    p.unit_price,
+    p.tags,
+    p.supplier,
+    p.supplier_url,
+    p.status,
+    p.deleted_at,
+    p.search_vector,
+    EXISTS (SELECT 1 FROM part_image pi WHERE pi.part_id = p.id) AS has_image,
+    (SELECT SUM(pp.reserved) FROM project_parts pp WHERE pp.part_id = p.id)::INTEGER AS reserved
+FROM parts p
+LEFT JOIN (
+    SELECT
+        part_id,
+        SUM(quantity)::INTEGER AS quantity,
+        SUM(staged)::INTEGER AS staged
+    FROM stock
+    GROUP BY part_id
+) s ON p.id = s.part_id
+LEFT JOIN categories c ON p.category_id = c.id
+LEFT JOIN footprints f ON p.footprint_id = f.id;
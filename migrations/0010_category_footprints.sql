@@ -0,0 +1,14 @@
+-- Every category and footprint combination in use, for the filter lists. Kept
+-- apart as DISTINCT over the whole inventory gets slow with many parts, at the
+-- cost of being refreshed (in the background) after parts are changed
+CREATE MATERIALIZED VIEW category_footprints AS
+SELECT DISTINCT
+    c.name AS category,
+    f.name AS footprint
+FROM parts p
+JOIN categories c ON p.category_id = c.id
+LEFT JOIN footprints f ON p.footprint_id = f.id
+WHERE p.deleted_at IS NULL;
+
+-- (Needed to refresh it concurrently, so that the lists keep working meanwhile)
+CREATE UNIQUE INDEX idx_category_footprints ON category_footprints(category, footprint);
@@ -0,0 +1,20 @@
+-- Staged parts can't be more than there are in stock. Lowering the quantity
+-- clamps the staged amount (before the check below sees the row), so any
+-- statement changing the quantity keeps them consistent
+CREATE OR REPLACE FUNCTION clamp_staged()
+RETURNS TRIGGER AS $$
+BEGIN
+    NEW.staged = LEAST(NEW.staged, NEW.quantity);
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE TRIGGER stock_clamp_staged
+    BEFORE INSERT OR UPDATE ON stock
+    FOR EACH ROW
+    EXECUTE FUNCTION clamp_staged();
+
+-- (Rows from before the trigger)
+UPDATE stock SET staged = quantity WHERE staged > quantity;
+
+ALTER TABLE stock ADD CONSTRAINT stock_staged_le_quantity CHECK (staged <= quantity);
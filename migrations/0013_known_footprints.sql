@@ -0,0 +1,5 @@
+-- Footprints that parts may use. While empty any footprint is accepted, once
+-- filled new and edited parts are checked against it (see admin.rs)
+CREATE TABLE known_footprints (
+    name TEXT PRIMARY KEY
+);
@@ -0,0 +1,2 @@
+-- For the search box suggestions, which match the start of MPNs in any case
+CREATE INDEX IF NOT EXISTS idx_parts_mpn_lower ON parts (LOWER(mpn) text_pattern_ops);
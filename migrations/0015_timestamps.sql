@@ -0,0 +1,64 @@
+-- When parts were added and last changed (by any update of the part's row,
+-- stock changes are in the stock log). Parts from before have no better guess
+-- for their last change than when they were added
+ALTER TABLE parts ALTER COLUMN created_at TYPE TIMESTAMPTZ;
+UPDATE parts SET created_at = CURRENT_TIMESTAMP WHERE created_at IS NULL;
+ALTER TABLE parts ALTER COLUMN created_at SET NOT NULL;
+
+ALTER TABLE parts ADD COLUMN updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP;
+UPDATE parts SET updated_at = created_at;
+
+CREATE OR REPLACE TRIGGER parts_updated_at
+    BEFORE UPDATE ON parts
+    FOR EACH ROW
+    EXECUTE FUNCTION update_updated_at();
+
+-- For the "recently added" and "recently changed" sorts
+CREATE INDEX IF NOT EXISTS idx_parts_created_at ON parts(created_at);
+CREATE INDEX IF NOT EXISTS idx_parts_updated_at ON parts(updated_at);
+
+-- (Same as before, plus the timestamps)
+DROP VIEW IF EXISTS inventory;
+CREATE VIEW inventory AS
+SELECT
+    p.id,
+    p.mpn,
+    c.name AS category,
+    f.name AS footprint,
+    p.value,
+    p.volt_rating AS voltage_rating,
+    p.watt_rating AS power_rating,
+    (
+        SELECT l.name FROM stock ls
+        LEFT JOIN locations l ON ls.location_id = l.id
+        WHERE ls.part_id = p.id
+        ORDER BY ls.quantity DESC NULLS LAST, ls.id
+        LIMIT 1
+    ) AS location,
+    s.quantity,
+    s.staged,
+    p.comments,
+    p.reorder_point,
+    p.unit_price,
+    p.tags,
+    p.supplier,
+    p.supplier_url,
+    p.status,
+    p.version,
+    p.created_at,
+    p.updated_at,
+    p.deleted_at,
+    p.search_vector,
+    EXISTS (SELECT 1 FROM part_image pi WHERE pi.part_id = p.id) AS has_image,
+    (SELECT SUM(pp.reserved) FROM project_parts pp WHERE pp.part_id = p.id)::INTEGER AS reserved
+FROM parts p
+LEFT JOIN (
+    SELECT
+        part_id,
+        SUM(quantity)::INTEGER AS quantity,
+        SUM(staged)::INTEGER AS staged
+    FROM stock
+    GROUP BY part_id
+) s ON p.id = s.part_id
+LEFT JOIN categories c ON p.category_id = c.id
+LEFT JOIN footprints f ON p.footprint_id = f.id;
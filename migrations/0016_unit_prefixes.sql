@@ -0,0 +1,3 @@
+-- SI prefixes values of the category are shown with, e.g. "k M G" for
+-- frequencies ("-" being no prefix). NULL picks from all of them, p to G
+ALTER TABLE category_unit ADD COLUMN si_prefixes TEXT;
@@ -0,0 +1,3 @@
+-- For the full-text search, which also matches the category names
+CREATE INDEX IF NOT EXISTS idx_categories_name_search ON categories
+    USING GIN (to_tsvector('simple', name));
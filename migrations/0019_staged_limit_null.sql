@@ -0,0 +1,11 @@
+-- 0012 clamped with LEAST, which ignores NULLs, so every stock update with
+-- nothing staged staged the whole quantity
+CREATE OR REPLACE FUNCTION clamp_staged()
+RETURNS TRIGGER AS $$
+BEGIN
+    IF NEW.staged > NEW.quantity THEN
+        NEW.staged = NEW.quantity;
+    END IF;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
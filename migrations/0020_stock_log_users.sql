@@ -0,0 +1,3 @@
+-- Who made each change, NULL for changes from before there were users (and
+-- of users deleted since)
+ALTER TABLE stock_log ADD COLUMN IF NOT EXISTS user_id INTEGER REFERENCES users(id) ON DELETE SET NULL;
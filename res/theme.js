@@ -0,0 +1,10 @@
+// Switches between the light and dark themes, starting from the system one.
+// The cookie is read by the server, so that pages are sent with the theme set
+function toggleTheme() {
+    const root = document.documentElement;
+    const current = root.dataset.theme
+        || (window.matchMedia('(prefers-color-scheme: dark)').matches ? 'dark' : 'light');
+    const theme = current === 'dark' ? 'light' : 'dark';
+    root.dataset.theme = theme;
+    document.cookie = 'theme=' + theme + '; path=/; max-age=31536000; SameSite=Lax';
+}
@@ -0,0 +1,500 @@
+use std::collections::HashMap;
+
+use axum::{
+    Extension, Form,
+    extract::State,
+    http::StatusCode,
+    response::{Html, IntoResponse},
+};
+use maud::{Markup, html};
+use serde::Deserialize;
+use sqlx::{Acquire, PgConnection, QueryBuilder};
+use tower_sessions::Session;
+use tracing::info;
+
+use axum_extra::extract::Form as MultiForm;
+
+use crate::{
+    auth::{CurrentUser, csrf_token},
+    inventory::{
+        BulkChange, Columns, bulk_update_items, handle_generic_inventory_error,
+        html_static_table_header, html_table_row, merge_parts, normalize_name, query_items,
+    },
+    layout::{Theme, html_page},
+    state::AppState,
+};
+
+// Categories which only differ in case or whitespace, each with its part count.
+// New parts already reuse the existing category, this finds those left over
+// from before (or from imports into the database by hand)
+pub async fn normalize_categories_page(
+    State(state): State<AppState>,
+    session: Session,
+    theme: Theme,
+) -> impl IntoResponse {
+    info!("Performing category normalization report");
+
+    let csrf_token = match csrf_token(&session).await {
+        Ok(token) => token,
+        Err(e) => return handle_generic_inventory_error(e),
+    };
+
+    let mut query = QueryBuilder::new(
+        "SELECT c.name, COUNT(p.id) FROM categories c \
+         LEFT JOIN parts p ON p.category_id = c.id AND p.deleted_at IS NULL \
+         GROUP BY c.id ORDER BY c.id",
+    );
+    let categories = match query
+        .build_query_as::<(String, i64)>()
+        .fetch_all(&state.pool)
+        .await
+    {
+        Ok(categories) => categories,
+        Err(e) => return handle_generic_inventory_error(e),
+    };
+
+    // (In order of creation, so the first of each group is the one kept)
+    let mut groups: Vec<(String, Vec<(String, i64)>)> = vec![];
+    for (name, count) in categories {
+        let key = normalize_name(&name).to_lowercase();
+        match groups.iter_mut().find(|(x, _)| *x == key) {
+            Some((_, variants)) => variants.push((name, count)),
+            None => groups.push((key, vec![(name, count)])),
+        }
+    }
+    groups.retain(|(_, variants)| {
+        variants.len() > 1
+            || variants
+                .iter()
+                .any(|(name, _)| *name != normalize_name(name))
+    });
+
+    let content = html! {
+        @if groups.is_empty() {
+            p { "All categories are normalized." }
+        } @else {
+            p { "These categories are the same except for case or whitespace, so they are listed separately." }
+            div class="overflow-auto" {
+                table class="striped" {
+                    thead {
+                        tr {
+                            th scope="col" { "Category" }
+                            th scope="col" { "Variants (parts)" }
+                        }
+                    }
+                    tbody {
+                        @for (_, variants) in &groups {
+                            tr {
+                                th scope="row" { (normalize_name(&variants[0].0)) }
+                                td {
+                                    @for (i, (name, count)) in variants.iter().enumerate() {
+                                        @if i > 0 { ", " }
+                                        code { (name) } " (" (count) ")"
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    };
+
+    Html(html_page("Category Normalization", &csrf_token, theme, content).into_string())
+        .into_response()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RenameFootprintForm {
+    from: String,
+    to: String,
+}
+
+#[derive(Deserialize)]
+pub struct KnownFootprintForm {
+    name: String,
+    // Unchecking the box leaves it out, which removes the footprint
+    known: Option<String>,
+}
+
+// Every footprint with the number of parts using it, so that variants of the
+// same one (e.g. "0805" and "C0805") stand out next to each other. Also those
+// which are known but unused, and whether each is known
+async fn query_footprint_counts(state: &AppState) -> Result<Vec<(String, i64, bool)>, sqlx::Error> {
+    let mut query = QueryBuilder::new(
+        "SELECT n.name, COUNT(p.id), k.name IS NOT NULL \
+         FROM (SELECT name FROM footprints UNION SELECT name FROM known_footprints) n \
+         LEFT JOIN footprints f ON f.name = n.name \
+         LEFT JOIN parts p ON p.footprint_id = f.id AND p.deleted_at IS NULL \
+         LEFT JOIN known_footprints k ON k.name = n.name \
+         GROUP BY n.name, k.name ORDER BY n.name",
+    );
+    query
+        .build_query_as::<(String, i64, bool)>()
+        .fetch_all(&state.pool)
+        .await
+}
+
+fn html_footprint_table(footprints: &[(String, i64, bool)]) -> Markup {
+    html! {
+        div class="overflow-auto" {
+            table class="striped" {
+                thead {
+                    tr {
+                        th scope="col" { "Footprint" }
+                        th scope="col" { "Parts" }
+                        th scope="col" { "Known" }
+                    }
+                }
+                tbody {
+                    @for (name, count, known) in footprints {
+                        tr {
+                            th scope="row" { code { (name) } }
+                            td { (count) }
+                            td {
+                                input
+                                type="checkbox"
+                                name="known"
+                                checked[*known]
+                                aria-label={"Known footprint " (name)}
+                                hx-post="/admin/footprints/known"
+                                hx-vals=(serde_json::json!({ "name": name }))
+                                hx-target="#footprints";
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub async fn footprints_page(
+    State(state): State<AppState>,
+    session: Session,
+    theme: Theme,
+) -> impl IntoResponse {
+    info!("Performing footprint count query");
+
+    let csrf_token = match csrf_token(&session).await {
+        Ok(token) => token,
+        Err(e) => return handle_generic_inventory_error(e),
+    };
+
+    let footprints = match query_footprint_counts(&state).await {
+        Ok(footprints) => footprints,
+        Err(e) => return handle_generic_inventory_error(e),
+    };
+
+    let content = html! {
+        p {
+            "Once any footprint is marked as known, parts can only be added with "
+            "(or edited to) a known footprint."
+        }
+        form hx-post="/admin/footprints/known" hx-target="#footprints" hx-on::after-request="if (event.detail.successful) this.reset()" {
+            input type="hidden" name="known" value="on";
+            div role="group" {
+                input type="text" name="name" placeholder="Known footprint" aria-label="Known footprint" required;
+                button type="submit" class="secondary" { "Add" }
+            }
+        }
+        form hx-post="/admin/footprints/rename" hx-target="#footprints" {
+            div role="group" {
+                input type="text" name="from" placeholder="Rename footprint" aria-label="Footprint to rename" list="footprint-names" required;
+                input type="text" name="to" placeholder="To" aria-label="New footprint" list="footprint-names" required;
+                button type="submit" { "Rename" }
+            }
+        }
+        datalist id="footprint-names" {
+            @for (name, _, _) in &footprints {
+                option value=(name) {}
+            }
+        }
+        div id="footprints" {
+            (html_footprint_table(&footprints))
+        }
+    };
+
+    Html(html_page("Footprints", &csrf_token, theme, content).into_string()).into_response()
+}
+
+async fn rename_footprint(
+    from: &str,
+    to: &str,
+    user_id: Option<i32>,
+    db_conn: &mut PgConnection,
+) -> Result<u64, sqlx::Error> {
+    let mut tx = db_conn.begin().await?;
+
+    let mut query = QueryBuilder::new(
+        "SELECT p.id FROM parts p JOIN footprints f ON p.footprint_id = f.id \
+         WHERE p.deleted_at IS NULL AND f.name = ",
+    );
+    query.push_bind(from);
+    let ids = query
+        .build_query_scalar::<i32>()
+        .fetch_all(&mut *tx)
+        .await?;
+
+    // (So that the change is logged for each part)
+    let affected = bulk_update_items(
+        &ids,
+        &BulkChange::Footprint(Some(to.to_string())),
+        user_id,
+        &mut tx,
+    )
+    .await?
+    .updated;
+
+    // (Deleted parts keep the old footprint, in case they are restored)
+    let mut query = QueryBuilder::new("DELETE FROM footprints WHERE name = ");
+    query.push_bind(from);
+    query.push(" AND NOT EXISTS (SELECT 1 FROM parts p WHERE p.footprint_id = footprints.id)");
+    query.build().execute(&mut *tx).await?;
+
+    tx.commit().await?;
+
+    Ok(affected)
+}
+
+pub async fn rename_footprint_handler(
+    State(state): State<AppState>,
+    Extension(CurrentUser(user_id)): Extension<CurrentUser>,
+    Form(form): Form<RenameFootprintForm>,
+) -> impl IntoResponse {
+    let from = form.from.trim();
+    let to = form.to.trim();
+    info!("Renaming footprint {} to {}", from, to);
+
+    if from.is_empty() || to.is_empty() || from == to {
+        let response = html! {
+            p style="color: red;" { "Enter the footprint to rename and a different new name." }
+        };
+        return (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Html(response.into_string()),
+        )
+            .into_response();
+    }
+
+    let mut db_conn = match state.pool.acquire().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            return handle_generic_inventory_error(e);
+        }
+    };
+
+    let affected = match rename_footprint(from, to, Some(user_id), &mut db_conn).await {
+        Ok(affected) => affected,
+        Err(e) => return handle_generic_inventory_error(e),
+    };
+    state.list_cache.invalidate();
+    state.notify_change(None);
+
+    let footprints = match query_footprint_counts(&state).await {
+        Ok(footprints) => footprints,
+        Err(e) => return handle_generic_inventory_error(e),
+    };
+
+    let response = html! {
+        p { "Renamed " (from) " to " (to) " on " (affected) " parts." }
+        (html_footprint_table(&footprints))
+    };
+    Html(response.into_string()).into_response()
+}
+
+pub async fn known_footprint_handler(
+    State(state): State<AppState>,
+    Form(form): Form<KnownFootprintForm>,
+) -> impl IntoResponse {
+    let name = form.name.trim();
+    info!("Setting footprint {} known: {}", name, form.known.is_some());
+
+    if name.is_empty() {
+        let response = html! {
+            p style="color: red;" { "Enter a footprint." }
+        };
+        return (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Html(response.into_string()),
+        )
+            .into_response();
+    }
+
+    let mut query = if form.known.is_some() {
+        let mut query = QueryBuilder::new("INSERT INTO known_footprints (name) VALUES (");
+        query.push_bind(name);
+        query.push(") ON CONFLICT DO NOTHING");
+        query
+    } else {
+        let mut query = QueryBuilder::new("DELETE FROM known_footprints WHERE name = ");
+        query.push_bind(name);
+        query
+    };
+    if let Err(e) = query.build().execute(&state.pool).await {
+        return handle_generic_inventory_error(e);
+    }
+
+    match query_footprint_counts(&state).await {
+        Ok(footprints) => Html(html_footprint_table(&footprints).into_string()).into_response(),
+        Err(e) => handle_generic_inventory_error(e),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MergeDuplicatesForm {
+    #[serde(default)]
+    ids: Vec<i32>,
+}
+
+// Groups of parts which are likely the same one entered more than once: those
+// with the same MPN (ignoring case and whitespace), or with the same category,
+// footprint and value. Each with the ids of its parts, oldest first
+async fn query_duplicates(state: &AppState) -> Result<Vec<(String, Vec<i32>)>, sqlx::Error> {
+    let mut query = QueryBuilder::new(
+        "SELECT 'Same MPN', ARRAY_AGG(id ORDER BY id) FROM parts \
+         WHERE deleted_at IS NULL AND mpn IS NOT NULL \
+         GROUP BY LOWER(regexp_replace(mpn, '\\s', '', 'g')) HAVING COUNT(*) > 1 \
+         UNION ALL \
+         SELECT 'Same category, footprint and value', ARRAY_AGG(id ORDER BY id) FROM parts \
+         WHERE deleted_at IS NULL AND value IS NOT NULL \
+         GROUP BY category_id, footprint_id, value HAVING COUNT(*) > 1 \
+         ORDER BY 2",
+    );
+    query
+        .build_query_as::<(String, Vec<i32>)>()
+        .fetch_all(&state.pool)
+        .await
+}
+
+async fn html_duplicates(state: &AppState, columns: &Columns) -> Result<Markup, sqlx::Error> {
+    let groups = query_duplicates(state).await?;
+    let ids: Vec<i32> = groups.iter().flat_map(|(_, ids)| ids.clone()).collect();
+
+    let mut db_conn = state.pool.acquire().await?;
+    let items: HashMap<i32, _> = query_items(&ids, &mut db_conn)
+        .await?
+        .into_iter()
+        .map(|x| (x.id(), x))
+        .collect();
+    let units = state.category_units();
+
+    Ok(html! {
+        @if groups.is_empty() {
+            p { "No duplicates found." }
+        }
+        @for (reason, ids) in &groups {
+            article {
+                header { (reason) }
+                div class="overflow-auto" {
+                    table class="striped" {
+                        (html_static_table_header(columns))
+                        tbody {
+                            @for item in ids.iter().filter_map(|x| items.get(x)) {
+                                (html_table_row(item, &units, columns))
+                            }
+                        }
+                    }
+                }
+                // (Into the oldest, which is the first)
+                form
+                hx-post="/admin/duplicates/merge"
+                hx-target="#duplicates"
+                hx-confirm={"Merge these " (ids.len()) " parts into #" (ids[0]) ", adding up their stock?"} {
+                    @for id in ids {
+                        input type="hidden" name="ids" value=(id);
+                    }
+                    button type="submit" class="secondary" { "Merge into #" (ids[0]) }
+                }
+            }
+        }
+    })
+}
+
+pub async fn duplicates_page(
+    State(state): State<AppState>,
+    session: Session,
+    theme: Theme,
+    columns: Columns,
+) -> impl IntoResponse {
+    info!("Performing duplicate parts report");
+
+    let csrf_token = match csrf_token(&session).await {
+        Ok(token) => token,
+        Err(e) => return handle_generic_inventory_error(e),
+    };
+
+    let duplicates = match html_duplicates(&state, &columns).await {
+        Ok(duplicates) => duplicates,
+        Err(e) => return handle_generic_inventory_error(e),
+    };
+
+    let content = html! {
+        div id="duplicates" {
+            (duplicates)
+        }
+    };
+
+    Html(html_page("Duplicate Parts", &csrf_token, theme, content).into_string()).into_response()
+}
+
+pub async fn merge_duplicates_handler(
+    State(state): State<AppState>,
+    Extension(CurrentUser(user_id)): Extension<CurrentUser>,
+    columns: Columns,
+    MultiForm(form): MultiForm<MergeDuplicatesForm>,
+) -> impl IntoResponse {
+    let Some(&keep) = form.ids.iter().min() else {
+        let response = html! {
+            p style="color: red;" { "Select the parts to merge." }
+        };
+        return (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Html(response.into_string()),
+        )
+            .into_response();
+    };
+    info!("Merging components {:?} into {}", form.ids, keep);
+
+    let mut db_conn = match state.pool.acquire().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            return handle_generic_inventory_error(e);
+        }
+    };
+
+    // (All or none of them, if one fails)
+    let merged = async {
+        let mut tx = db_conn.begin().await?;
+        let mut merged = 0;
+        for &id in form.ids.iter().filter(|x| **x != keep) {
+            if merge_parts(keep, id, Some(user_id), &mut tx)
+                .await?
+                .is_some()
+            {
+                merged += 1;
+            }
+        }
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(merged)
+    }
+    .await;
+    drop(db_conn);
+    let merged = match merged {
+        Ok(merged) => merged,
+        Err(e) => return handle_generic_inventory_error(e),
+    };
+    state.list_cache.invalidate();
+    state.notify_change(None);
+
+    let duplicates = match html_duplicates(&state, &columns).await {
+        Ok(duplicates) => duplicates,
+        Err(e) => return handle_generic_inventory_error(e),
+    };
+
+    let response = html! {
+        p { "Merged " (merged) " parts into #" (keep) "." }
+        (duplicates)
+    };
+    Html(response.into_string()).into_response()
+}
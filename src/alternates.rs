@@ -0,0 +1,186 @@
+use axum::{
+    Form,
+    extract::{Path, State},
+    response::{Html, IntoResponse},
+};
+use maud::{Markup, html};
+use serde::Deserialize;
+use sqlx::{PgConnection, QueryBuilder};
+use tracing::info;
+
+use crate::{inventory::handle_generic_inventory_error, state::AppState};
+
+#[derive(Debug, Deserialize)]
+pub struct AlternateForm {
+    // MPN or part id, like the scan endpoint
+    part: String,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct Alternate {
+    pub id: i32,
+    pub mpn: Option<String>,
+    pub footprint: Option<String>,
+    // Neither staged nor reserved
+    pub available: i32,
+}
+
+// Only the parts linked directly to the given one (links aren't followed further)
+pub async fn query_alternates(
+    id: i32,
+    db_conn: &mut PgConnection,
+) -> Result<Vec<Alternate>, sqlx::Error> {
+    let mut query = QueryBuilder::new(
+        "SELECT i.id, i.mpn, i.footprint, \
+         COALESCE(i.quantity, 0) - COALESCE(i.staged, 0) - COALESCE(i.reserved, 0) AS available",
+    );
+    query.push(" FROM alternates a JOIN inventory i ON i.id = CASE WHEN a.part_a = ");
+    query.push_bind(id);
+    query.push(" THEN a.part_b ELSE a.part_a END");
+    query.push(" WHERE (a.part_a = ");
+    query.push_bind(id);
+    query.push(" OR a.part_b = ");
+    query.push_bind(id);
+    query.push(") AND i.deleted_at IS NULL ORDER BY available DESC, i.id");
+    query
+        .build_query_as::<Alternate>()
+        .fetch_all(&mut *db_conn)
+        .await
+}
+
+// Shown below the row of the part in the inventory table
+fn html_alternates(id: i32, alternates: &[Alternate], error: Option<&str>) -> Markup {
+    html! {
+        tr {
+            td colspan="11" {
+                @if alternates.is_empty() {
+                    "No alternates."
+                } @else {
+                    "Alternates: "
+                    @for alternate in alternates {
+                        span style="margin-right: 1rem;" {
+                            a href={"/part/" (alternate.id)} {
+                                (alternate.mpn.as_deref().unwrap_or("—"))
+                            }
+                            " (" (alternate.available) " available) "
+                            a
+                            style="cursor: pointer;"
+                            title="Unlink"
+                            hx-delete={"/api/inventory/alternates/" (id) "/" (alternate.id)}
+                            hx-target="closest tr"
+                            hx-swap="outerHTML" {
+                                "×"
+                            }
+                        }
+                    }
+                }
+                form
+                hx-post={"/api/inventory/alternates/" (id)}
+                hx-target="closest tr"
+                hx-swap="outerHTML"
+                style="margin: 0.5rem 0 0 0;" {
+                    div role="group" {
+                        input type="text" name="part" placeholder="MPN or part id" aria-label="Alternate part" required;
+                        button type="submit" { "Link" }
+                        button type="button" class="secondary" onclick="this.closest('tr').remove()" { "Close" }
+                    }
+                }
+                @if let Some(error) = error {
+                    p style="color: red;" { (error) }
+                }
+            }
+        }
+    }
+}
+
+async fn alternates_response(
+    state: &AppState,
+    id: i32,
+    error: Option<&str>,
+) -> axum::response::Response {
+    let mut db_conn = match state.pool.acquire().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            return handle_generic_inventory_error(e);
+        }
+    };
+
+    match query_alternates(id, &mut db_conn).await {
+        Ok(alternates) => {
+            Html(html_alternates(id, &alternates, error).into_string()).into_response()
+        }
+        Err(e) => handle_generic_inventory_error(e),
+    }
+}
+
+pub async fn alternates_handler(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+) -> impl IntoResponse {
+    info!("Performing alternates query for component {}", id);
+    alternates_response(&state, id, None).await
+}
+
+pub async fn link_handler(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+    Form(form): Form<AlternateForm>,
+) -> impl IntoResponse {
+    let part = form.part.trim();
+    info!("Linking component {} with alternate {}", id, part);
+
+    let mut query =
+        QueryBuilder::new("SELECT id FROM inventory WHERE deleted_at IS NULL AND (mpn = ");
+    query.push_bind(part);
+    query.push(" OR id::TEXT = ");
+    query.push_bind(part);
+    query.push(") LIMIT 1");
+    let other = match query
+        .build_query_scalar::<i32>()
+        .fetch_optional(&state.pool)
+        .await
+    {
+        Ok(Some(other)) if other == id => {
+            return alternates_response(&state, id, Some("A part can't be its own alternate."))
+                .await;
+        }
+        Ok(Some(other)) => other,
+        Ok(None) => {
+            let error = format!("No part matches \"{}\".", part);
+            return alternates_response(&state, id, Some(&error)).await;
+        }
+        Err(e) => return handle_generic_inventory_error(e),
+    };
+
+    let mut query = QueryBuilder::new("INSERT INTO alternates (part_a, part_b) VALUES (");
+    let mut values = query.separated(", ");
+    values.push_bind(id.min(other));
+    values.push_bind(id.max(other));
+    query.push(") ON CONFLICT DO NOTHING");
+    if let Err(e) = query.build().execute(&state.pool).await {
+        return handle_generic_inventory_error(e);
+    }
+
+    state.notify_change(Some(id));
+    state.notify_change(Some(other));
+    alternates_response(&state, id, None).await
+}
+
+pub async fn unlink_handler(
+    State(state): State<AppState>,
+    Path((id, other)): Path<(i32, i32)>,
+) -> impl IntoResponse {
+    info!("Unlinking component {} from alternate {}", id, other);
+
+    let mut query = QueryBuilder::new("DELETE FROM alternates WHERE part_a = ");
+    query.push_bind(id.min(other));
+    query.push(" AND part_b = ");
+    query.push_bind(id.max(other));
+    if let Err(e) = query.build().execute(&state.pool).await {
+        return handle_generic_inventory_error(e);
+    }
+
+    state.notify_change(Some(id));
+    state.notify_change(Some(other));
+    alternates_response(&state, id, None).await
+}
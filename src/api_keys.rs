@@ -0,0 +1,232 @@
+use axum::{
+    Form,
+    extract::{Path, State},
+    http::StatusCode,
+    response::{Html, IntoResponse, Redirect},
+};
+use maud::{Markup, html};
+use rand::Rng;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use sqlx::QueryBuilder;
+use tower_sessions::Session;
+use tracing::{info, warn};
+
+use crate::{
+    auth::{csrf_token, current_user},
+    inventory::handle_generic_inventory_error,
+    layout::{Theme, format_time, html_page},
+    state::AppState,
+};
+
+// (So that keys are easy to recognize, e.g. when leaked in a script)
+const API_KEY_PREFIX: &str = "station_";
+
+#[derive(Debug, Deserialize)]
+pub struct NewApiKeyForm {
+    name: String,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+struct ApiKeyRow {
+    id: i32,
+    name: String,
+    created_at: chrono::DateTime<chrono::Utc>,
+    last_used_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+// Keys are long and random, so a fast hash is enough (unlike passwords)
+fn hash_key(key: &str) -> String {
+    Sha256::digest(key.as_bytes())
+        .iter()
+        .map(|x| format!("{:02x}", x))
+        .collect()
+}
+
+fn generate_key() -> String {
+    let bytes: [u8; 32] = rand::thread_rng().r#gen();
+    let key: String = bytes.iter().map(|x| format!("{:02x}", x)).collect();
+    format!("{}{}", API_KEY_PREFIX, key)
+}
+
+// Returns the user owning the key, if it exists
+pub async fn find_key_user(state: &AppState, key: &str) -> Result<Option<i32>, sqlx::Error> {
+    let mut query =
+        QueryBuilder::new("UPDATE api_keys SET last_used_at = CURRENT_TIMESTAMP WHERE key_hash = ");
+    query.push_bind(hash_key(key));
+    query.push(" RETURNING user_id");
+    query
+        .build_query_scalar::<i32>()
+        .fetch_optional(&state.pool)
+        .await
+}
+
+async fn query_keys(state: &AppState, user_id: i32) -> Result<Vec<ApiKeyRow>, sqlx::Error> {
+    let mut query = QueryBuilder::new(
+        "SELECT id, name, created_at, last_used_at FROM api_keys WHERE user_id = ",
+    );
+    query.push_bind(user_id);
+    query.push(" ORDER BY created_at DESC, id DESC");
+    query
+        .build_query_as::<ApiKeyRow>()
+        .fetch_all(&state.pool)
+        .await
+}
+
+fn internal_error(e: sqlx::Error) -> axum::response::Response {
+    warn!("Error while processing API keys: {}", e);
+    (StatusCode::INTERNAL_SERVER_ERROR, "").into_response()
+}
+
+// The new key is only ever shown here, right after creating it
+fn html_key_list(keys: &[ApiKeyRow], new_key: Option<&str>) -> Markup {
+    html! {
+        @if let Some(new_key) = new_key {
+            article {
+                p { "Copy the key now, it won't be shown again:" }
+                pre { code { (new_key) } }
+            }
+        }
+        @if keys.is_empty() {
+            p { "No API keys." }
+        } @else {
+            div class="overflow-auto" {
+                table class="striped" {
+                    thead {
+                        tr {
+                            th scope="col" { "Name" }
+                            th scope="col" { "Created" }
+                            th scope="col" { "Last used" }
+                            th scope="col" { "Action" }
+                        }
+                    }
+                    tbody {
+                        @for key in keys {
+                            tr {
+                                th scope="row" { (key.name) }
+                                td { (format_time(&key.created_at)) }
+                                td {
+                                    @if let Some(last_used_at) = &key.last_used_at {
+                                        (format_time(last_used_at))
+                                    } @else {
+                                        "Never"
+                                    }
+                                }
+                                td {
+                                    button
+                                    class="secondary"
+                                    hx-delete={"/account/api-keys/" (key.id)}
+                                    hx-target="#api-keys"
+                                    hx-confirm={"Revoke the key \"" (key.name) "\"?"} {
+                                        "Revoke"
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub async fn api_keys_page(
+    State(state): State<AppState>,
+    session: Session,
+    theme: Theme,
+) -> impl IntoResponse {
+    let Some(user_id) = current_user(&session).await else {
+        return Redirect::to("/login").into_response();
+    };
+    let csrf_token = match csrf_token(&session).await {
+        Ok(token) => token,
+        Err(e) => return handle_generic_inventory_error(e),
+    };
+
+    let keys = match query_keys(&state, user_id).await {
+        Ok(keys) => keys,
+        Err(e) => return internal_error(e),
+    };
+
+    let content = html! {
+        p {
+            "Scripts can use the JSON endpoints by sending "
+            code { "Authorization: Bearer <key>" }
+            " instead of logging in."
+        }
+        form hx-post="/account/api-keys" hx-target="#api-keys" hx-on::after-request="if (event.detail.successful) this.reset()" {
+            div role="group" {
+                input type="text" name="name" placeholder="Name (e.g. label printer)" aria-label="Name" required;
+                button type="submit" { "Create Key" }
+            }
+        }
+        div id="api-keys" {
+            (html_key_list(&keys, None))
+        }
+    };
+
+    Html(html_page("API Keys", &csrf_token, theme, content).into_string()).into_response()
+}
+
+pub async fn create_handler(
+    State(state): State<AppState>,
+    session: Session,
+    Form(form): Form<NewApiKeyForm>,
+) -> impl IntoResponse {
+    let Some(user_id) = current_user(&session).await else {
+        return Redirect::to("/login").into_response();
+    };
+
+    let name = form.name.trim();
+    if name.is_empty() {
+        return (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Html(String::from("A name is required.")),
+        )
+            .into_response();
+    }
+
+    info!("Creating API key \"{}\" for user {}", name, user_id);
+
+    let key = generate_key();
+    let mut query = QueryBuilder::new("INSERT INTO api_keys (user_id, name, key_hash) VALUES (");
+    let mut values = query.separated(", ");
+    values.push_bind(user_id);
+    values.push_bind(name);
+    values.push_bind(hash_key(&key));
+    query.push(")");
+    if let Err(e) = query.build().execute(&state.pool).await {
+        return internal_error(e);
+    }
+
+    match query_keys(&state, user_id).await {
+        Ok(keys) => Html(html_key_list(&keys, Some(&key)).into_string()).into_response(),
+        Err(e) => internal_error(e),
+    }
+}
+
+pub async fn revoke_handler(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+    session: Session,
+) -> impl IntoResponse {
+    let Some(user_id) = current_user(&session).await else {
+        return Redirect::to("/login").into_response();
+    };
+
+    info!("Revoking API key {} of user {}", id, user_id);
+
+    // (Users may only revoke their own keys)
+    let mut query = QueryBuilder::new("DELETE FROM api_keys WHERE id = ");
+    query.push_bind(id);
+    query.push(" AND user_id = ");
+    query.push_bind(user_id);
+    if let Err(e) = query.build().execute(&state.pool).await {
+        return internal_error(e);
+    }
+
+    match query_keys(&state, user_id).await {
+        Ok(keys) => Html(html_key_list(&keys, None).into_string()).into_response(),
+        Err(e) => internal_error(e),
+    }
+}
@@ -0,0 +1,67 @@
+use axum::{
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use sha2::{Digest, Sha256};
+
+// A file embedded in the binary, which browsers may cache and then revalidate
+// through its ETag
+pub struct StaticAsset {
+    bytes: &'static [u8],
+    content_type: &'static str,
+    cache_control: String,
+    etag: String,
+}
+
+impl StaticAsset {
+    pub fn new(bytes: &'static [u8], content_type: &'static str, max_age_secs: u64) -> Self {
+        // (The contents only change with a new build, so hashing them once is enough)
+        let hash: String = Sha256::digest(bytes)
+            .iter()
+            .take(8)
+            .map(|x| format!("{:02x}", x))
+            .collect();
+
+        StaticAsset {
+            bytes,
+            content_type,
+            cache_control: format!("public, max-age={}", max_age_secs),
+            etag: format!("\"{}\"", hash),
+        }
+    }
+
+    fn matches(&self, headers: &HeaderMap) -> bool {
+        let Some(if_none_match) = headers
+            .get(header::IF_NONE_MATCH)
+            .and_then(|x| x.to_str().ok())
+        else {
+            return false;
+        };
+
+        if_none_match.split(',').any(|tag| {
+            let tag = tag.trim();
+            tag == "*" || tag.trim_start_matches("W/") == self.etag
+        })
+    }
+
+    pub fn response(&self, headers: &HeaderMap) -> Response {
+        let cache_headers = [
+            (header::ETAG, HeaderValue::from_str(&self.etag).unwrap()),
+            (
+                header::CACHE_CONTROL,
+                HeaderValue::from_str(&self.cache_control).unwrap(),
+            ),
+        ];
+
+        if self.matches(headers) {
+            return (StatusCode::NOT_MODIFIED, cache_headers).into_response();
+        }
+
+        (
+            cache_headers,
+            [(header::CONTENT_TYPE, self.content_type)],
+            self.bytes,
+        )
+            .into_response()
+    }
+}
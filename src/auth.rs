@@ -1,67 +1,466 @@
-use crate::state::AppState;
-use argon2::{Argon2, PasswordHash, PasswordVerifier};
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::Display,
+    net::{IpAddr, SocketAddr},
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    api_keys,
+    config::Config,
+    inventory::handle_generic_inventory_error,
+    layout::{Theme, html_page},
+    state::AppState,
+    totp,
+};
+use argon2::{
+    Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version,
+    password_hash::{SaltString, rand_core::OsRng},
+};
 use axum::{
     Form,
-    extract::{Request, State},
+    extract::{ConnectInfo, Request, State},
+    http::{HeaderMap, Method, StatusCode, header},
     middleware::Next,
-    response::{IntoResponse, Redirect},
+    response::{Html, IntoResponse, Redirect},
 };
 use maud::html;
+use rand::Rng;
 use serde::Deserialize;
+use sqlx::{Pool, Postgres, QueryBuilder};
+use subtle::ConstantTimeEq;
 use tower_sessions::Session;
+use tracing::{error, info, warn};
 
-const AUTH_SESSION_NAME: &'static str = "auth";
+// Holds the id of the logged in user
+const USER_SESSION_NAME: &str = "user_id";
+const CSRF_SESSION_NAME: &str = "csrf_token";
+pub const CSRF_HEADER: &str = "X-CSRF-Token";
 
 #[derive(Deserialize)]
 pub struct LoginCredentials {
+    pub username: String,
     pub password: String,
 }
 
-pub async fn auth_guard(session: Session, request: Request, next: Next) -> impl IntoResponse {
-    let auth = session
-        .get::<bool>(AUTH_SESSION_NAME)
+#[derive(Deserialize)]
+pub struct PasswordChangeForm {
+    pub old_password: String,
+    pub new_password: String,
+    pub confirm_password: String,
+}
+
+const MIN_PASSWORD_LEN: usize = 8;
+
+// Checked against when logging in as an unknown user. Hashed on startup with
+// the parameters of the stored hashes, see check_user_hashes
+static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+
+fn dummy_hash() -> &'static str {
+    DUMMY_HASH.get_or_init(|| dummy_hash_like(None))
+}
+
+// Hashes a throwaway password with the same parameters as the given hash (or
+// like new passwords)
+fn dummy_hash_like(hash: Option<&PasswordHash>) -> String {
+    let argon2 = hash
+        .and_then(|x| {
+            Some(Argon2::new(
+                Algorithm::try_from(x.algorithm).ok()?,
+                Version::try_from(x.version?).ok()?,
+                Params::try_from(x).ok()?,
+            ))
+        })
+        .unwrap_or_default();
+    let salt = SaltString::generate(&mut OsRng);
+    argon2
+        .hash_password(b"dummy password", &salt)
+        .unwrap()
+        .to_string()
+}
+
+// Counts failed logins per client within a sliding window, locking the client
+// out once there are too many
+pub struct LoginLimiter {
+    max_attempts: usize,
+    window: Duration,
+    // (Behind a reverse proxy all clients share its address, so the forwarded one is used)
+    trust_proxy: bool,
+    failures: Mutex<HashMap<IpAddr, VecDeque<Instant>>>,
+}
+
+impl LoginLimiter {
+    pub fn new(config: &Config) -> Self {
+        LoginLimiter {
+            max_attempts: config.login_max_attempts,
+            window: Duration::from_secs(config.login_window_secs),
+            trust_proxy: config.trust_proxy,
+            failures: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn client_ip(&self, addr: SocketAddr, headers: &HeaderMap) -> IpAddr {
+        let forwarded = headers
+            .get("X-Forwarded-For")
+            .and_then(|x| x.to_str().ok())
+            .and_then(|x| x.split(',').next())
+            .and_then(|x| x.trim().parse().ok());
+
+        match forwarded {
+            Some(ip) if self.trust_proxy => ip,
+            _ => addr.ip(),
+        }
+    }
+
+    // Returns how long the client has to wait, if locked out
+    pub fn locked_for(&self, ip: IpAddr) -> Option<Duration> {
+        let mut failures = self.failures.lock().unwrap();
+        let attempts = failures.get_mut(&ip)?;
+
+        let now = Instant::now();
+        while attempts
+            .front()
+            .is_some_and(|x| now.duration_since(*x) > self.window)
+        {
+            attempts.pop_front();
+        }
+
+        if attempts.is_empty() {
+            failures.remove(&ip);
+            None
+        } else if attempts.len() >= self.max_attempts {
+            // (Unlocks as soon as the oldest failure leaves the window)
+            Some(self.window.saturating_sub(now.duration_since(attempts[0])))
+        } else {
+            None
+        }
+    }
+
+    pub fn record_failure(&self, ip: IpAddr) {
+        let now = Instant::now();
+        let mut failures = self.failures.lock().unwrap();
+        // (Clients which stopped trying would otherwise be kept forever)
+        failures.retain(|_, attempts| {
+            attempts
+                .back()
+                .is_some_and(|x| now.duration_since(*x) <= self.window)
+        });
+        failures.entry(ip).or_default().push_back(now);
+    }
+
+    fn reset(&self, ip: IpAddr) {
+        self.failures.lock().unwrap().remove(&ip);
+    }
+}
+
+// Deployments from before there were users keep working, with an admin
+// account using the old shared password
+pub async fn bootstrap_users(
+    pool: &Pool<Postgres>,
+    login_password: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    let num_users = QueryBuilder::new("SELECT COUNT(*) FROM users")
+        .build_query_scalar::<i64>()
+        .fetch_one(pool)
+        .await?;
+    if num_users > 0 {
+        return Ok(());
+    }
+
+    info!("No users exist, creating admin user from LOGIN_PASSWORD");
+    let password_hash = login_password.expect("LOGIN_PASSWORD is needed to create the first user");
+    // (A plain password instead of its hash is an easy mistake to make)
+    if let Err(e) = PasswordHash::new(password_hash) {
+        panic!("LOGIN_PASSWORD is not a valid Argon2 PHC string: {}", e);
+    }
+
+    let mut query = QueryBuilder::new("INSERT INTO users (username, argon2_hash, role) VALUES (");
+    let mut values = query.separated(", ");
+    values.push_bind("admin");
+    values.push_bind(password_hash);
+    values.push_bind("admin");
+    query.push(")");
+    query.build().execute(pool).await?;
+
+    Ok(())
+}
+
+// Stored hashes are checked on startup, but may still be broken by hand afterwards
+fn misconfigured_hash_error(
+    user_id: i32,
+    e: argon2::password_hash::Error,
+) -> axum::response::Response {
+    error!("Password hash of user {} is malformed: {}", user_id, e);
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Authentication misconfigured",
+    )
+        .into_response()
+}
+
+#[derive(Debug)]
+pub enum UserHashError {
+    Query(sqlx::Error),
+    // (Usernames of the users whose hash is broken)
+    Malformed(Vec<String>),
+}
+
+impl Display for UserHashError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UserHashError::Query(e) => write!(f, "Failed to check user password hashes: {}", e),
+            UserHashError::Malformed(usernames) => write!(
+                f,
+                "Password hash of user(s) {} is not a valid Argon2 PHC string, fix it in the \
+                 users table",
+                usernames.join(", ")
+            ),
+        }
+    }
+}
+
+// Refuses to start with users which wouldn't be able to log in, as their hash
+// isn't a valid PHC string. The dummy hash is made like the first valid one
+pub async fn check_user_hashes(pool: &Pool<Postgres>) -> Result<(), UserHashError> {
+    let users = QueryBuilder::new("SELECT username, argon2_hash FROM users ORDER BY id")
+        .build_query_as::<(String, String)>()
+        .fetch_all(pool)
+        .await
+        .map_err(UserHashError::Query)?;
+
+    let mut first_valid = None;
+    let mut malformed = Vec::new();
+    for (username, hash) in &users {
+        match PasswordHash::new(hash.as_str()) {
+            Ok(hash) => {
+                first_valid.get_or_insert(hash);
+            }
+            Err(e) => {
+                error!("Password hash of user {} is malformed: {}", username, e);
+                malformed.push(username.clone());
+            }
+        }
+    }
+    if !malformed.is_empty() {
+        return Err(UserHashError::Malformed(malformed));
+    }
+    DUMMY_HASH.get_or_init(|| dummy_hash_like(first_valid.as_ref()));
+
+    Ok(())
+}
+
+pub async fn current_user(session: &Session) -> Option<i32> {
+    session
+        .get::<i32>(USER_SESSION_NAME)
         .await
         .unwrap_or_default()
-        .unwrap_or(false);
+}
 
-    if auth {
+// Scripts may authenticate with an API key instead of a session
+pub async fn auth_guard(
+    State(state): State<AppState>,
+    session: Session,
+    mut request: Request,
+    next: Next,
+) -> impl IntoResponse {
+    if let Some(key) = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|x| x.to_str().ok())
+        .and_then(|x| x.strip_prefix("Bearer "))
+    {
+        return match api_keys::find_key_user(&state, key.trim()).await {
+            Ok(Some(user_id)) => {
+                info!("Authenticated request with API key of user {}", user_id);
+                request.extensions_mut().insert(ApiKeyAuth);
+                request.extensions_mut().insert(CurrentUser(user_id));
+                next.run(request).await
+            }
+            Ok(None) => (StatusCode::UNAUTHORIZED, "Invalid API key").into_response(),
+            Err(e) => {
+                warn!("Error while checking API key: {}", e);
+                (StatusCode::INTERNAL_SERVER_ERROR, "").into_response()
+            }
+        };
+    }
+
+    if let Some(user_id) = current_user(&session).await {
+        request.extensions_mut().insert(CurrentUser(user_id));
         next.run(request).await
     } else {
         Redirect::to("/login").into_response()
     }
 }
 
-pub async fn is_auth(session: Session) -> bool {
-    return session
-        .get::<bool>(AUTH_SESSION_NAME)
+// Returns the CSRF token of the session, creating it on first use
+pub async fn csrf_token(session: &Session) -> Result<String, tower_sessions::session::Error> {
+    if let Some(token) = session.get::<String>(CSRF_SESSION_NAME).await? {
+        return Ok(token);
+    }
+
+    let bytes: [u8; 32] = rand::thread_rng().r#gen();
+    let token: String = bytes.iter().map(|x| format!("{:02x}", x)).collect();
+    session.insert(CSRF_SESSION_NAME, &token).await?;
+    Ok(token)
+}
+
+// Set by auth_guard on requests authenticated with an API key
+#[derive(Clone, Copy)]
+pub struct ApiKeyAuth;
+
+// Set by auth_guard to the id of the user making the request, however they
+// authenticated
+#[derive(Clone, Copy)]
+pub struct CurrentUser(pub i32);
+
+// Mutating requests must carry the session's token in the X-CSRF-Token header,
+// which pages send on every HTMX request through hx-headers
+pub async fn csrf_guard(session: Session, request: Request, next: Next) -> impl IntoResponse {
+    // (API keys aren't sent by browsers on their own, so they can't be forged cross-site)
+    if matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS
+    ) || request.extensions().get::<ApiKeyAuth>().is_some()
+    {
+        return next.run(request).await;
+    }
+
+    let expected = session
+        .get::<String>(CSRF_SESSION_NAME)
         .await
-        .unwrap_or_default()
-        .unwrap_or(false);
+        .ok()
+        .flatten();
+    let received = request
+        .headers()
+        .get(CSRF_HEADER)
+        .and_then(|x| x.to_str().ok());
+
+    // (In constant time, so that the token can't be guessed from how long it takes)
+    match (expected, received) {
+        (Some(expected), Some(received))
+            if bool::from(expected.as_bytes().ct_eq(received.as_bytes())) =>
+        {
+            next.run(request).await
+        }
+        _ => {
+            warn!(
+                "Rejected {} {} with missing or invalid CSRF token",
+                request.method(),
+                request.uri()
+            );
+            (StatusCode::FORBIDDEN, "Invalid CSRF token").into_response()
+        }
+    }
+}
+
+pub fn locked_out_response(ip: IpAddr, wait: Duration) -> axum::response::Response {
+    warn!("Rejected login from {}, too many failed attempts", ip);
+    let retry_after = wait.as_secs().max(1);
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [(header::RETRY_AFTER, retry_after.to_string())],
+        html!({
+            div.alert.alert-danger role="alert" style="color: red; margin-top: 10px;" {
+                strong { "Too many failed attempts, try again in " (retry_after) " seconds." }
+            }
+        })
+        .into_string(),
+    )
+        .into_response()
+}
+
+pub fn wrong_credentials_response() -> axum::response::Response {
+    html!({
+        div.alert.alert-danger role="alert" style="color: red; margin-top: 10px;" {
+            strong { "You shall not pass!" }
+        }
+    })
+    .into_string()
+    .into_response()
+}
+
+// Once all factors have been checked
+pub async fn finish_login(
+    state: &AppState,
+    session: &Session,
+    ip: IpAddr,
+    user_id: i32,
+) -> axum::response::Response {
+    state.login_limiter.reset(ip);
+    // (New session id, so that one fixed before logging in is useless)
+    if let Err(e) = session.cycle_id().await {
+        return handle_generic_inventory_error(e);
+    }
+    if let Err(e) = session.insert(USER_SESSION_NAME, user_id).await {
+        return handle_generic_inventory_error(e);
+    }
+    let mut headers = axum::http::HeaderMap::new();
+    headers.insert("HX-Redirect", "/inventory".parse().unwrap());
+    (headers, "").into_response()
+}
+
+pub async fn is_auth(session: Session) -> bool {
+    current_user(&session).await.is_some()
 }
 
 pub async fn login_handler(
     State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
     session: Session,
     Form(creds): Form<LoginCredentials>,
 ) -> impl IntoResponse {
-    let true_pass = PasswordHash::new(state.password_hash.as_str()).unwrap();
-    let pass_valid = Argon2::default()
-        .verify_password(creds.password.as_bytes(), &true_pass)
-        .is_ok();
-
-    if pass_valid {
-        session.insert(AUTH_SESSION_NAME, true).await.unwrap();
-        let mut headers = axum::http::HeaderMap::new();
-        headers.insert("HX-Redirect", "/inventory".parse().unwrap());
-        (headers, "").into_response()
-    } else {
-        return html!({
-            div.alert.alert-danger role="alert" style="color: red; margin-top: 10px;" {
-                strong { "You shall not pass!" }
-            }
-        })
-        .into_string()
-        .into_response();
+    let ip = state.login_limiter.client_ip(addr, &headers);
+    if let Some(wait) = state.login_limiter.locked_for(ip) {
+        return locked_out_response(ip, wait);
+    }
+
+    let mut query =
+        QueryBuilder::new("SELECT id, argon2_hash, totp_secret FROM users WHERE username = ");
+    query.push_bind(creds.username.trim());
+    let user = match query
+        .build_query_as::<(i32, String, Option<String>)>()
+        .fetch_optional(&state.pool)
+        .await
+    {
+        Ok(user) => user,
+        Err(e) => {
+            warn!("Error while querying user: {}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "").into_response();
+        }
+    };
+
+    let user = match user {
+        Some((id, hash, totp_secret)) => {
+            let true_pass = match PasswordHash::new(hash.as_str()) {
+                Ok(true_pass) => true_pass,
+                Err(e) => return misconfigured_hash_error(id, e),
+            };
+            Argon2::default()
+                .verify_password(creds.password.as_bytes(), &true_pass)
+                .is_ok()
+                .then_some((id, totp_secret))
+        }
+        None => {
+            // (Checked anyway, so that unknown usernames take as long as wrong passwords)
+            let dummy = PasswordHash::new(dummy_hash()).unwrap();
+            Argon2::default()
+                .verify_password(creds.password.as_bytes(), &dummy)
+                .ok();
+            None
+        }
+    };
+
+    match user {
+        // (The code is asked for in a second step, see totp.rs)
+        Some((user_id, Some(_))) => totp::ask_for_code(&session, user_id).await,
+        Some((user_id, None)) => finish_login(&state, &session, ip, user_id).await,
+        None => {
+            state.login_limiter.record_failure(ip);
+            metrics::counter!("login_failures_total", "step" => "password").increment(1);
+            wrong_credentials_response()
+        }
     }
 }
 
@@ -71,3 +470,208 @@ pub async fn logout_handler(session: Session) -> impl IntoResponse {
     headers.insert("HX-Redirect", "/login".parse().unwrap());
     (headers, "").into_response()
 }
+
+pub async fn account_page(
+    State(state): State<AppState>,
+    session: Session,
+    theme: Theme,
+) -> impl IntoResponse {
+    let csrf_token = match csrf_token(&session).await {
+        Ok(token) => token,
+        Err(e) => return handle_generic_inventory_error(e),
+    };
+
+    let totp_section = match current_user(&session).await {
+        Some(user_id) => totp::html_account_section(&state, user_id).await,
+        None => return Redirect::to("/login").into_response(),
+    };
+
+    let content = html! {
+        form hx-post="/account/password" hx-target="#password-result" {
+            label {
+                "Current password"
+                input type="password" name="old_password" autocomplete="current-password" required;
+            }
+            label {
+                "New password"
+                input type="password" name="new_password" autocomplete="new-password" required;
+            }
+            label {
+                "Confirm new password"
+                input type="password" name="confirm_password" autocomplete="new-password" required;
+            }
+            button type="submit" { "Change Password" }
+        }
+        div id="password-result" {}
+        hr;
+        (totp_section)
+        hr;
+        p { a href="/account/api-keys" { "Manage API keys" } }
+    };
+
+    Html(html_page("Account", &csrf_token, theme, content).into_string()).into_response()
+}
+
+fn password_change_error(msg: &str) -> axum::response::Response {
+    let response = html! {
+        p style="color: red;" { (msg) }
+    };
+    (
+        StatusCode::UNPROCESSABLE_ENTITY,
+        Html(response.into_string()),
+    )
+        .into_response()
+}
+
+pub async fn password_change_handler(
+    State(state): State<AppState>,
+    session: Session,
+    Form(form): Form<PasswordChangeForm>,
+) -> impl IntoResponse {
+    // (Can't be missing, as the route sits behind auth_guard)
+    let Some(user_id) = current_user(&session).await else {
+        return Redirect::to("/login").into_response();
+    };
+
+    info!("Changing password of user {}", user_id);
+
+    if form.new_password != form.confirm_password {
+        return password_change_error("The new passwords don't match.");
+    }
+
+    if form.new_password.chars().count() < MIN_PASSWORD_LEN {
+        return password_change_error(&format!(
+            "The new password must have at least {} characters.",
+            MIN_PASSWORD_LEN
+        ));
+    }
+
+    let mut query = QueryBuilder::new("SELECT argon2_hash FROM users WHERE id = ");
+    query.push_bind(user_id);
+    let hash = match query
+        .build_query_scalar::<String>()
+        .fetch_one(&state.pool)
+        .await
+    {
+        Ok(hash) => hash,
+        Err(e) => {
+            warn!("Error while querying user: {}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "").into_response();
+        }
+    };
+
+    let true_pass = match PasswordHash::new(hash.as_str()) {
+        Ok(true_pass) => true_pass,
+        Err(e) => return misconfigured_hash_error(user_id, e),
+    };
+    if Argon2::default()
+        .verify_password(form.old_password.as_bytes(), &true_pass)
+        .is_err()
+    {
+        return password_change_error("The current password is wrong.");
+    }
+
+    let salt = SaltString::generate(&mut OsRng);
+    let new_hash = match Argon2::default().hash_password(form.new_password.as_bytes(), &salt) {
+        Ok(hash) => hash.to_string(),
+        Err(e) => {
+            warn!("Error while hashing password: {}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "").into_response();
+        }
+    };
+
+    let mut query = QueryBuilder::new("UPDATE users SET argon2_hash = ");
+    query.push_bind(new_hash);
+    query.push(" WHERE id = ");
+    query.push_bind(user_id);
+    if let Err(e) = query.build().execute(&state.pool).await {
+        warn!("Error while updating password: {}", e);
+        return (StatusCode::INTERNAL_SERVER_ERROR, "").into_response();
+    }
+
+    Html(
+        html! {
+            p { "Password changed." }
+        }
+        .into_string(),
+    )
+    .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(max_attempts: usize, window: Duration) -> LoginLimiter {
+        LoginLimiter {
+            max_attempts,
+            window,
+            trust_proxy: false,
+            failures: Mutex::new(HashMap::new()),
+        }
+    }
+
+    #[test]
+    fn locks_out_after_too_many_failures() {
+        let limiter = limiter(3, Duration::from_secs(300));
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+        let other: IpAddr = "192.0.2.2".parse().unwrap();
+
+        for _ in 0..2 {
+            limiter.record_failure(ip);
+            assert_eq!(limiter.locked_for(ip), None);
+        }
+        limiter.record_failure(ip);
+        let wait = limiter.locked_for(ip).unwrap();
+        assert!(wait > Duration::from_secs(290) && wait <= Duration::from_secs(300));
+
+        // (Per client)
+        assert_eq!(limiter.locked_for(other), None);
+
+        // (A successful login resets it)
+        limiter.reset(ip);
+        assert_eq!(limiter.locked_for(ip), None);
+    }
+
+    #[test]
+    fn unlocks_once_failures_leave_the_window() {
+        let limiter = limiter(2, Duration::from_millis(50));
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+
+        limiter.record_failure(ip);
+        limiter.record_failure(ip);
+        assert!(limiter.locked_for(ip).is_some());
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(limiter.locked_for(ip), None);
+    }
+
+    #[test]
+    fn forgets_clients_which_stopped_trying() {
+        let limiter = limiter(3, Duration::from_millis(50));
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+        let other: IpAddr = "192.0.2.2".parse().unwrap();
+
+        limiter.record_failure(ip);
+        std::thread::sleep(Duration::from_millis(60));
+        limiter.record_failure(other);
+
+        let failures = limiter.failures.lock().unwrap();
+        assert!(!failures.contains_key(&ip));
+        assert!(failures.contains_key(&other));
+    }
+
+    #[test]
+    fn dummy_hash_uses_the_same_parameters() {
+        let salt = SaltString::generate(&mut OsRng);
+        let params = Params::new(4096, 3, 1, None).unwrap();
+        let argon2 = Argon2::new(Algorithm::Argon2i, Version::V0x13, params);
+        let hash = argon2.hash_password(b"password", &salt).unwrap();
+
+        let dummy = dummy_hash_like(Some(&hash));
+        let dummy = PasswordHash::new(&dummy).unwrap();
+        assert_eq!(dummy.algorithm, hash.algorithm);
+        assert_eq!(Params::try_from(&dummy), Params::try_from(&hash));
+        assert!(PasswordHash::new(dummy_hash()).is_ok());
+    }
+}
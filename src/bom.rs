@@ -0,0 +1,533 @@
+use axum::{
+    Extension,
+    extract::{Multipart, State},
+    http::{HeaderMap, StatusCode},
+    response::{Html, IntoResponse},
+};
+use axum_extra::extract::Form as MultiForm;
+use maud::{Markup, html};
+use serde::Deserialize;
+use sqlx::{Acquire, PgConnection, Postgres, QueryBuilder};
+use tracing::info;
+
+use crate::{
+    alternates::query_alternates,
+    auth::CurrentUser,
+    inventory::{handle_generic_inventory_error, parse_multiple_value, update_stage},
+    state::AppState,
+};
+
+// A line of the BOM, which may stand for many components sharing a part
+#[derive(Debug)]
+struct BomLine {
+    refs: String,
+    value: String,
+    footprint: String,
+    mpn: String,
+    qty: i32,
+    matched: Option<BomMatch>,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+struct BomMatch {
+    id: i32,
+    mpn: Option<String>,
+    footprint: Option<String>,
+    available: i32,
+    // MPN of the part the line matched, if an alternate was picked instead
+    #[sqlx(skip)]
+    alternate_of: Option<Option<String>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BomStageForm {
+    boards: String,
+    // "<part id>:<qty per board>:<MPN>", one per matched part. (The MPN is only
+    // there to name the part if it's gone by the time it's staged)
+    #[serde(default)]
+    part: Vec<String>,
+}
+
+// KiCad (and its BOM plugins) name the columns differently between versions
+fn find_column(headers: &csv::StringRecord, names: &[&str]) -> Option<usize> {
+    headers
+        .iter()
+        .position(|x| names.iter().any(|name| x.trim().eq_ignore_ascii_case(name)))
+}
+
+fn count_refs(refs: &str) -> i32 {
+    refs.split([',', ' ']).filter(|x| !x.is_empty()).count() as i32
+}
+
+fn parse_bom_csv(data: &[u8]) -> Result<Vec<BomLine>, String> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_reader(data);
+
+    let headers = reader.headers().map_err(|e| e.to_string())?.clone();
+    let refs = find_column(
+        &headers,
+        &["Reference", "References", "Refs", "Ref", "Designator"],
+    );
+    let value = find_column(&headers, &["Value"]);
+    let footprint = find_column(&headers, &["Footprint"]);
+    let qty = find_column(&headers, &["Qty", "Quantity", "Qnty"]);
+    let mpn = find_column(
+        &headers,
+        &[
+            "MPN",
+            "Manufacturer_Part_Number",
+            "Manufacturer Part Number",
+            "PartNumber",
+        ],
+    );
+
+    if value.is_none() && mpn.is_none() {
+        return Err(String::from(
+            "The BOM needs a value or MPN column to match parts.",
+        ));
+    }
+
+    let mut lines = vec![];
+    for record in reader.records() {
+        let record = record.map_err(|e| e.to_string())?;
+        let get = |column: Option<usize>| {
+            column
+                .and_then(|x| record.get(x))
+                .unwrap_or_default()
+                .to_string()
+        };
+
+        let refs = get(refs);
+        // (Without a quantity column, each reference is one component)
+        let qty = get(qty)
+            .parse::<i32>()
+            .unwrap_or_else(|_| count_refs(&refs).max(1));
+
+        lines.push(BomLine {
+            refs,
+            value: get(value),
+            footprint: get(footprint),
+            mpn: get(mpn),
+            qty,
+            matched: None,
+        });
+    }
+
+    Ok(lines)
+}
+
+// The XML lists every component on its own, so they are grouped into lines here
+fn parse_bom_xml(data: &str) -> Result<Vec<BomLine>, String> {
+    let doc = roxmltree::Document::parse(data).map_err(|e| e.to_string())?;
+
+    let mut lines: Vec<BomLine> = vec![];
+    for comp in doc.descendants().filter(|x| x.has_tag_name("comp")) {
+        let child_text = |name: &str| {
+            comp.children()
+                .find(|x| x.has_tag_name(name))
+                .and_then(|x| x.text())
+                .unwrap_or_default()
+                .trim()
+                .to_string()
+        };
+        let mpn = comp
+            .descendants()
+            .find(|x| {
+                x.has_tag_name("field")
+                    && x.attribute("name").is_some_and(|name| {
+                        ["MPN", "Manufacturer_Part_Number", "PartNumber"]
+                            .iter()
+                            .any(|x| name.eq_ignore_ascii_case(x))
+                    })
+            })
+            .and_then(|x| x.text())
+            .unwrap_or_default()
+            .trim()
+            .to_string();
+        let refdes = comp.attribute("ref").unwrap_or_default();
+        let value = child_text("value");
+        let footprint = child_text("footprint");
+
+        match lines
+            .iter_mut()
+            .find(|x| x.mpn == mpn && x.value == value && x.footprint == footprint)
+        {
+            Some(line) => {
+                line.refs = format!("{}, {}", line.refs, refdes);
+                line.qty += 1;
+            }
+            None => lines.push(BomLine {
+                refs: refdes.to_string(),
+                value,
+                footprint,
+                mpn,
+                qty: 1,
+                matched: None,
+            }),
+        }
+    }
+
+    Ok(lines)
+}
+
+// KiCad footprints carry their library and more details ("Resistor_SMD:R_0402_1005Metric"),
+// so one of their parts has to match the footprint of the part in the inventory
+fn footprint_matches(part: &str, kicad: &str) -> bool {
+    let kicad = kicad.rsplit(':').next().unwrap_or(kicad);
+    kicad.eq_ignore_ascii_case(part) || kicad.split('_').any(|x| x.eq_ignore_ascii_case(part))
+}
+
+fn bom_match_query<'a>() -> QueryBuilder<'a, Postgres> {
+    let mut query = QueryBuilder::new(
+        "SELECT id, mpn, footprint, \
+         COALESCE(quantity, 0) - COALESCE(staged, 0) - COALESCE(reserved, 0) AS available",
+    );
+    query.push(" FROM inventory WHERE deleted_at IS NULL AND ");
+    query
+}
+
+// By MPN first, and otherwise (also if the MPN isn't in the inventory) by value
+// and footprint
+async fn match_line(
+    line: &BomLine,
+    db_conn: &mut PgConnection,
+) -> Result<Option<BomMatch>, sqlx::Error> {
+    if !line.mpn.is_empty() {
+        let mut query = bom_match_query();
+        query.push("mpn = ");
+        query.push_bind(&line.mpn);
+        let matched = query
+            .build_query_as::<BomMatch>()
+            .fetch_optional(&mut *db_conn)
+            .await?;
+        if matched.is_some() {
+            return Ok(matched);
+        }
+    }
+
+    let Some(value) = parse_multiple_value(&line.value) else {
+        return Ok(None);
+    };
+    let mut query = bom_match_query();
+    query.push("value = ");
+    query.push_bind(value);
+    query.push(" ORDER BY available DESC");
+    let candidates = query
+        .build_query_as::<BomMatch>()
+        .fetch_all(&mut *db_conn)
+        .await?;
+
+    Ok(candidates.into_iter().find(|x| {
+        line.footprint.is_empty()
+            || x.footprint
+                .as_deref()
+                .is_some_and(|footprint| footprint_matches(footprint, &line.footprint))
+    }))
+}
+
+// Without enough stock for the line, an alternate which has it is used instead
+async fn resolve_alternate(
+    matched: BomMatch,
+    qty: i32,
+    db_conn: &mut PgConnection,
+) -> Result<BomMatch, sqlx::Error> {
+    if matched.available >= qty {
+        return Ok(matched);
+    }
+
+    let alternate = query_alternates(matched.id, db_conn)
+        .await?
+        .into_iter()
+        .find(|x| x.available >= qty);
+    Ok(match alternate {
+        Some(alternate) => BomMatch {
+            id: alternate.id,
+            mpn: alternate.mpn,
+            footprint: alternate.footprint,
+            available: alternate.available,
+            alternate_of: Some(matched.mpn),
+        },
+        None => matched,
+    })
+}
+
+fn bom_error(msg: &str) -> axum::response::Response {
+    let response = html! {
+        p style="color: red;" { (msg) }
+    };
+    (
+        StatusCode::UNPROCESSABLE_ENTITY,
+        Html(response.into_string()),
+    )
+        .into_response()
+}
+
+fn html_bom_report(lines: &[BomLine]) -> Markup {
+    // (Lines matching the same part are staged together)
+    let mut parts: Vec<(i32, i32, &str)> = vec![];
+    for line in lines {
+        if let Some(matched) = &line.matched {
+            match parts.iter_mut().find(|x| x.0 == matched.id) {
+                Some(part) => part.1 += line.qty,
+                None => parts.push((
+                    matched.id,
+                    line.qty,
+                    matched.mpn.as_deref().unwrap_or_default(),
+                )),
+            }
+        }
+    }
+    let num_matched = lines.iter().filter(|x| x.matched.is_some()).count();
+
+    html! {
+        p {
+            "Matched " (num_matched) " of " (lines.len()) " lines."
+        }
+        div class="overflow-auto" {
+            table class="striped" {
+                thead {
+                    tr {
+                        th scope="col" { "Refs" }
+                        th scope="col" { "Value" }
+                        th scope="col" { "Footprint" }
+                        th scope="col" { "MPN" }
+                        th scope="col" { "Qty." }
+                        th scope="col" { "Part" }
+                        th scope="col" { "Available" }
+                    }
+                }
+                tbody {
+                    @for line in lines {
+                        tr {
+                            td { (line.refs) }
+                            td { (line.value) }
+                            td { (line.footprint) }
+                            td { (line.mpn) }
+                            td { (line.qty) }
+                            @if let Some(matched) = &line.matched {
+                                td {
+                                    a href={"/part/" (matched.id)} {
+                                        @if let Some(mpn) = &matched.mpn {
+                                            (mpn)
+                                        } @else {
+                                            "#" (matched.id)
+                                        }
+                                    }
+                                    @if let Some(original) = &matched.alternate_of {
+                                        small { " (alternate of " (original.as_deref().unwrap_or("—")) ")" }
+                                    }
+                                }
+                                td {
+                                    @if matched.available < line.qty {
+                                        mark title="Not enough for one board" { (matched.available) }
+                                    } @else {
+                                        (matched.available)
+                                    }
+                                }
+                            } @else {
+                                td style="color: red;" { "No match" }
+                                td { "—" }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        @if !parts.is_empty() {
+            form hx-post="/api/bom/stage" hx-target="#bom-result" {
+                @for (id, qty, mpn) in &parts {
+                    input type="hidden" name="part" value={(id) ":" (qty) ":" (mpn)};
+                }
+                div role="group" {
+                    input type="number" name="boards" value="1" min="1" aria-label="Boards";
+                    button type="submit" { "Stage for boards" }
+                }
+            }
+        }
+    }
+}
+
+pub async fn bom_import_handler(
+    State(state): State<AppState>,
+    mut multipart: Multipart,
+) -> impl IntoResponse {
+    let mut data = None;
+
+    loop {
+        match multipart.next_field().await {
+            Ok(Some(field)) if field.name() == Some("file") => match field.bytes().await {
+                Ok(bytes) => data = Some(bytes),
+                Err(e) => return handle_generic_inventory_error(e),
+            },
+            Ok(Some(_)) => {}
+            Ok(None) => break,
+            Err(e) => return handle_generic_inventory_error(e),
+        }
+    }
+
+    let Some(data) = data else {
+        return bom_error("No BOM file uploaded.");
+    };
+
+    info!("Importing BOM of {} bytes", data.len());
+
+    let Ok(text) = std::str::from_utf8(&data) else {
+        return bom_error("The BOM is not valid UTF-8.");
+    };
+    let lines = if text.trim_start().starts_with('<') {
+        parse_bom_xml(text)
+    } else {
+        parse_bom_csv(text.as_bytes())
+    };
+    let mut lines = match lines {
+        Ok(lines) => lines,
+        Err(e) => return bom_error(&format!("Unable to read the BOM: {}", e)),
+    };
+
+    let mut db_conn = match state.pool.acquire().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            return handle_generic_inventory_error(e);
+        }
+    };
+
+    for line in lines.iter_mut() {
+        let matched = match match_line(line, &mut db_conn).await {
+            Ok(matched) => matched,
+            Err(e) => return handle_generic_inventory_error(e),
+        };
+        line.matched = match matched {
+            Some(matched) => match resolve_alternate(matched, line.qty, &mut db_conn).await {
+                Ok(matched) => Some(matched),
+                Err(e) => return handle_generic_inventory_error(e),
+            },
+            None => None,
+        };
+    }
+
+    Html(html_bom_report(&lines).into_string()).into_response()
+}
+
+// Stages the parts of the BOM, in a single transaction. Returns how many were
+// staged, the ones without enough stock and the ones which no longer exist
+async fn stage_bom(
+    parts: &[(i32, i32, String)],
+    user_id: Option<i32>,
+    db_conn: &mut PgConnection,
+) -> Result<(usize, Vec<(i32, Option<String>, i32)>, Vec<String>), sqlx::Error> {
+    let mut tx = db_conn.begin().await?;
+
+    let mut num_staged = 0;
+    let mut shortages = vec![];
+    let mut skipped = vec![];
+    for (id, amount, form_mpn) in parts {
+        let mut query =
+            QueryBuilder::new("SELECT mpn FROM parts WHERE deleted_at IS NULL AND id = ");
+        query.push_bind(id);
+        let Some(mpn) = query
+            .build_query_scalar::<Option<String>>()
+            .fetch_optional(&mut *tx)
+            .await?
+        else {
+            skipped.push(match form_mpn.as_str() {
+                "" => format!("#{}", id),
+                mpn => mpn.to_string(),
+            });
+            continue;
+        };
+
+        // (Staging is clamped to the stock, so whatever there is gets staged)
+        let staged = update_stage(*id, *amount, None, user_id, &mut tx)
+            .await?
+            .delta();
+        if staged > 0 {
+            num_staged += 1;
+        }
+        if staged < *amount {
+            shortages.push((*id, mpn, amount - staged));
+        }
+    }
+
+    tx.commit().await?;
+
+    Ok((num_staged, shortages, skipped))
+}
+
+// Stages the parts for the given number of boards, flagging the ones without
+// enough stock so that it's noticed before committing the stage
+pub async fn bom_stage_handler(
+    State(state): State<AppState>,
+    Extension(CurrentUser(user_id)): Extension<CurrentUser>,
+    MultiForm(form): MultiForm<BomStageForm>,
+) -> impl IntoResponse {
+    let Some(boards) = form.boards.trim().parse::<i32>().ok().filter(|x| *x > 0) else {
+        return bom_error("The number of boards must be a positive number.");
+    };
+
+    // (In the order of the BOM, so that the report follows it)
+    let mut parts: Vec<(i32, i32, String)> = vec![];
+    for part in &form.part {
+        let mut fields = part.splitn(3, ':');
+        let parsed = fields
+            .next()
+            .zip(fields.next())
+            .and_then(|(id, qty)| Some((id.parse::<i32>().ok()?, qty.parse::<i32>().ok()?)));
+        let Some((id, qty)) = parsed else {
+            return bom_error(&format!("Malformed part \"{}\".", part));
+        };
+        let Some(amount) = qty.checked_mul(boards) else {
+            return bom_error("Too many boards.");
+        };
+        match parts.iter_mut().find(|x| x.0 == id) {
+            Some(part) => match part.1.checked_add(amount) {
+                Some(total) => part.1 = total,
+                None => return bom_error("Too many boards."),
+            },
+            None => parts.push((id, amount, fields.next().unwrap_or_default().to_string())),
+        }
+    }
+
+    info!("Staging {} BOM parts for {} boards", parts.len(), boards);
+
+    let mut db_conn = match state.pool.acquire().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            return handle_generic_inventory_error(e);
+        }
+    };
+
+    let (num_staged, shortages, skipped) =
+        match stage_bom(&parts, Some(user_id), &mut db_conn).await {
+            Ok(result) => result,
+            Err(e) => return handle_generic_inventory_error(e),
+        };
+    metrics::counter!("staging_operations_total", "operation" => "bom").increment(1);
+
+    state.notify_change(None);
+    let mut headers = HeaderMap::new();
+    headers.insert("HX-Trigger", "inventoryUpdated".parse().unwrap());
+    let response = html! {
+        article {
+            "Staged " (num_staged) " parts for " (boards) " boards."
+            @if !shortages.is_empty() {
+                p style="color: red;" { "Not enough stock for:" }
+                ul {
+                    @for (id, mpn, missing) in &shortages {
+                        li {
+                            a href={"/part/" (id)} { (mpn.as_deref().unwrap_or("—")) }
+                            ", short by " (missing)
+                        }
+                    }
+                }
+            }
+            @if !skipped.is_empty() {
+                p style="color: red;" {
+                    "Skipped, as they no longer exist: " (skipped.join(", "))
+                }
+            }
+        }
+    };
+    (headers, Html(response.into_string())).into_response()
+}
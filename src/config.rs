@@ -0,0 +1,229 @@
+use std::{fmt::Display, str::FromStr};
+
+use serde::Deserialize;
+use tower_sessions::cookie::SameSite;
+
+const DEFAULT_CONFIG_PATH: &str = "station.toml";
+
+// As read from the (optional) TOML file, where the keys are the lowercase
+// names of the environment variables
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FileConfig {
+    db_host: Option<String>,
+    db_port: Option<u16>,
+    db_name: Option<String>,
+    db_user: Option<String>,
+    db_password: Option<String>,
+    db_max_connections: Option<u32>,
+    db_acquire_timeout_secs: Option<u64>,
+    db_idle_timeout_secs: Option<u64>,
+    db_statement_timeout_secs: Option<u64>,
+    host: Option<String>,
+    allow_unsecure_cookie: Option<bool>,
+    session_expiry_secs: Option<i64>,
+    session_samesite: Option<String>,
+    session_cookie_name: Option<String>,
+    login_password: Option<String>,
+    login_max_attempts: Option<usize>,
+    login_window_secs: Option<u64>,
+    trust_proxy: Option<bool>,
+    metrics_host: Option<String>,
+    lookup_url: Option<String>,
+    lookup_api_key: Option<String>,
+}
+
+pub struct Config {
+    pub db_host: String,
+    // (Only used by pg_dump, the connection itself goes to the default port)
+    pub db_port: Option<u16>,
+    pub db_name: String,
+    pub db_user: String,
+    pub db_password: String,
+    pub db_max_connections: u32,
+    // Requests waiting longer than this for a connection fail instead
+    pub db_acquire_timeout_secs: u64,
+    // Of unused connections, which are opened again once needed (0 keeps them)
+    pub db_idle_timeout_secs: u64,
+    // Queries running longer are cancelled by Postgres (0 lets them run)
+    pub db_statement_timeout_secs: u64,
+    pub host: String,
+    pub allow_unsecure_cookie: bool,
+    // Of inactivity
+    pub session_expiry_secs: i64,
+    pub session_samesite: SameSite,
+    pub session_cookie_name: String,
+    // Only needed to create the first user, see auth::bootstrap_users
+    pub login_password: Option<String>,
+    pub login_max_attempts: usize,
+    pub login_window_secs: u64,
+    pub trust_proxy: bool,
+    pub metrics_host: Option<String>,
+    // Supplier part search, disabled if not set (see lookup.rs)
+    pub lookup_url: Option<String>,
+    pub lookup_api_key: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    File(String, String),
+    Missing(&'static str),
+    Invalid(&'static str, String),
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::File(path, e) => write!(f, "Failed to read {}: {}", path, e),
+            ConfigError::Missing(name) => write!(
+                f,
+                "Missing setting {}, set {} in the environment or {} in the config file",
+                name,
+                name.to_uppercase(),
+                name
+            ),
+            ConfigError::Invalid(name, e) => write!(
+                f,
+                "Invalid value for {} (from {}): {}",
+                name,
+                name.to_uppercase(),
+                e
+            ),
+        }
+    }
+}
+
+// Browsers drop SameSite=None cookies which aren't Secure, so logging in would
+// silently fail
+fn same_site(value: Option<String>, allow_unsecure_cookie: bool) -> Result<SameSite, ConfigError> {
+    let same_site = match value.as_deref().map(str::to_lowercase).as_deref() {
+        None | Some("lax") => SameSite::Lax,
+        Some("strict") => SameSite::Strict,
+        Some("none") => SameSite::None,
+        Some(_) => {
+            return Err(ConfigError::Invalid(
+                "session_samesite",
+                String::from("expected Lax, Strict or None"),
+            ));
+        }
+    };
+
+    if same_site == SameSite::None && allow_unsecure_cookie {
+        return Err(ConfigError::Invalid(
+            "session_samesite",
+            String::from("None needs a Secure cookie, unset allow_unsecure_cookie"),
+        ));
+    }
+
+    Ok(same_site)
+}
+
+// The environment variable takes precedence over the file
+fn setting<T>(name: &'static str, file: Option<T>) -> Result<Option<T>, ConfigError>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    match dotenvy::var(name.to_uppercase()) {
+        Ok(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|e: T::Err| ConfigError::Invalid(name, e.to_string())),
+        Err(_) => Ok(file),
+    }
+}
+
+fn required<T>(name: &'static str, file: Option<T>) -> Result<T, ConfigError>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    setting(name, file)?.ok_or(ConfigError::Missing(name))
+}
+
+impl Config {
+    // Reads STATION_CONFIG (or station.toml) if it exists, and then the environment
+    pub fn load() -> Result<Self, ConfigError> {
+        let path = dotenvy::var("STATION_CONFIG").ok();
+        let file = match std::fs::read_to_string(path.as_deref().unwrap_or(DEFAULT_CONFIG_PATH)) {
+            Ok(contents) => toml::from_str::<FileConfig>(&contents).map_err(|e| {
+                ConfigError::File(
+                    path.clone().unwrap_or(DEFAULT_CONFIG_PATH.into()),
+                    e.to_string(),
+                )
+            })?,
+            // (The default file is optional, but an explicitly given one isn't)
+            Err(e) if path.is_some() || e.kind() != std::io::ErrorKind::NotFound => {
+                return Err(ConfigError::File(
+                    path.unwrap_or(DEFAULT_CONFIG_PATH.into()),
+                    e.to_string(),
+                ));
+            }
+            Err(_) => FileConfig::default(),
+        };
+
+        let allow_unsecure_cookie =
+            setting("allow_unsecure_cookie", file.allow_unsecure_cookie)?.unwrap_or(false);
+
+        Ok(Config {
+            db_host: required("db_host", file.db_host)?,
+            db_port: setting("db_port", file.db_port)?,
+            db_name: required("db_name", file.db_name)?,
+            db_user: required("db_user", file.db_user)?,
+            db_password: required("db_password", file.db_password)?,
+            db_max_connections: setting("db_max_connections", file.db_max_connections)?
+                .unwrap_or(5),
+            db_acquire_timeout_secs: setting(
+                "db_acquire_timeout_secs",
+                file.db_acquire_timeout_secs,
+            )?
+            .unwrap_or(10),
+            db_idle_timeout_secs: setting("db_idle_timeout_secs", file.db_idle_timeout_secs)?
+                .unwrap_or(10 * 60),
+            db_statement_timeout_secs: setting(
+                "db_statement_timeout_secs",
+                file.db_statement_timeout_secs,
+            )?
+            .unwrap_or(30),
+            host: required("host", file.host)?,
+            allow_unsecure_cookie,
+            session_expiry_secs: setting("session_expiry_secs", file.session_expiry_secs)?
+                .unwrap_or(60 * 60 * 24 * 7),
+            session_samesite: same_site(
+                setting("session_samesite", file.session_samesite)?,
+                allow_unsecure_cookie,
+            )?,
+            session_cookie_name: setting("session_cookie_name", file.session_cookie_name)?
+                .unwrap_or(String::from("station_session")),
+            login_password: setting("login_password", file.login_password)?,
+            login_max_attempts: setting("login_max_attempts", file.login_max_attempts)?
+                .unwrap_or(10),
+            login_window_secs: setting("login_window_secs", file.login_window_secs)?
+                .unwrap_or(5 * 60),
+            trust_proxy: setting("trust_proxy", file.trust_proxy)?.unwrap_or(false),
+            metrics_host: setting("metrics_host", file.metrics_host)?,
+            lookup_url: setting("lookup_url", file.lookup_url)?,
+            lookup_api_key: setting("lookup_api_key", file.lookup_api_key)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_site_none_needs_secure_cookies() {
+        assert_eq!(same_site(None, true).unwrap(), SameSite::Lax);
+        assert_eq!(
+            same_site(Some(String::from("Strict")), true).unwrap(),
+            SameSite::Strict
+        );
+        assert_eq!(
+            same_site(Some(String::from("None")), false).unwrap(),
+            SameSite::None
+        );
+        assert!(same_site(Some(String::from("None")), true).is_err());
+        assert!(same_site(Some(String::from("Sometimes")), false).is_err());
+    }
+}
@@ -0,0 +1,120 @@
+use axum::{
+    extract::State,
+    response::{Html, IntoResponse},
+};
+use maud::{Markup, html};
+use rust_decimal::Decimal;
+use sqlx::QueryBuilder;
+use tower_sessions::Session;
+use tracing::info;
+
+use crate::{
+    auth::csrf_token,
+    inventory::handle_generic_inventory_error,
+    layout::{Theme, html_page},
+    state::AppState,
+};
+
+async fn query_count(state: &AppState, sql: &str) -> Result<i64, sqlx::Error> {
+    QueryBuilder::new(sql)
+        .build_query_scalar::<i64>()
+        .fetch_one(&state.pool)
+        .await
+}
+
+async fn query_stock_value(state: &AppState) -> Result<Decimal, sqlx::Error> {
+    QueryBuilder::new(
+        "SELECT COALESCE(SUM(s.quantity * p.unit_price), 0) FROM stock s \
+         JOIN parts p ON s.part_id = p.id WHERE p.deleted_at IS NULL",
+    )
+    .build_query_scalar::<Decimal>()
+    .fetch_one(&state.pool)
+    .await
+}
+
+fn html_card(title: &str, value: &str, href: Option<&str>) -> Markup {
+    html! {
+        article {
+            header { (title) }
+            @if let Some(href) = href {
+                a href=(href) { h2 { (value) } }
+            } @else {
+                h2 { (value) }
+            }
+        }
+    }
+}
+
+struct DashboardStats {
+    parts: i64,
+    units: i64,
+    categories: i64,
+    below_reorder: i64,
+    stock_value: Decimal,
+}
+
+// (Independent aggregates, so they run at the same time on separate connections)
+async fn query_stats(state: &AppState) -> Result<DashboardStats, sqlx::Error> {
+    let (parts, units, categories, below_reorder, stock_value) = tokio::try_join!(
+        query_count(state, "SELECT COUNT(*) FROM parts WHERE deleted_at IS NULL"),
+        query_count(
+            state,
+            "SELECT COALESCE(SUM(s.quantity), 0)::BIGINT FROM stock s \
+             JOIN parts p ON s.part_id = p.id WHERE p.deleted_at IS NULL"
+        ),
+        query_count(
+            state,
+            "SELECT COUNT(DISTINCT category_id) FROM parts WHERE deleted_at IS NULL"
+        ),
+        // (Same condition as the reorder page)
+        query_count(
+            state,
+            "SELECT COUNT(*) FROM inventory WHERE deleted_at IS NULL \
+             AND COALESCE(quantity, 0) - COALESCE(staged, 0) - COALESCE(reserved, 0) \
+             <= reorder_point"
+        ),
+        query_stock_value(state),
+    )?;
+
+    Ok(DashboardStats {
+        parts,
+        units,
+        categories,
+        below_reorder,
+        stock_value,
+    })
+}
+
+pub async fn dashboard_page(
+    State(state): State<AppState>,
+    session: Session,
+    theme: Theme,
+) -> impl IntoResponse {
+    info!("Performing dashboard queries");
+
+    let csrf_token = match csrf_token(&session).await {
+        Ok(token) => token,
+        Err(e) => return handle_generic_inventory_error(e),
+    };
+
+    let stats = match query_stats(&state).await {
+        Ok(stats) => stats,
+        Err(e) => {
+            return handle_generic_inventory_error(e);
+        }
+    };
+
+    let content = html! {
+        div class="grid" {
+            (html_card("Parts", &stats.parts.to_string(), None))
+            (html_card("Units in stock", &stats.units.to_string(), None))
+            (html_card("Categories", &stats.categories.to_string(), None))
+        }
+        div class="grid" {
+            (html_card("Below reorder point", &stats.below_reorder.to_string(), Some("/reorder")))
+            (html_card("Stock value", &format!("{:.2}", stats.stock_value), None))
+        }
+    };
+
+    Html(html_page("Dashboard", &csrf_token, theme, content).into_string()).into_response()
+}
@@ -0,0 +1,42 @@
+use std::time::Duration;
+
+use axum::{extract::State, http::StatusCode, response::IntoResponse};
+use sqlx::Acquire;
+use tracing::warn;
+
+use crate::state::AppState;
+
+// (Load balancers give up on probes after a few seconds, so fail before that)
+const READY_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Liveness, the process is up and serving requests
+pub async fn health_handler() -> impl IntoResponse {
+    (StatusCode::OK, "ok")
+}
+
+async fn check_db(state: &AppState) -> Result<(), sqlx::Error> {
+    let mut conn = state.pool.acquire().await?;
+    let mut tx = conn.begin().await?;
+    // (Only applies within this transaction, the connection is left as it was)
+    sqlx::query("SET LOCAL statement_timeout = 1000")
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("SELECT 1").execute(&mut *tx).await?;
+    tx.commit().await
+}
+
+// Readiness, the DB is reachable
+pub async fn ready_handler(State(state): State<AppState>) -> impl IntoResponse {
+    // (Also covers waiting for a connection from an exhausted pool)
+    match tokio::time::timeout(READY_TIMEOUT, check_db(&state)).await {
+        Ok(Ok(())) => (StatusCode::OK, "ready"),
+        Ok(Err(e)) => {
+            warn!("Readiness check failed: {}", e);
+            (StatusCode::SERVICE_UNAVAILABLE, "db unavailable")
+        }
+        Err(_) => {
+            warn!("Readiness check timed out");
+            (StatusCode::SERVICE_UNAVAILABLE, "db unavailable")
+        }
+    }
+}
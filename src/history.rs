@@ -0,0 +1,197 @@
+use axum::{
+    extract::{Path, State},
+    response::{Html, IntoResponse},
+};
+use maud::{Markup, html};
+use sqlx::{PgConnection, QueryBuilder};
+use tower_sessions::Session;
+use tracing::info;
+
+use crate::{
+    auth::csrf_token,
+    inventory::handle_generic_inventory_error,
+    layout::{Theme, html_page},
+    state::AppState,
+};
+
+// A change to a single field of a part, to be stored in the stock log
+#[derive(Debug)]
+pub struct StockLogEntry {
+    pub part_id: i32,
+    pub field: &'static str,
+    pub delta: Option<i32>,
+    pub old_value: Option<String>,
+    pub new_value: Option<String>,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct StockLogRow {
+    username: Option<String>,
+    field: String,
+    delta: Option<i32>,
+    old_value: Option<String>,
+    new_value: Option<String>,
+    timestamp: chrono::DateTime<chrono::Utc>,
+}
+
+impl StockLogEntry {
+    pub fn count(part_id: i32, field: &'static str, old: Option<i32>, new: Option<i32>) -> Self {
+        StockLogEntry {
+            part_id,
+            field,
+            delta: Some(new.unwrap_or(0) - old.unwrap_or(0)),
+            old_value: old.map(|x| x.to_string()),
+            new_value: new.map(|x| x.to_string()),
+        }
+    }
+
+    pub fn text(
+        part_id: i32,
+        field: &'static str,
+        old: Option<String>,
+        new: Option<String>,
+    ) -> Self {
+        StockLogEntry {
+            part_id,
+            field,
+            delta: None,
+            old_value: old,
+            new_value: new,
+        }
+    }
+}
+
+// Should be called within the same transaction as the change itself, with the
+// user who made it
+pub async fn insert_log(
+    entries: &[StockLogEntry],
+    user_id: Option<i32>,
+    db_conn: &mut PgConnection,
+) -> Result<(), sqlx::Error> {
+    if entries.is_empty() {
+        return Ok(());
+    }
+
+    let mut query = QueryBuilder::new(
+        "INSERT INTO stock_log (part_id, field, delta, old_value, new_value, user_id) ",
+    );
+    query.push_values(entries, |mut row, entry| {
+        row.push_bind(entry.part_id)
+            .push_bind(entry.field)
+            .push_bind(entry.delta)
+            .push_bind(&entry.old_value)
+            .push_bind(&entry.new_value)
+            .push_bind(user_id);
+    });
+    query.build().execute(&mut *db_conn).await?;
+
+    Ok(())
+}
+
+// Newest changes first
+pub async fn query_history(
+    id: i32,
+    db_conn: &mut PgConnection,
+) -> Result<Vec<StockLogRow>, sqlx::Error> {
+    let mut query = QueryBuilder::new(
+        "SELECT u.username, l.field, l.delta, l.old_value, l.new_value, l.timestamp FROM stock_log l",
+    );
+    query.push(" LEFT JOIN users u ON l.user_id = u.id WHERE l.part_id = ");
+    query.push_bind(id);
+    query.push(" ORDER BY l.timestamp DESC, l.id DESC");
+    query
+        .build_query_as::<StockLogRow>()
+        .fetch_all(&mut *db_conn)
+        .await
+}
+
+pub async fn history_page(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+    session: Session,
+    theme: Theme,
+) -> impl IntoResponse {
+    info!("Performing history query for component {}", id);
+
+    let csrf_token = match csrf_token(&session).await {
+        Ok(token) => token,
+        Err(e) => return handle_generic_inventory_error(e),
+    };
+
+    let mut db_conn = match state.pool.acquire().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            return handle_generic_inventory_error(e);
+        }
+    };
+
+    let mut query = QueryBuilder::new("SELECT mpn FROM parts WHERE id = ");
+    query.push_bind(id);
+    let mpn = match query
+        .build_query_scalar::<Option<String>>()
+        .fetch_optional(db_conn.as_mut())
+        .await
+    {
+        Ok(Some(mpn)) => mpn,
+        Ok(None) => {
+            return handle_generic_inventory_error(format!("No component with id {}", id));
+        }
+        Err(e) => {
+            return handle_generic_inventory_error(e);
+        }
+    };
+
+    let entries = match query_history(id, &mut db_conn).await {
+        Ok(entries) => entries,
+        Err(e) => {
+            return handle_generic_inventory_error(e);
+        }
+    };
+
+    let title = format!("History of {}", mpn.as_deref().unwrap_or("—"));
+    Html(html_page(&title, &csrf_token, theme, html_history_table(&entries)).into_string())
+        .into_response()
+}
+
+pub fn html_history_table(entries: &[StockLogRow]) -> Markup {
+    html! {
+        @if entries.is_empty() {
+            p { "No changes recorded." }
+        } @else {
+            div class="overflow-auto" {
+                table class="striped" {
+                    thead {
+                        tr {
+                            th scope="col" { "Time" }
+                            th scope="col" { "By" }
+                            th scope="col" { "Field" }
+                            th scope="col" { "Change" }
+                            th scope="col" { "Old" }
+                            th scope="col" { "New" }
+                        }
+                    }
+                    tbody {
+                        @for entry in entries {
+                            tr {
+                                th scope="row" {
+                                    (entry.timestamp.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M:%S"))
+                                }
+                                td { (entry.username.as_deref().unwrap_or("—")) }
+                                td { (entry.field) }
+                                td {
+                                    @if let Some(delta) = entry.delta {
+                                        @if delta > 0 { "+" } (delta)
+                                    } @else {
+                                        "—"
+                                    }
+                                }
+                                td { (entry.old_value.as_deref().unwrap_or("—")) }
+                                td { (entry.new_value.as_deref().unwrap_or("—")) }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,172 @@
+use axum::{
+    Extension,
+    extract::{Multipart, Path, State},
+    http::{HeaderMap, StatusCode, header},
+    response::{Html, IntoResponse},
+};
+use sqlx::{Connection, PgConnection, QueryBuilder};
+use tracing::info;
+
+use crate::{
+    auth::CurrentUser,
+    history::{StockLogEntry, insert_log},
+    inventory::{Columns, handle_generic_inventory_error, html_table_row, query_item},
+    state::AppState,
+};
+
+// (Must stay below axum's default body limit of 2 MiB, which includes the multipart framing)
+const MAX_IMAGE_SIZE: usize = 1024 * 1024;
+
+// Sniffs the magic bytes, as the uploaded content type can't be trusted
+fn image_content_type(data: &[u8]) -> Option<&'static str> {
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("image/png")
+    } else if data.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some("image/jpeg")
+    } else {
+        None
+    }
+}
+
+// Logged as the type and size, the previous image isn't kept
+async fn set_image(
+    id: i32,
+    content_type: &str,
+    data: &[u8],
+    user_id: Option<i32>,
+    db_conn: &mut PgConnection,
+) -> Result<(), sqlx::Error> {
+    let mut tx = db_conn.begin().await?;
+
+    let mut query = QueryBuilder::new(
+        "SELECT content_type || ', ' || octet_length(data) || ' bytes' FROM part_image WHERE part_id = ",
+    );
+    query.push_bind(id);
+    query.push(" FOR UPDATE");
+    let old = query
+        .build_query_scalar::<String>()
+        .fetch_optional(&mut *tx)
+        .await?;
+
+    let mut query =
+        QueryBuilder::new("INSERT INTO part_image (part_id, content_type, data) VALUES (");
+    let mut values = query.separated(", ");
+    values.push_bind(id);
+    values.push_bind(content_type);
+    values.push_bind(data);
+    query.push(") ON CONFLICT (part_id) DO UPDATE SET content_type = EXCLUDED.content_type, data = EXCLUDED.data");
+    query.build().execute(&mut *tx).await?;
+
+    let new = format!("{}, {} bytes", content_type, data.len());
+    insert_log(
+        &[StockLogEntry::text(id, "image", old, Some(new))],
+        user_id,
+        &mut tx,
+    )
+    .await?;
+
+    tx.commit().await?;
+    Ok(())
+}
+
+fn image_error(msg: String) -> axum::response::Response {
+    let mut headers = HeaderMap::new();
+    headers.insert("HX-Retarget", "#stage-status".parse().unwrap());
+    headers.insert("HX-Reswap", "innerHTML".parse().unwrap());
+    (StatusCode::UNPROCESSABLE_ENTITY, headers, Html(msg)).into_response()
+}
+
+pub async fn upload_image_handler(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+    Extension(CurrentUser(user_id)): Extension<CurrentUser>,
+    columns: Columns,
+    mut multipart: Multipart,
+) -> impl IntoResponse {
+    let mut image = None;
+
+    loop {
+        match multipart.next_field().await {
+            Ok(Some(field)) if field.name() == Some("image") => match field.bytes().await {
+                Ok(bytes) => image = Some(bytes),
+                Err(e) => return handle_generic_inventory_error(e),
+            },
+            Ok(Some(_)) => {}
+            Ok(None) => break,
+            Err(e) => return handle_generic_inventory_error(e),
+        }
+    }
+
+    let Some(image) = image.filter(|x| !x.is_empty()) else {
+        return image_error(String::from("No image uploaded"));
+    };
+
+    info!("Uploading {} byte image for component {}", image.len(), id);
+
+    if image.len() > MAX_IMAGE_SIZE {
+        return image_error(format!(
+            "Image is too large, the limit is {} KiB",
+            MAX_IMAGE_SIZE / 1024
+        ));
+    }
+
+    let Some(content_type) = image_content_type(&image) else {
+        return image_error(String::from("Only PNG and JPEG images are supported"));
+    };
+
+    let mut db_conn = match state.pool.acquire().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            return handle_generic_inventory_error(e);
+        }
+    };
+
+    // (Checked first, as the foreign key error wouldn't be too readable)
+    match query_item(id, &mut db_conn).await {
+        Ok(Some(_)) => {}
+        Ok(None) => {
+            return handle_generic_inventory_error(format!("No component with id {}", id));
+        }
+        Err(e) => return handle_generic_inventory_error(e),
+    }
+
+    if let Err(e) = set_image(id, content_type, &image, Some(user_id), &mut db_conn).await {
+        return handle_generic_inventory_error(e);
+    }
+
+    match query_item(id, &mut db_conn).await {
+        Ok(Some(result)) => {
+            state.notify_change(Some(id));
+            Html(html_table_row(&result, &state.category_units(), &columns).into_string())
+                .into_response()
+        }
+        Ok(None) => handle_generic_inventory_error(format!("No component with id {}", id)),
+        Err(e) => handle_generic_inventory_error(e),
+    }
+}
+
+pub async fn image_handler(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+) -> impl IntoResponse {
+    let mut db_conn = match state.pool.acquire().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            return handle_generic_inventory_error(e);
+        }
+    };
+
+    let mut query = QueryBuilder::new("SELECT content_type, data FROM part_image WHERE part_id = ");
+    query.push_bind(id);
+    match query
+        .build_query_as::<(String, Vec<u8>)>()
+        .fetch_optional(db_conn.as_mut())
+        .await
+    {
+        Ok(Some((content_type, data))) => {
+            ([(header::CONTENT_TYPE, content_type)], data).into_response()
+        }
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => handle_generic_inventory_error(e),
+    }
+}
@@ -1,396 +1,4387 @@
-use std::{fmt::Display, str::from_utf8};
+use std::{
+    collections::{HashMap, HashSet},
+    convert::Infallible,
+    fmt::{Display, Write},
+    str::{FromStr, from_utf8},
+    sync::{Arc, LazyLock, RwLock},
+    time::{Duration, Instant},
+};
 
 use axum::{
-    Form,
-    extract::{Path, State},
-    http::{HeaderMap, header},
+    Extension, Form, Json,
+    body::Body,
+    extract::{FromRequestParts, Multipart, Path, RawQuery, State},
+    http::{HeaderMap, StatusCode, header, request::Parts},
     response::{Html, IntoResponse},
 };
+use axum_extra::extract::Form as MultiForm;
 use maud::{Markup, html};
-use serde::Deserialize;
-use sqlx::{Postgres, QueryBuilder, pool::PoolConnection};
-use tracing::{error, info};
+use qrcode::{Color, QrCode};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use sqlx::{Acquire, PgConnection, Pool, Postgres, QueryBuilder, pool::PoolConnection};
+use tokio::sync::{Notify, mpsc};
+use tokio_stream::{StreamExt, wrappers::ReceiverStream};
+use tower_sessions::Session;
+use tracing::{debug, error, info, warn};
+
+const ALL_CATEGORIES_STR: &str = "All Categories";
+const ALL_FOOTPRINTS_STR: &str = "All Footprints";
+const NO_FOOTPRINT_STR: &str = "No Footprint";
 
-const ALL_CATEGORIES_STR: &'static str = "All Categories";
-const ALL_FOOTPRINTS_STR: &'static str = "All Footprints";
-const NO_FOOTPRINT_STR: &'static str = "No Footprint";
+// Shorter terms are ignored, as they'd match most parts anyway and scan the
+// whole table doing so (the page only searches once typing pauses for 300ms)
+const MIN_SEARCH_LEN: usize = 2;
+const MIN_FULLTEXT_SEARCH_LEN: usize = 3;
 
+const DEFAULT_PAGE_SIZE: u32 = 100;
+const MAX_PAGE_SIZE: u32 = 500;
+
+// (Deserialized with axum_extra's Form, which accepts the repeated fields that
+// multi-selects produce)
 #[derive(Debug, Deserialize)]
 pub struct SearchForm {
-    category: String,
-    footprint: String,
+    #[serde(default)]
+    category: Vec<String>,
+    #[serde(default)]
+    footprint: Vec<String>,
     min_val: String,
     max_val: String,
+    min_voltage: Option<String>,
+    max_voltage: Option<String>,
+    min_power: Option<String>,
+    max_power: Option<String>,
     in_stock: Option<String>,
     in_stage: Option<String>,
+    // Data quality, to find parts which still need details filled in
+    missing_mpn: Option<String>,
+    missing_footprint: Option<String>,
+    missing_value: Option<String>,
+    has_comments: Option<String>,
+    // Prefix, so that a shelf also matches the drawers on it
+    location: Option<String>,
+    tag: Option<String>,
+    status: Option<String>,
     search: String,
     sort: String,
     dir: String,
+    page: Option<u32>,
+    page_size: Option<u32>,
 }
 
-#[derive(Debug, sqlx::FromRow)]
-pub struct InventoryItem {
-    id: i32,
-    mpn: Option<String>,
-    category: String,
-    footprint: Option<String>,
-    value: Option<f32>,
-    location: Option<String>,
-    quantity: Option<i32>,
-    staged: Option<i32>,
-    comments: Option<String>,
+// Columns of the table which can be hidden (MPN, category and actions always
+// show), picked in inventory.html and kept in a cookie listing the hidden ones
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Columns {
+    pub footprint: bool,
+    pub comments: bool,
+    pub value: bool,
+    pub voltage_rating: bool,
+    pub power_rating: bool,
+    pub location: bool,
+    pub quantity: bool,
+    pub stock_value: bool,
 }
 
-#[derive(Debug, Deserialize)]
-pub struct FootprintAndCategoryForm {
-    footprint: String,
-    category: String,
+const HIDDEN_COLUMNS_COOKIE: &str = "hidden_columns";
+
+impl Default for Columns {
+    fn default() -> Self {
+        Columns {
+            footprint: true,
+            comments: true,
+            value: true,
+            voltage_rating: true,
+            power_rating: true,
+            location: true,
+            quantity: true,
+            stock_value: true,
+        }
+    }
 }
 
-use crate::state::AppState;
+impl Columns {
+    // Unknown names are ignored, e.g. from columns which no longer exist
+    pub fn from_headers(headers: &HeaderMap) -> Columns {
+        let mut columns = Columns::default();
+        let Some(hidden) = cookie(headers, HIDDEN_COLUMNS_COOKIE) else {
+            return columns;
+        };
 
-pub fn handle_generic_inventory_error<E: Display>(e: E) -> Html<String> {
-    error!("Error while processing inventory API call: {}", e);
-    return Html(
-        html! {
-            article {
-                "Error while processing, try again later."
+        for name in hidden.split(',') {
+            match name.trim() {
+                "footprint" => columns.footprint = false,
+                "comments" => columns.comments = false,
+                "value" => columns.value = false,
+                "voltage_rating" => columns.voltage_rating = false,
+                "power_rating" => columns.power_rating = false,
+                "location" => columns.location = false,
+                "quantity" => columns.quantity = false,
+                "stock_value" => columns.stock_value = false,
+                _ => {}
             }
         }
-        .into_string(),
-    );
+        columns
+    }
 }
 
-fn parse_multiple_value(v: &String) -> Option<f32> {
-    let number_end = v.rfind(|x: char| x.is_ascii_digit())?;
-    if number_end + 1 >= v.len() {
-        return v.parse::<f32>().ok();
-    }
+impl<S: Send + Sync> FromRequestParts<S> for Columns {
+    type Rejection = Infallible;
 
-    assert!(number_end + 1 < v.len());
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Columns::from_headers(&parts.headers))
+    }
+}
 
-    let number_part = &v[0..number_end + 1];
-    let qty_part = &v[number_end + 1..].trim();
+// Columns the table can be sorted by, which are also the ids of the headers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SortColumn {
+    #[default]
+    Mpn,
+    Category,
+    Footprint,
+    Comments,
+    Value,
+    VoltageRating,
+    PowerRating,
+    Location,
+    Quantity,
+    // (Not columns of the table, picked from the sort menu instead)
+    CreatedAt,
+    UpdatedAt,
+}
 
-    let number = number_part.parse::<f32>().ok()?;
-    match *qty_part {
-        "p" => Some(number * 1e-12),
-        "n" => Some(number * 1e-9),
-        "u" => Some(number * 1e-6),
-        "m" => Some(number * 1e-3),
-        "k" => Some(number * 1e3),
-        "M" => Some(number * 1e6),
-        "G" => Some(number * 1e9),
-        _ => Some(number),
+impl SortColumn {
+    fn as_str(&self) -> &'static str {
+        match self {
+            SortColumn::Mpn => "mpn",
+            SortColumn::Category => "category",
+            SortColumn::Footprint => "footprint",
+            SortColumn::Comments => "comments",
+            SortColumn::Value => "value",
+            SortColumn::VoltageRating => "voltage_rating",
+            SortColumn::PowerRating => "power_rating",
+            SortColumn::Location => "location",
+            SortColumn::Quantity => "quantity",
+            SortColumn::CreatedAt => "created_at",
+            SortColumn::UpdatedAt => "updated_at",
+        }
     }
 }
 
-async fn query_inventory(
-    search: &SearchForm,
-    db_conn: &mut PoolConnection<Postgres>,
-) -> Result<Vec<InventoryItem>, sqlx::Error> {
-    let mut query = QueryBuilder::new("SELECT * FROM inventory WHERE 1=1");
-    if search.category != ALL_CATEGORIES_STR && !search.category.is_empty() {
-        query.push(" AND category = ");
-        query.push_bind(&search.category);
-    }
+impl FromStr for SortColumn {
+    type Err = String;
 
-    if search.footprint != ALL_FOOTPRINTS_STR && !search.footprint.is_empty() {
-        if search.footprint == NO_FOOTPRINT_STR {
-            query.push(" AND footprint IS NULL");
-        } else {
-            query.push(" AND footprint = ");
-            query.push_bind(&search.footprint);
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mpn" => Ok(SortColumn::Mpn),
+            "category" => Ok(SortColumn::Category),
+            "footprint" => Ok(SortColumn::Footprint),
+            "comments" => Ok(SortColumn::Comments),
+            "value" => Ok(SortColumn::Value),
+            "voltage_rating" => Ok(SortColumn::VoltageRating),
+            "power_rating" => Ok(SortColumn::PowerRating),
+            "location" => Ok(SortColumn::Location),
+            "quantity" => Ok(SortColumn::Quantity),
+            "created_at" => Ok(SortColumn::CreatedAt),
+            "updated_at" => Ok(SortColumn::UpdatedAt),
+            _ => Err(format!("Invalid sort column {}", s)),
         }
     }
+}
 
-    if search.in_stock.is_some() {
-        query.push(" AND quantity > 0");
-    }
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SortDir {
+    Asc,
+    #[default]
+    Desc,
+}
 
-    if search.in_stage.is_some() {
-        query.push(" AND staged > 0");
+impl SortDir {
+    fn as_sql(&self) -> &'static str {
+        match self {
+            SortDir::Asc => " ASC",
+            SortDir::Desc => " DESC",
+        }
     }
+}
+
+impl FromStr for SortDir {
+    type Err = String;
 
-    if !search.min_val.is_empty() {
-        if let Some(min) = parse_multiple_value(&search.min_val) {
-            query.push(" AND value >= ");
-            query.push_bind(min);
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "asc" => Ok(SortDir::Asc),
+            "" | "desc" => Ok(SortDir::Desc),
+            _ => Err(format!("Invalid sort direction {}", s)),
         }
     }
+}
 
-    if !search.max_val.is_empty() {
-        if let Some(max) = parse_multiple_value(&search.max_val) {
-            query.push(" AND value <= ");
-            query.push_bind(max);
+impl SearchForm {
+    // Without a sort picked, the recently added parts come first, unless
+    // looking for something in particular
+    pub fn sort_order(&self) -> Result<(SortColumn, SortDir), String> {
+        match self.sort.as_str() {
+            "" if self.has_filters() => Ok((SortColumn::Mpn, SortDir::Asc)),
+            "" | "recent" => Ok((SortColumn::CreatedAt, SortDir::Desc)),
+            sort => Ok((sort.parse()?, self.dir.parse()?)),
         }
     }
 
-    if !search.search.is_empty() {
-        query.push(" AND (mpn ILIKE ");
-        query.push_bind(format!("%{}%", search.search));
-        query.push(" OR category ILIKE ");
-        query.push_bind(format!("%{}%", search.search));
-        query.push(" OR comments ILIKE ");
-        query.push_bind(format!("%{}%", search.search));
-        query.push(")");
+    // (Only in stock is checked by default, so it isn't counted)
+    fn has_filters(&self) -> bool {
+        let text = |x: Option<&str>| x.and_then(non_empty).is_some();
+        selected_filter(&self.category, ALL_CATEGORIES_STR).is_some()
+            || selected_filter(&self.footprint, ALL_FOOTPRINTS_STR).is_some()
+            || [
+                Some(self.min_val.as_str()),
+                Some(self.max_val.as_str()),
+                self.min_voltage.as_deref(),
+                self.max_voltage.as_deref(),
+                self.min_power.as_deref(),
+                self.max_power.as_deref(),
+                self.location.as_deref(),
+                self.tag.as_deref(),
+                self.status.as_deref(),
+            ]
+            .into_iter()
+            .any(text)
+            || self.in_stage.is_some()
+            || self.missing_mpn.is_some()
+            || self.missing_footprint.is_some()
+            || self.missing_value.is_some()
+            || self.has_comments.is_some()
+            || self.search.trim().chars().count() >= MIN_SEARCH_LEN
     }
 
-    match search.sort.as_str() {
-        "mpn" => query.push(" ORDER BY mpn"),
-        "category" => query.push(" ORDER BY category"),
-        "footprint" => query.push(" ORDER BY footprint"),
-        "value" => query.push(" ORDER BY value"),
-        "quantity" => query.push(" ORDER BY quantity"),
-        _ => query.push(" ORDER BY mpn"),
-    };
+    fn page(&self) -> u32 {
+        self.page.unwrap_or(0)
+    }
 
-    match search.dir.as_str() {
-        "asc" => query.push(" ASC"),
-        _ => query.push(" DESC"),
-    };
+    fn page_size(&self) -> u32 {
+        self.page_size
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE)
+    }
+}
 
-    query.push(" LIMIT 100");
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "part_status", rename_all = "lowercase")]
+pub enum PartStatus {
+    Active,
+    Nrnd,
+    Obsolete,
+    Sample,
+}
 
-    let sql = query.sql();
+impl PartStatus {
+    const ALL: [PartStatus; 4] = [
+        PartStatus::Active,
+        PartStatus::Nrnd,
+        PartStatus::Obsolete,
+        PartStatus::Sample,
+    ];
 
-    info!("Database query: {}", sql);
+    fn as_str(&self) -> &'static str {
+        match self {
+            PartStatus::Active => "active",
+            PartStatus::Nrnd => "nrnd",
+            PartStatus::Obsolete => "obsolete",
+            PartStatus::Sample => "sample",
+        }
+    }
 
-    query
-        .build_query_as::<InventoryItem>()
-        .fetch_all(db_conn.as_mut())
-        .await
+    fn label(&self) -> &'static str {
+        match self {
+            PartStatus::Active => "Active",
+            PartStatus::Nrnd => "NRND",
+            PartStatus::Obsolete => "Obsolete",
+            PartStatus::Sample => "Sample",
+        }
+    }
 }
 
-fn format_mult_value(value: f32) -> String {
-    if value < 1e-21 {
-        // (0 but with floating point precision!)
-        format!("{:.2}  ", value)
-    } else if value < 1e-9 {
-        format!("{:.2} p", value * 1e12)
-    } else if value < 1e-6 {
-        format!("{:.2} n", value * 1e9)
-    } else if value < 1e-3 {
-        format!("{:.2} µ", value * 1e6)
-    } else if value < 1e0 {
-        format!("{:.2} m", value * 1e3)
-    } else if value < 1e3 {
-        format!("{:.2}  ", value * 1e0)
-    } else if value < 1e6 {
-        format!("{:.2} k", value * 1e-3)
-    } else if value < 1e9 {
-        format!("{:.2} M", value * 1e-6)
-    } else {
-        format!("{:.2} G", value * 1e-9)
+impl FromStr for PartStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        PartStatus::ALL
+            .into_iter()
+            .find(|x| x.as_str() == s)
+            .ok_or_else(|| format!("Invalid status {}", s))
     }
 }
-fn format_value(category: &String, value: f32) -> String {
-    let (unit, mult) = match category.as_str() {
-        "CapCeramic" => ("F", true),
-        "CapElectro" => ("F", true),
-        "Resistor" => ("Ω", true),
-        "Inductor" => ("H", true),
-        _ => ("", false),
-    };
-    let value = if mult {
-        format_mult_value(value)
-    } else {
-        format!("{:.2}  ", value)
-    };
 
-    format!("{}{}", value, unit)
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct InventoryItem {
+    id: i32,
+    mpn: Option<String>,
+    category: String,
+    footprint: Option<String>,
+    value: Option<Decimal>,
+    voltage_rating: Option<Decimal>,
+    power_rating: Option<Decimal>,
+    location: Option<String>,
+    quantity: Option<i32>,
+    staged: Option<i32>,
+    // Across all projects
+    reserved: Option<i32>,
+    comments: Option<String>,
+    reorder_point: Option<i32>,
+    unit_price: Option<Decimal>,
+    tags: Vec<String>,
+    supplier: Option<String>,
+    supplier_url: Option<String>,
+    status: PartStatus,
+    // Label of the quantity (pcs, m, reel...)
+    qty_unit: String,
+    version: i32,
+    has_image: bool,
+    created_at: chrono::DateTime<chrono::Utc>,
+    updated_at: chrono::DateTime<chrono::Utc>,
 }
 
-fn response_filter_list(
-    filter_results: Vec<String>,
-    prev_value: &String,
-    no_filter: &'static str,
-) -> Markup {
-    let mut filter_results = filter_results;
-    // Remove the already chosen category, we insert it at the top
-    let chosen_idx = filter_results.iter().position(|x| x == prev_value);
-    let mut chosen_elem = None;
-    if let Some(idx) = chosen_idx
-        && prev_value != no_filter
-    {
-        chosen_elem = Some(filter_results.swap_remove(idx));
+impl InventoryItem {
+    pub fn id(&self) -> i32 {
+        self.id
     }
 
-    html! {
-        @if let Some(chosen) = chosen_elem {
-            option {
-                (chosen)
-            }
-        }
-        option {
-            (no_filter)
-        }
-        @for cat in &filter_results {
-            option {
-                (cat)
-            }
+    // Worth of the stock in hand, if the part has a price
+    fn stock_value(&self) -> Option<Decimal> {
+        Some(self.unit_price? * Decimal::from(self.quantity.unwrap_or(0)))
+    }
+
+    // What is left to build with, once the staged and reserved parts are taken
+    fn available(&self) -> i32 {
+        self.quantity.unwrap_or(0) - self.staged.unwrap_or(0) - self.reserved.unwrap_or(0)
+    }
+
+    // How many parts are missing to reach the reorder point, if at or below it
+    fn shortfall(&self) -> Option<i32> {
+        let reorder_point = self.reorder_point?;
+        let available = self.available();
+        if available <= reorder_point {
+            Some(reorder_point - available)
+        } else {
+            None
         }
     }
 }
 
-pub async fn category_list_handler(
-    State(state): State<AppState>,
-    Form(fandc): Form<FootprintAndCategoryForm>,
-) -> impl IntoResponse {
-    info!("Performing category list query");
+#[derive(Debug, Deserialize)]
+pub struct FootprintAndCategoryForm {
+    #[serde(default)]
+    footprint: Vec<String>,
+    #[serde(default)]
+    category: Vec<String>,
+}
 
-    let mut db_conn = match state.pool.acquire().await {
-        Ok(conn) => conn,
-        Err(e) => {
-            return handle_generic_inventory_error(e);
-        }
-    };
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct NewItemForm {
+    pub mpn: String,
+    pub category: String,
+    pub footprint: String,
+    pub value: String,
+    pub location: String,
+    pub quantity: String,
+    pub comments: String,
+    // (Optional so that imports without these columns keep working)
+    #[serde(default)]
+    pub voltage_rating: String,
+    #[serde(default)]
+    pub power_rating: String,
+    #[serde(default)]
+    pub unit_price: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StageForm {
+    amount: Option<String>,
+}
 
-    let mut query = QueryBuilder::new("SELECT DISTINCT category FROM inventory");
-    if fandc.footprint == NO_FOOTPRINT_STR {
-        query.push(" WHERE footprint IS NULL");
-    } else if fandc.footprint != ALL_FOOTPRINTS_STR {
-        query.push(" WHERE footprint = ");
-        query.push_bind(fandc.footprint);
+impl StageForm {
+    // Defaults to a single part if missing or invalid
+    fn amount(&self) -> i32 {
+        self.amount
+            .as_deref()
+            .and_then(|x| x.trim().parse::<i32>().ok())
+            .filter(|x| *x > 0)
+            .unwrap_or(1)
     }
+}
 
-    let results = match query
-        .build_query_scalar::<String>()
-        .fetch_all(db_conn.as_mut())
-        .await
-    {
-        Ok(results) => results,
-        Err(e) => {
-            return handle_generic_inventory_error(e);
-        }
-    };
+#[derive(Debug, Deserialize)]
+pub struct SetQuantityForm {
+    quantity: i64,
+    location: Option<String>,
+}
 
-    Html(response_filter_list(results, &fandc.category, ALL_CATEGORIES_STR).into_string())
+// Sets one field of every selected part to the same value
+#[derive(Debug, Deserialize)]
+pub struct BulkEditForm {
+    #[serde(default)]
+    ids: Vec<i32>,
+    field: String,
+    value: String,
 }
 
-pub async fn footprint_list_handler(
-    State(state): State<AppState>,
-    Form(fandc): Form<FootprintAndCategoryForm>,
-) -> impl IntoResponse {
-    info!("Performing footprint list query");
+// Fields which are missing or empty are left untouched
+#[derive(Debug, Deserialize)]
+pub struct EditItemForm {
+    mpn: Option<String>,
+    category: Option<String>,
+    footprint: Option<String>,
+    value: Option<String>,
+    voltage_rating: Option<String>,
+    power_rating: Option<String>,
+    location: Option<String>,
+    comments: Option<String>,
+    reorder_point: Option<String>,
+    unit_price: Option<String>,
+    // Comma separated
+    tags: Option<String>,
+    supplier: Option<String>,
+    supplier_url: Option<String>,
+    status: Option<String>,
+    qty_unit: Option<String>,
+    // As loaded in the form, scripts may leave it out to skip the check
+    version: Option<i32>,
+}
 
-    let mut db_conn = match state.pool.acquire().await {
-        Ok(conn) => conn,
-        Err(e) => {
-            return handle_generic_inventory_error(e);
+#[derive(Debug, Clone)]
+pub struct CategoryUnit {
+    unit: String,
+    use_si_prefix: bool,
+    // Largest first, all of SI_PREFIXES if None
+    prefixes: Option<Vec<(i32, &'static str)>>,
+}
+
+// Keyed by category name, categories without an entry have no unit
+pub type CategoryUnits = HashMap<String, CategoryUnit>;
+
+#[derive(Debug, Deserialize)]
+pub struct CategoryUnitForm {
+    category: String,
+    unit: String,
+    use_si_prefix: Option<String>,
+    si_prefixes: Option<String>,
+}
+
+use crate::{
+    auth::{CurrentUser, csrf_token},
+    history::{StockLogEntry, html_history_table, insert_log, query_history},
+    layout::{Theme, cookie, format_time, html_page},
+    state::{AppState, no_statement_timeout},
+};
+
+// (A 500, so that scripts and probes see the failure, the page still swaps it in)
+pub fn handle_generic_inventory_error<E: Display>(e: E) -> axum::response::Response {
+    error!("Error while processing inventory API call: {}", e);
+    let response = html! {
+        article {
+            "Error while processing, try again later."
         }
     };
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Html(response.into_string()),
+    )
+        .into_response()
+}
 
-    let mut query = QueryBuilder::new("SELECT DISTINCT COALESCE(footprint, '");
-    query.push(NO_FOOTPRINT_STR);
-    query.push("') FROM inventory");
+// (Postgres' name for the UNIQUE constraint on parts.mpn)
+const MPN_UNIQUE_CONSTRAINT: &str = "parts_mpn_key";
 
-    if fandc.category != "All Categories" {
-        query.push(" WHERE category = ");
-        query.push_bind(fandc.category);
-    }
+fn is_mpn_conflict(e: &sqlx::Error) -> bool {
+    e.as_database_error()
+        .is_some_and(|x| x.is_unique_violation() && x.constraint() == Some(MPN_UNIQUE_CONSTRAINT))
+}
 
-    let results = match query
-        .build_query_scalar::<String>()
-        .fetch_all(db_conn.as_mut())
+// Points to the part which already has the MPN, so that its stock is added to
+// instead of creating a duplicate. Shown in the given element
+async fn mpn_conflict_response(
+    mpn: &str,
+    target: &str,
+    db_conn: &mut PgConnection,
+) -> axum::response::Response {
+    let mut query = QueryBuilder::new("SELECT id, deleted_at IS NOT NULL FROM parts WHERE mpn = ");
+    query.push_bind(mpn);
+    let existing = match query
+        .build_query_as::<(i32, bool)>()
+        .fetch_optional(&mut *db_conn)
         .await
     {
-        Ok(results) => results,
-        Err(e) => {
-            return handle_generic_inventory_error(e);
+        Ok(existing) => existing,
+        Err(e) => return handle_generic_inventory_error(e),
+    };
+
+    let mut headers = HeaderMap::new();
+    headers.insert("HX-Retarget", target.parse().unwrap());
+    headers.insert("HX-Reswap", "innerHTML".parse().unwrap());
+    let response = html! {
+        p style="color: red;" {
+            "MPN " (mpn) " already exists"
+            @if let Some((id, deleted)) = existing {
+                " — "
+                a href={"/part/" (id)} { "part #" (id) }
+                @if deleted { " (deleted)" }
+            }
+            "."
         }
     };
+    (StatusCode::CONFLICT, headers, Html(response.into_string())).into_response()
+}
 
-    Html(response_filter_list(results, &fandc.footprint, ALL_FOOTPRINTS_STR).into_string())
+fn sort_error(msg: &str) -> axum::response::Response {
+    let response = html! {
+        p style="color: red;" { (msg) }
+    };
+    (
+        StatusCode::UNPROCESSABLE_ENTITY,
+        Html(response.into_string()),
+    )
+        .into_response()
 }
 
-pub async fn search_handler(
-    State(state): State<AppState>,
-    Form(search): Form<SearchForm>,
-) -> impl IntoResponse {
-    info!("Performing search query: {:?}", search);
+// Scripts may ask for JSON instead of the HTML fragments
+fn wants_json(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::ACCEPT)
+        .and_then(|x| x.to_str().ok())
+        .is_some_and(|x| x.contains("application/json"))
+}
 
-    let mut db_conn = match state.pool.acquire().await {
-        Ok(conn) => conn,
-        Err(e) => {
-            return handle_generic_inventory_error(e);
-        }
-    };
+fn pow10(exp: i32) -> Decimal {
+    if exp >= 0 {
+        Decimal::from(10i64.pow(exp as u32))
+    } else {
+        Decimal::new(1, (-exp) as u32)
+    }
+}
 
-    let results = match query_inventory(&search, &mut db_conn).await {
-        Ok(results) => results,
-        Err(e) => {
-            return handle_generic_inventory_error(e);
+// Shown after the value, largest first. Values below the smallest one are
+// still shown with it, e.g. 0.50 p
+const SI_PREFIXES: [(i32, &str); 8] = [
+    (9, "G"),
+    (6, "M"),
+    (3, "k"),
+    (0, " "),
+    (-3, "m"),
+    (-6, "µ"),
+    (-9, "n"),
+    (-12, "p"),
+];
+
+// A list of prefixes such as "k M G" (or "k,M,G"), with "-" for no prefix,
+// or None if empty (so that all are used)
+fn parse_prefixes(v: &str) -> Result<Option<Vec<(i32, &'static str)>>, String> {
+    let mut prefixes = vec![];
+    for symbol in v.split([' ', ',']).filter_map(non_empty) {
+        let prefix = match symbol {
+            "-" => " ",
+            "u" => "µ",
+            "K" => "k",
+            _ => symbol,
+        };
+        let Some(&entry) = SI_PREFIXES.iter().find(|(_, x)| *x == prefix) else {
+            return Err(format!("Unknown SI prefix {}", symbol));
+        };
+        if !prefixes.contains(&entry) {
+            prefixes.push(entry);
         }
+    }
+    prefixes.sort_by_key(|x| std::cmp::Reverse(x.0));
+
+    Ok((!prefixes.is_empty()).then_some(prefixes))
+}
+
+// (Largest first, parsing back to the same prefixes)
+fn prefixes_str(prefixes: &[(i32, &str)]) -> String {
+    prefixes
+        .iter()
+        .map(|(_, x)| if *x == " " { "-" } else { x })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn si_multiplier(prefix: char) -> Option<Decimal> {
+    match prefix {
+        'p' => Some(pow10(-12)),
+        'n' => Some(pow10(-9)),
+        'u' | 'µ' => Some(pow10(-6)),
+        'm' => Some(pow10(-3)),
+        'k' | 'K' => Some(pow10(3)),
+        'M' => Some(pow10(6)),
+        'G' => Some(pow10(9)),
+        _ => None,
+    }
+}
+
+// Accepts both plain (4.7) and scientific (4.7e-6) notation
+fn parse_decimal(v: &str) -> Option<Decimal> {
+    v.parse::<Decimal>()
+        .or_else(|_| Decimal::from_scientific(v))
+        .ok()
+}
+
+// Decimal can't overflow into infinity, but the product may not fit or be
+// rounded down to zero, both of which are rejected
+fn apply_multiplier(number: Decimal, mult: Decimal) -> Option<Decimal> {
+    let value = number.checked_mul(mult)?;
+    if value.is_zero() && !number.is_zero() {
+        None
+    } else {
+        Some(value)
+    }
+}
+
+// Parses RKM notation (4R7, 4K7, 1u5), where the multiplier sits in place of the decimal point
+fn parse_rkm_value(v: &str) -> Option<Decimal> {
+    let (idx, letter) = v.char_indices().find(|(_, x)| !x.is_ascii_digit())?;
+    if idx == 0 {
+        return None;
+    }
+
+    let mult = match letter {
+        'R' => Decimal::ONE,
+        _ => si_multiplier(letter)?,
     };
 
-    let response = html! {
-        table class="striped" {
-            (html_table_header(&search.sort))
-            @for result in &results {
-                (html_table_row(result))
-            }
-        }
+    let rest = &v[idx + letter.len_utf8()..];
+    let fraction_end = rest
+        .find(|x: char| !x.is_ascii_digit())
+        .unwrap_or(rest.len());
+    if fraction_end == 0 {
+        return None;
     }
-    .into_string();
 
-    Html(response)
+    let number = format!("{}.{}", &v[0..idx], &rest[0..fraction_end]);
+    apply_multiplier(parse_decimal(&number)?, mult)
 }
 
-async fn update_stage(id: i32, number: i32, db_conn: &mut PoolConnection<Postgres>) -> Option<i32> {
-    let mut query = QueryBuilder::new("UPDATE stock SET staged = LEAST(COALESCE(staged, 0) + ");
-    query.push_bind(number);
-    query.push(", quantity)");
-    query.push(" WHERE part_id = ");
-    query.push_bind(id);
-    query.push(" AND quantity IS NOT NULL");
-    query.push(" AND COALESCE(staged, 0) + ");
-    query.push_bind(number);
-    query.push(" >= 0");
-    query.push(" RETURNING staged");
-    match query
-        .build_query_scalar::<i32>()
-        .fetch_optional(db_conn.as_mut())
-        .await
-    {
-        Ok(v) => v,
-        Err(e) => {
-            let _ = handle_generic_inventory_error(e);
-            None
+pub fn parse_multiple_value(v: &str) -> Option<Decimal> {
+    let v = v.trim();
+    if let Some(value) = parse_rkm_value(v) {
+        return Some(value.normalize());
+    }
+
+    let number_end = v.rfind(|x: char| x.is_ascii_digit())?;
+    if number_end + 1 >= v.len() {
+        return parse_decimal(v).map(|x| x.normalize());
+    }
+
+    assert!(number_end + 1 < v.len());
+
+    let number_part = &v[0..number_end + 1];
+    let qty_part = v[number_end + 1..].trim();
+
+    let number = parse_decimal(number_part)?;
+
+    // The prefix may be followed by an unit (4.7uF, 10kΩ), which we ignore
+    let value = match qty_part.chars().next().and_then(si_multiplier) {
+        Some(mult) => apply_multiplier(number, mult)?,
+        None => number,
+    };
+
+    Some(value.normalize())
+}
+
+// So that the text is matched as it is by LIKE, where \ is the default escape
+fn escape_like(v: &str) -> String {
+    v.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+fn non_empty(v: &str) -> Option<&str> {
+    let v = v.trim();
+    if v.is_empty() { None } else { Some(v) }
+}
+
+// Trimmed and without duplicates, in the order given
+fn parse_tags(v: &str) -> Vec<String> {
+    let mut tags: Vec<String> = vec![];
+    for tag in v.split(',').filter_map(non_empty) {
+        if !tags.iter().any(|x| x == tag) {
+            tags.push(tag.to_string());
         }
     }
+    tags
+}
+
+// Only links which browsers open as a page are accepted
+fn valid_url(v: &str) -> bool {
+    reqwest::Url::parse(v).is_ok_and(|x| matches!(x.scheme(), "http" | "https"))
+}
+
+// Prices are plain decimals, without multipliers nor currency
+fn parse_price(v: &str) -> Option<Decimal> {
+    v.parse::<Decimal>().ok().filter(|x| !x.is_sign_negative())
 }
 
-pub async fn confirm_stage_handler(State(state): State<AppState>) -> impl IntoResponse {
-    info!("Confirming stage");
+// Values chosen in a multi-select, or None if it shouldn't filter at all
+fn selected_filter(values: &[String], no_filter: &str) -> Option<Vec<String>> {
+    let values: Vec<String> = values
+        .iter()
+        .filter_map(|x| non_empty(x))
+        .map(String::from)
+        .collect();
+
+    if values.is_empty() || values.iter().any(|x| x == no_filter) {
+        None
+    } else {
+        Some(values)
+    }
+}
+
+fn push_category_filter(query: &mut QueryBuilder<Postgres>, category: &[String]) {
+    if let Some(categories) = selected_filter(category, ALL_CATEGORIES_STR) {
+        // (Also matching variants which weren't normalized yet)
+        let categories: Vec<String> = categories
+            .iter()
+            .map(|x| normalize_name(x).to_lowercase())
+            .collect();
+        query.push(" AND LOWER(regexp_replace(TRIM(category), '\\s+', ' ', 'g')) = ANY(");
+        query.push_bind(categories);
+        query.push(")");
+    }
+}
+
+fn push_footprint_filter(query: &mut QueryBuilder<Postgres>, footprint: &[String]) {
+    if let Some(footprints) = selected_filter(footprint, ALL_FOOTPRINTS_STR) {
+        let no_footprint = footprints.iter().any(|x| x == NO_FOOTPRINT_STR);
+        query.push(" AND (footprint = ANY(");
+        query.push_bind(footprints);
+        query.push(")");
+        if no_footprint {
+            query.push(" OR footprint IS NULL");
+        }
+        query.push(")");
+    }
+}
+
+// Bounds which are missing or can't be parsed are ignored
+fn push_range_filter(
+    query: &mut QueryBuilder<Postgres>,
+    column: &'static str,
+    min: Option<&str>,
+    max: Option<&str>,
+) {
+    if let Some(min) = min.and_then(non_empty).and_then(parse_multiple_value) {
+        query.push(" AND ");
+        query.push(column);
+        query.push(" >= ");
+        query.push_bind(min);
+    }
+
+    if let Some(max) = max.and_then(non_empty).and_then(parse_multiple_value) {
+        query.push(" AND ");
+        query.push(column);
+        query.push(" <= ");
+        query.push_bind(max);
+    }
+}
+
+// Conditions for the parts matching the search, after a WHERE. Returns
+// whether the search term is used as a full-text query
+fn push_search_filter<'a>(query: &mut QueryBuilder<'a, Postgres>, search: &'a SearchForm) -> bool {
+    query.push("deleted_at IS NULL");
+    push_category_filter(query, &search.category);
+    push_footprint_filter(query, &search.footprint);
+
+    if search.in_stock.is_some() {
+        query.push(" AND quantity > 0");
+    }
+
+    if search.in_stage.is_some() {
+        query.push(" AND staged > 0");
+    }
+
+    // (Empty fields are stored as NULL)
+    if search.missing_mpn.is_some() {
+        query.push(" AND mpn IS NULL");
+    }
+
+    if search.missing_footprint.is_some() {
+        query.push(" AND footprint IS NULL");
+    }
+
+    if search.missing_value.is_some() {
+        query.push(" AND value IS NULL");
+    }
+
+    if search.has_comments.is_some() {
+        query.push(" AND comments IS NOT NULL");
+    }
+
+    // (Any of the part's locations, not only the one shown)
+    if let Some(location) = search.location.as_deref().and_then(non_empty) {
+        query.push(" AND EXISTS (SELECT 1 FROM stock ls");
+        query.push(" JOIN locations l ON ls.location_id = l.id");
+        query.push(" WHERE ls.part_id = inventory.id AND l.name LIKE ");
+        query.push_bind(escape_like(location));
+        query.push(" || '%')");
+    }
+
+    if let Some(tag) = search.tag.as_deref().and_then(non_empty) {
+        query.push(" AND tags @> ARRAY[");
+        query.push_bind(tag);
+        query.push("]");
+    }
+
+    if let Some(status) = search
+        .status
+        .as_deref()
+        .and_then(|x| x.parse::<PartStatus>().ok())
+    {
+        query.push(" AND status = ");
+        query.push_bind(status);
+    }
+
+    push_range_filter(query, "value", Some(&search.min_val), Some(&search.max_val));
+    push_range_filter(
+        query,
+        "voltage_rating",
+        search.min_voltage.as_deref(),
+        search.max_voltage.as_deref(),
+    );
+    push_range_filter(
+        query,
+        "power_rating",
+        search.min_power.as_deref(),
+        search.max_power.as_deref(),
+    );
+
+    // Very short terms are useless as a tsquery, so these fall back to ILIKE
+    let search_term = search.search.trim();
+    let search_len = search_term.chars().count();
+    let fulltext = search_len >= MIN_FULLTEXT_SEARCH_LEN;
+    if fulltext {
+        query.push(" AND (search_vector @@ plainto_tsquery('simple', ");
+        query.push_bind(search_term);
+        // (As a subquery, so that the index on the category names is used)
+        query.push(") OR category IN (SELECT name FROM categories");
+        query.push(" WHERE to_tsvector('simple', name) @@ plainto_tsquery('simple', ");
+        query.push_bind(search_term);
+        query.push(")))");
+    } else if search_len >= MIN_SEARCH_LEN {
+        query.push(" AND (mpn ILIKE ");
+        query.push_bind(format!("%{}%", search_term));
+        query.push(" OR category ILIKE ");
+        query.push_bind(format!("%{}%", search_term));
+        query.push(" OR comments ILIKE ");
+        query.push_bind(format!("%{}%", search_term));
+        query.push(")");
+    }
+
+    fulltext
+}
+
+// Set SQL_DEBUG (and RUST_LOG=info,station::inventory=debug) to log what the searches are
+// bound to, which the SQL alone doesn't show. (Searches can carry anything
+// typed into the page, so this stays off by default and below info)
+static SQL_DEBUG: LazyLock<bool> = LazyLock::new(|| dotenvy::var("SQL_DEBUG").is_ok());
+
+// The filters as push_search_filter resolves them, e.g. ignoring unparsable bounds
+fn log_search_filters(search: &SearchForm, fulltext: bool) {
+    let range = |min: Option<&str>, max: Option<&str>| {
+        (
+            min.and_then(non_empty).and_then(parse_multiple_value),
+            max.and_then(non_empty).and_then(parse_multiple_value),
+        )
+    };
+    let search_term = search.search.trim();
+    let search_kind = if fulltext {
+        "full-text"
+    } else if search_term.chars().count() >= MIN_SEARCH_LEN {
+        "ILIKE"
+    } else {
+        "ignored"
+    };
+
+    debug!(
+        "Search filters: categories {:?}, footprints {:?}, value {:?}, voltage {:?}, power {:?}, search {:?} ({}), from {:?}",
+        selected_filter(&search.category, ALL_CATEGORIES_STR),
+        selected_filter(&search.footprint, ALL_FOOTPRINTS_STR),
+        range(Some(&search.min_val), Some(&search.max_val)),
+        range(search.min_voltage.as_deref(), search.max_voltage.as_deref()),
+        range(search.min_power.as_deref(), search.max_power.as_deref()),
+        search_term,
+        search_kind,
+        search
+    );
+}
+
+// If not paginated, all matching rows are returned
+async fn query_inventory(
+    search: &SearchForm,
+    order: (SortColumn, SortDir),
+    paginate: bool,
+    db_conn: &mut PoolConnection<Postgres>,
+) -> Result<Vec<InventoryItem>, sqlx::Error> {
+    inventory_query(search, order, paginate)
+        .build_query_as::<InventoryItem>()
+        .fetch_all(db_conn.as_mut())
+        .await
+}
+
+// (Separate from query_inventory, so that exports can stream the rows)
+fn inventory_query(
+    search: &SearchForm,
+    (sort, dir): (SortColumn, SortDir),
+    paginate: bool,
+) -> QueryBuilder<'_, Postgres> {
+    let mut query = QueryBuilder::new("SELECT * FROM inventory WHERE ");
+    let fulltext = push_search_filter(&mut query, search);
+    let search_term = search.search.trim();
+
+    query.push(" ORDER BY ");
+    if fulltext {
+        query.push("ts_rank(search_vector, plainto_tsquery('simple', ");
+        query.push_bind(search_term);
+        query.push(")) DESC, ");
+    }
+
+    // (Both come from a fixed set, so these are safe to push as they are)
+    query.push(sort.as_str());
+    query.push(dir.as_sql());
+    // (Ties are broken by id, so that pages don't skip or repeat parts. In the
+    // same direction, so parts imported together still show newest first)
+    query.push(", id");
+    query.push(dir.as_sql());
+
+    if paginate {
+        query.push(" LIMIT ");
+        query.push_bind(search.page_size() as i64);
+        query.push(" OFFSET ");
+        query.push_bind(search.page() as i64 * search.page_size() as i64);
+    }
+
+    let sql = query.sql();
+
+    info!("Database query: {}", sql);
+    if *SQL_DEBUG {
+        log_search_filters(search, fulltext);
+    }
+
+    query
+}
+
+// All parts matching the search, regardless of the page
+async fn count_inventory(search: &SearchForm, pool: &Pool<Postgres>) -> Result<i64, sqlx::Error> {
+    let mut query = QueryBuilder::new("SELECT COUNT(*) FROM inventory WHERE ");
+    push_search_filter(&mut query, search);
+
+    query.build_query_scalar::<i64>().fetch_one(pool).await
+}
+
+// Worth of the stock matching the search, parts without a price are left out
+async fn query_valuation(
+    search: &SearchForm,
+    db_conn: &mut PoolConnection<Postgres>,
+) -> Result<Decimal, sqlx::Error> {
+    let mut query =
+        QueryBuilder::new("SELECT COALESCE(SUM(quantity * unit_price), 0) FROM inventory WHERE ");
+    push_search_filter(&mut query, search);
+
+    query
+        .build_query_scalar::<Decimal>()
+        .fetch_one(db_conn.as_mut())
+        .await
+}
+
+fn format_price(price: Decimal) -> String {
+    format!("{:.2}", price)
+}
+
+fn format_mult_value(value: Decimal) -> String {
+    format_prefixed_value(value, &SI_PREFIXES)
+}
+
+// Picks the largest of the prefixes (largest first) the value reaches
+fn format_prefixed_value(value: Decimal, prefixes: &[(i32, &str)]) -> String {
+    // (Decimals are exact, so tiny non-zero values still get the smallest prefix)
+    if value.is_zero() {
+        return String::from("0  ");
+    }
+
+    // (The prefixes are picked by magnitude, e.g. for negative offsets)
+    if value < Decimal::ZERO {
+        return format!("-{}", format_prefixed_value(-value, prefixes));
+    }
+
+    let (exp, prefix) = prefixes
+        .iter()
+        .find(|(exp, _)| value >= pow10(*exp))
+        .or(prefixes.last())
+        .unwrap_or(&(0, " "));
+    format!("{:.2} {}", value / pow10(*exp), prefix)
+}
+
+// Returns the unit of the category, and the SI prefixes it uses, if any
+// (Categories are matched ignoring case, like when inserting them)
+fn category_unit<'a>(
+    units: &'a CategoryUnits,
+    category: &str,
+) -> (&'a str, Option<&'a [(i32, &'static str)]>) {
+    let unit = units.get(category).or_else(|| {
+        units
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(category))
+            .map(|(_, unit)| unit)
+    });
+    match unit {
+        Some(unit) if unit.use_si_prefix => (
+            &unit.unit,
+            Some(unit.prefixes.as_deref().unwrap_or(&SI_PREFIXES)),
+        ),
+        Some(unit) => (&unit.unit, None),
+        None => ("", None),
+    }
+}
+
+fn format_value(units: &CategoryUnits, category: &str, value: Decimal) -> String {
+    let (unit, prefixes) = category_unit(units, category);
+    let value = match prefixes {
+        Some(prefixes) => format_prefixed_value(value, prefixes),
+        None => format!("{:.2}  ", value),
+    };
+
+    format!("{}{}", value, unit)
+}
+
+// Ratings always have the same unit, regardless of category
+fn format_rating(value: Decimal, unit: &str) -> String {
+    format!("{}{}", format_mult_value(value), unit)
+}
+
+pub async fn load_category_units(pool: &Pool<Postgres>) -> Result<CategoryUnits, sqlx::Error> {
+    let rows =
+        QueryBuilder::new("SELECT category, unit, use_si_prefix, si_prefixes FROM category_unit")
+            .build_query_as::<(String, String, bool, Option<String>)>()
+            .fetch_all(pool)
+            .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|(category, unit, use_si_prefix, si_prefixes)| {
+            // (May have been set by hand, in which case all prefixes are used)
+            let prefixes = match parse_prefixes(si_prefixes.as_deref().unwrap_or("")) {
+                Ok(prefixes) => prefixes,
+                Err(e) => {
+                    warn!("Ignoring the SI prefixes of category {}: {}", category, e);
+                    None
+                }
+            };
+            (
+                category,
+                CategoryUnit {
+                    unit,
+                    use_si_prefix,
+                    prefixes,
+                },
+            )
+        })
+        .collect())
+}
+
+async fn set_category_unit(
+    category: &str,
+    unit: &CategoryUnit,
+    db_conn: &mut PgConnection,
+) -> Result<(), sqlx::Error> {
+    let mut query = QueryBuilder::new(
+        "INSERT INTO category_unit (category, unit, use_si_prefix, si_prefixes) ",
+    );
+    query.push_values([unit], |mut row, unit| {
+        row.push_bind(category)
+            .push_bind(&unit.unit)
+            .push_bind(unit.use_si_prefix)
+            .push_bind(unit.prefixes.as_deref().map(prefixes_str));
+    });
+    query.push(
+        " ON CONFLICT (category) DO UPDATE SET unit = EXCLUDED.unit, use_si_prefix = EXCLUDED.use_si_prefix, si_prefixes = EXCLUDED.si_prefixes",
+    );
+    query.build().execute(&mut *db_conn).await?;
+
+    Ok(())
+}
+
+pub async fn category_unit_handler(
+    State(state): State<AppState>,
+    Form(form): Form<CategoryUnitForm>,
+) -> impl IntoResponse {
+    info!("Setting category unit: {:?}", form);
+
+    let Some(category) = non_empty(&form.category) else {
+        let response = html! {
+            article { "A category is required." }
+        };
+        return (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Html(response.into_string()),
+        )
+            .into_response();
+    };
+
+    let prefixes = match parse_prefixes(form.si_prefixes.as_deref().unwrap_or("")) {
+        Ok(prefixes) => prefixes,
+        Err(msg) => {
+            let response = html! {
+                article { (msg) ", use p, n, u, m, k, M, G or - (no prefix)." }
+            };
+            return (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Html(response.into_string()),
+            )
+                .into_response();
+        }
+    };
+
+    let unit = CategoryUnit {
+        unit: form.unit.trim().to_string(),
+        use_si_prefix: form.use_si_prefix.is_some(),
+        prefixes,
+    };
+
+    let mut db_conn = match state.pool.acquire().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            return handle_generic_inventory_error(e);
+        }
+    };
+
+    if let Err(e) = set_category_unit(category, &unit, &mut db_conn).await {
+        return handle_generic_inventory_error(e);
+    }
+
+    let response = html! {
+        article {
+            "Unit of " (category) " set to \"" (unit.unit) "\""
+            @if let (true, Some(prefixes)) = (unit.use_si_prefix, &unit.prefixes) {
+                ", with the SI prefixes " (prefixes_str(prefixes)) "."
+            } @else if unit.use_si_prefix {
+                ", with SI prefixes."
+            } @else {
+                "."
+            }
+        }
+    };
+
+    state
+        .category_units
+        .write()
+        .unwrap()
+        .insert(category.to_string(), unit);
+
+    state.notify_change(None);
+    let mut headers = HeaderMap::new();
+    headers.insert("HX-Trigger", "inventoryUpdated".parse().unwrap());
+    (headers, Html(response.into_string())).into_response()
+}
+
+// (Short, as changes made to the DB by hand aren't noticed)
+const LIST_CACHE_TTL: Duration = Duration::from_secs(30);
+// Refreshes are at least this far apart, so that an import or a burst of edits
+// only refreshes a couple of times
+const LIST_REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+
+// (Without the statement timeout, as it rewrites the whole view)
+async fn refresh_lists(pool: &Pool<Postgres>) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    no_statement_timeout(&mut tx).await?;
+    QueryBuilder::new("REFRESH MATERIALIZED VIEW CONCURRENTLY category_footprints")
+        .build()
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await
+}
+
+// Results of the category and footprint lists, keyed by the list and its filter.
+// These are queried from category_footprints, so the cache is cleared after
+// that is refreshed, which happens whenever parts change through the API
+#[derive(Default)]
+pub struct ListCache {
+    entries: RwLock<HashMap<String, (Instant, Vec<String>)>>,
+    refresh: Notify,
+}
+
+impl ListCache {
+    // Call after changing the category, footprint or deletion of parts
+    pub fn invalidate(&self) {
+        // (Stores a single permit, so any number of changes cause one refresh)
+        self.refresh.notify_one();
+    }
+
+    pub async fn keep_refreshed(self: Arc<Self>, pool: Pool<Postgres>) {
+        loop {
+            self.refresh.notified().await;
+
+            if let Err(e) = refresh_lists(&pool).await {
+                error!(
+                    "Error while refreshing the category and footprint lists: {}",
+                    e
+                );
+            }
+            self.clear();
+
+            tokio::time::sleep(LIST_REFRESH_INTERVAL).await;
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<Vec<String>> {
+        let entries = self.entries.read().unwrap();
+        let (time, results) = entries.get(key)?;
+        (time.elapsed() < LIST_CACHE_TTL).then(|| results.clone())
+    }
+
+    fn insert(&self, key: String, results: Vec<String>) {
+        let mut entries = self.entries.write().unwrap();
+        entries.retain(|_, (time, _)| time.elapsed() < LIST_CACHE_TTL);
+        entries.insert(key, (Instant::now(), results));
+    }
+
+    fn clear(&self) {
+        self.entries.write().unwrap().clear();
+    }
+}
+
+async fn query_list_cached(
+    state: &AppState,
+    key: String,
+    mut query: QueryBuilder<'_, Postgres>,
+) -> Result<Vec<String>, sqlx::Error> {
+    if let Some(results) = state.list_cache.get(&key) {
+        return Ok(results);
+    }
+
+    let results = query
+        .build_query_scalar::<String>()
+        .fetch_all(&state.pool)
+        .await?;
+    state.list_cache.insert(key, results.clone());
+
+    Ok(results)
+}
+
+fn response_filter_list(filter_results: Vec<String>, chosen: &[String], no_filter: &str) -> Markup {
+    let mut filter_results = filter_results;
+    filter_results.sort();
+    // The chosen values are kept selected, and listed at the top
+    let (chosen_elems, other_elems): (Vec<String>, Vec<String>) = filter_results
+        .into_iter()
+        .partition(|x| chosen.contains(x) && x != no_filter);
+
+    html! {
+        @for elem in &chosen_elems {
+            option selected {
+                (elem)
+            }
+        }
+        option selected[chosen_elems.is_empty()] {
+            (no_filter)
+        }
+        @for elem in &other_elems {
+            option {
+                (elem)
+            }
+        }
+    }
+}
+
+pub async fn category_list_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    MultiForm(fandc): MultiForm<FootprintAndCategoryForm>,
+) -> impl IntoResponse {
+    info!("Performing category list query");
+
+    let mut query =
+        QueryBuilder::new("SELECT DISTINCT category FROM category_footprints WHERE TRUE");
+    push_footprint_filter(&mut query, &fandc.footprint);
+
+    let key = format!("categories {:?}", fandc.footprint);
+    let results = match query_list_cached(&state, key, query).await {
+        Ok(results) => results,
+        Err(e) => {
+            return handle_generic_inventory_error(e);
+        }
+    };
+
+    if wants_json(&headers) {
+        return Json(results).into_response();
+    }
+
+    Html(response_filter_list(results, &fandc.category, ALL_CATEGORIES_STR).into_string())
+        .into_response()
+}
+
+pub async fn footprint_list_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    MultiForm(fandc): MultiForm<FootprintAndCategoryForm>,
+) -> impl IntoResponse {
+    info!("Performing footprint list query");
+
+    let mut query = QueryBuilder::new("SELECT DISTINCT COALESCE(footprint, '");
+    query.push(NO_FOOTPRINT_STR);
+    query.push("') FROM category_footprints WHERE TRUE");
+    push_category_filter(&mut query, &fandc.category);
+
+    let key = format!("footprints {:?}", fandc.category);
+    let results = match query_list_cached(&state, key, query).await {
+        Ok(results) => results,
+        Err(e) => {
+            return handle_generic_inventory_error(e);
+        }
+    };
+
+    if wants_json(&headers) {
+        return Json(results).into_response();
+    }
+
+    Html(response_filter_list(results, &fandc.footprint, ALL_FOOTPRINTS_STR).into_string())
+        .into_response()
+}
+
+pub async fn search_handler(
+    State(state): State<AppState>,
+    columns: Columns,
+    headers: HeaderMap,
+    MultiForm(search): MultiForm<SearchForm>,
+) -> impl IntoResponse {
+    info!("Performing search query: {:?}", search);
+
+    let order = match search.sort_order() {
+        Ok(order) => order,
+        Err(msg) => return sort_error(&msg),
+    };
+
+    let mut db_conn = match state.pool.acquire().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            return handle_generic_inventory_error(e);
+        }
+    };
+
+    // (The count takes another connection, so that both run at the same time)
+    let (results, total) = tokio::join!(
+        query_inventory(&search, order, true, &mut db_conn),
+        count_inventory(&search, &state.pool)
+    );
+    let results = match results {
+        Ok(results) => results,
+        Err(e) => {
+            return handle_generic_inventory_error(e);
+        }
+    };
+    let total = match total {
+        Ok(total) => total,
+        Err(e) => {
+            return handle_generic_inventory_error(e);
+        }
+    };
+
+    if wants_json(&headers) {
+        let mut headers = HeaderMap::new();
+        headers.insert("X-Total-Count", total.into());
+        return (headers, Json(results)).into_response();
+    }
+
+    let units = state.category_units();
+    let shown = search.page() as usize * search.page_size() as usize + results.len();
+
+    // Later pages are only requested by the row at the end of the previous
+    // one, which they replace, so they don't repeat the table around them
+    if search.page() > 0 {
+        let response = html! {
+            @for result in &results {
+                (html_table_row(result, &units, &columns))
+            }
+            (html_more_row(&search, results.len()))
+            (html_results_count(shown, total, true))
+        }
+        .into_string();
+        return Html(response).into_response();
+    }
+
+    let response = html! {
+        table class="striped" {
+            caption {
+                (html_results_count(shown, total, false))
+            }
+            (html_table_header(order, &columns))
+            tbody id="results-body" {
+                @for result in &results {
+                    (html_table_row(result, &units, &columns))
+                }
+                (html_more_row(&search, results.len()))
+            }
+        }
+    }
+    .into_string();
+
+    Html(response).into_response()
+}
+
+// Every tag in use, for autocompletion
+pub async fn tag_list_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    info!("Performing tag list query");
+
+    let mut db_conn = match state.pool.acquire().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            return handle_generic_inventory_error(e);
+        }
+    };
+
+    let query = QueryBuilder::new(
+        "SELECT DISTINCT UNNEST(tags) AS tag FROM parts WHERE deleted_at IS NULL ORDER BY tag",
+    )
+    .build_query_scalar::<String>()
+    .fetch_all(db_conn.as_mut())
+    .await;
+
+    let results = match query {
+        Ok(results) => results,
+        Err(e) => {
+            return handle_generic_inventory_error(e);
+        }
+    };
+
+    if wants_json(&headers) {
+        return Json(results).into_response();
+    }
+
+    let response = html! {
+        @for tag in &results {
+            option value=(tag) {}
+        }
+    };
+    Html(response.into_string()).into_response()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SuggestForm {
+    q: String,
+}
+
+const MAX_SUGGESTIONS: i64 = 10;
+
+// MPNs starting with what is typed in the search box, for its datalist
+pub async fn suggest_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Form(form): Form<SuggestForm>,
+) -> impl IntoResponse {
+    let prefix = form.q.trim();
+    info!("Performing MPN suggestion query: {:?}", prefix);
+
+    // (Like the search itself, as these would suggest most parts)
+    if prefix.chars().count() < MIN_SEARCH_LEN {
+        return if wants_json(&headers) {
+            Json(Vec::<String>::new()).into_response()
+        } else {
+            Html(String::new()).into_response()
+        };
+    }
+
+    let mut db_conn = match state.pool.acquire().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            return handle_generic_inventory_error(e);
+        }
+    };
+
+    // (LOWER on both sides, so that idx_parts_mpn_lower is used, and the
+    // wildcards escaped, as MPNs may well contain '_')
+    let pattern = escape_like(&prefix.to_lowercase());
+    let mut query = QueryBuilder::new(
+        "SELECT DISTINCT mpn FROM parts WHERE deleted_at IS NULL AND LOWER(mpn) LIKE ",
+    );
+    query.push_bind(pattern);
+    query.push(" || '%' ORDER BY mpn LIMIT ");
+    query.push_bind(MAX_SUGGESTIONS);
+
+    let results = match query
+        .build_query_scalar::<String>()
+        .fetch_all(db_conn.as_mut())
+        .await
+    {
+        Ok(results) => results,
+        Err(e) => {
+            return handle_generic_inventory_error(e);
+        }
+    };
+
+    if wants_json(&headers) {
+        return Json(results).into_response();
+    }
+
+    let response = html! {
+        @for mpn in &results {
+            option value=(mpn) {}
+        }
+    };
+    Html(response.into_string()).into_response()
+}
+
+#[derive(Serialize)]
+struct Valuation {
+    value: Decimal,
+}
+
+// Total worth of the parts matching the search, e.g. of a single category
+pub async fn valuation_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    MultiForm(search): MultiForm<SearchForm>,
+) -> impl IntoResponse {
+    info!("Performing valuation query: {:?}", search);
+
+    let mut db_conn = match state.pool.acquire().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            return handle_generic_inventory_error(e);
+        }
+    };
+
+    let value = match query_valuation(&search, &mut db_conn).await {
+        Ok(value) => value,
+        Err(e) => {
+            return handle_generic_inventory_error(e);
+        }
+    };
+
+    if wants_json(&headers) {
+        return Json(Valuation { value }).into_response();
+    }
+
+    Html(format!("Stock value: {}", format_price(value))).into_response()
+}
+
+const CSV_COLUMNS: [&str; 13] = [
+    "id",
+    "mpn",
+    "category",
+    "footprint",
+    "value",
+    "value_formatted",
+    "voltage_rating",
+    "power_rating",
+    "location",
+    "quantity",
+    "staged",
+    "comments",
+    "unit_price",
+];
+
+// Rows are sent once this much is written, rather than one by one
+const CSV_CHUNK_SIZE: usize = 64 * 1024;
+
+fn write_csv_row(
+    writer: &mut csv::Writer<Vec<u8>>,
+    result: &InventoryItem,
+    units: &CategoryUnits,
+) -> Result<(), csv::Error> {
+    writer.write_record([
+        result.id.to_string(),
+        result.mpn.clone().unwrap_or_default(),
+        result.category.clone(),
+        result.footprint.clone().unwrap_or_default(),
+        result.value.map(|x| x.to_string()).unwrap_or_default(),
+        result
+            .value
+            .map(|x| format_value(units, &result.category, x).trim().to_string())
+            .unwrap_or_default(),
+        result
+            .voltage_rating
+            .map(|x| x.to_string())
+            .unwrap_or_default(),
+        result
+            .power_rating
+            .map(|x| x.to_string())
+            .unwrap_or_default(),
+        result.location.clone().unwrap_or_default(),
+        result.quantity.map(|x| x.to_string()).unwrap_or_default(),
+        result.staged.map(|x| x.to_string()).unwrap_or_default(),
+        result.comments.clone().unwrap_or_default(),
+        result.unit_price.map(|x| x.to_string()).unwrap_or_default(),
+    ])
+}
+
+// (Aborts the download, so a partial file isn't taken for all)
+async fn abort_csv(e: sqlx::Error, sender: &mpsc::Sender<Result<Vec<u8>, std::io::Error>>) {
+    error!("Error while exporting inventory: {}", e);
+    let _ = sender.send(Err(std::io::Error::other(e))).await;
+}
+
+// Writes the rows as they come from the database, in chunks, until they run
+// out or the receiver is dropped (e.g. as the client went away, which then
+// returns the connection to the pool)
+async fn stream_csv(
+    search: SearchForm,
+    order: (SortColumn, SortDir),
+    units: CategoryUnits,
+    mut db_conn: PoolConnection<Postgres>,
+    sender: mpsc::Sender<Result<Vec<u8>, std::io::Error>>,
+) {
+    let mut writer = csv::Writer::from_writer(vec![]);
+    // (Writing to a Vec can't fail)
+    writer.write_record(CSV_COLUMNS).unwrap();
+
+    // (Without the statement timeout, as it spans the whole download)
+    let mut tx = match db_conn.begin().await {
+        Ok(mut tx) => match no_statement_timeout(&mut tx).await {
+            Ok(()) => tx,
+            Err(e) => return abort_csv(e, &sender).await,
+        },
+        Err(e) => return abort_csv(e, &sender).await,
+    };
+
+    let mut query = inventory_query(&search, order, false);
+    let mut rows = query.build_query_as::<InventoryItem>().fetch(&mut *tx);
+
+    while let Some(row) = rows.next().await {
+        let result = match row {
+            Ok(result) => result,
+            Err(e) => return abort_csv(e, &sender).await,
+        };
+        write_csv_row(&mut writer, &result, &units).unwrap();
+
+        // (The writer buffers a bit before writing to the Vec, which is fine)
+        if writer.get_ref().len() >= CSV_CHUNK_SIZE {
+            let full = std::mem::replace(&mut writer, csv::Writer::from_writer(vec![]));
+            if sender.send(Ok(full.into_inner().unwrap())).await.is_err() {
+                info!("Inventory export cancelled");
+                return;
+            }
+        }
+    }
+
+    let _ = sender.send(Ok(writer.into_inner().unwrap())).await;
+}
+
+pub async fn export_csv_handler(
+    State(state): State<AppState>,
+    MultiForm(search): MultiForm<SearchForm>,
+) -> impl IntoResponse {
+    info!("Exporting search query to CSV: {:?}", search);
+
+    let order = match search.sort_order() {
+        Ok(order) => order,
+        Err(msg) => return sort_error(&msg),
+    };
+
+    let db_conn = match state.pool.acquire().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            return handle_generic_inventory_error(e);
+        }
+    };
+
+    // (A few chunks are buffered, so the query isn't held up by each send)
+    let (sender, receiver) = mpsc::channel(4);
+    let units = state.category_units().clone();
+    tokio::spawn(stream_csv(search, order, units, db_conn, sender));
+
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CONTENT_TYPE, "text/csv".parse().unwrap());
+    headers.insert(
+        header::CONTENT_DISPOSITION,
+        "attachment; filename=\"inventory.csv\"".parse().unwrap(),
+    );
+    (headers, Body::from_stream(ReceiverStream::new(receiver))).into_response()
+}
+
+// Either the parts picked in the table, or otherwise the ones matching the search
+#[derive(Debug, Deserialize)]
+pub struct KicadExportForm {
+    #[serde(default)]
+    ids: Vec<i32>,
+}
+
+// As in KiCad's own BOM export, with "~" standing for an empty field
+const KICAD_CSV_COLUMNS: [&str; 4] = ["Value", "Footprint", "MPN", "Datasheet"];
+
+// Parts looked up online keep the datasheet in their comments, see lookup.rs
+fn datasheet_url(comments: &str) -> Option<&str> {
+    let (_, url) = comments.split_once("(datasheet: ")?;
+    url.split(')').next().and_then(non_empty)
+}
+
+fn kicad_csv(results: &[InventoryItem], units: &CategoryUnits) -> Result<Vec<u8>, csv::Error> {
+    let field = |x: Option<String>| x.unwrap_or_else(|| String::from("~"));
+    let mut writer = csv::Writer::from_writer(vec![]);
+    writer.write_record(KICAD_CSV_COLUMNS)?;
+    for result in results {
+        // (Parts without a value, like ICs, go by their part number in KiCad)
+        let value = match result.value {
+            Some(value) => Some(
+                format_value(units, &result.category, value)
+                    .trim()
+                    .to_string(),
+            ),
+            None => result.mpn.clone(),
+        };
+        writer.write_record([
+            field(value),
+            field(result.footprint.clone()),
+            field(result.mpn.clone()),
+            field(
+                result
+                    .comments
+                    .as_deref()
+                    .and_then(datasheet_url)
+                    .map(String::from),
+            ),
+        ])?;
+    }
+
+    writer.into_inner().map_err(|e| e.into_error().into())
+}
+
+pub async fn export_kicad_handler(
+    State(state): State<AppState>,
+    RawQuery(query): RawQuery,
+) -> impl IntoResponse {
+    let query = query.unwrap_or_default();
+    info!("Exporting to KiCad CSV: {}", query);
+
+    let form = match serde_html_form::from_str::<KicadExportForm>(&query) {
+        Ok(form) => form,
+        Err(e) => return sort_error(&format!("Invalid part ids: {}", e)),
+    };
+
+    let mut db_conn = match state.pool.acquire().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            return handle_generic_inventory_error(e);
+        }
+    };
+
+    let results = if form.ids.is_empty() {
+        let search = match serde_html_form::from_str::<SearchForm>(&query) {
+            Ok(search) => search,
+            Err(e) => return sort_error(&format!("Invalid search: {}", e)),
+        };
+        let order = match search.sort_order() {
+            Ok(order) => order,
+            Err(msg) => return sort_error(&msg),
+        };
+        query_inventory(&search, order, false, &mut db_conn).await
+    } else {
+        query_items(&form.ids, &mut db_conn).await
+    };
+    let results = match results {
+        Ok(results) => results,
+        Err(e) => {
+            return handle_generic_inventory_error(e);
+        }
+    };
+
+    let body = match kicad_csv(&results, &state.category_units()) {
+        Ok(body) => body,
+        Err(e) => {
+            return handle_generic_inventory_error(e);
+        }
+    };
+
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CONTENT_TYPE, "text/csv".parse().unwrap());
+    headers.insert(
+        header::CONTENT_DISPOSITION,
+        "attachment; filename=\"kicad-bom.csv\"".parse().unwrap(),
+    );
+    (headers, body).into_response()
+}
+
+pub async fn reorder_page(
+    State(state): State<AppState>,
+    session: Session,
+    columns: Columns,
+    theme: Theme,
+) -> impl IntoResponse {
+    info!("Performing reorder query");
+
+    let csrf_token = match csrf_token(&session).await {
+        Ok(token) => token,
+        Err(e) => return handle_generic_inventory_error(e),
+    };
+
+    let mut db_conn = match state.pool.acquire().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            return handle_generic_inventory_error(e);
+        }
+    };
+
+    // (Parts without a reorder point are never listed, as the comparison is NULL)
+    let mut query = QueryBuilder::new("SELECT * FROM inventory WHERE deleted_at IS NULL");
+    // (What's available, see InventoryItem::available)
+    query.push(" AND COALESCE(quantity, 0) - COALESCE(staged, 0) - COALESCE(reserved, 0)");
+    query.push(" <= reorder_point");
+    // (Grouped by supplier, so that each group is one order)
+    query.push(" ORDER BY supplier NULLS LAST, reorder_point -");
+    query.push(" (COALESCE(quantity, 0) - COALESCE(staged, 0) - COALESCE(reserved, 0)) DESC, id");
+
+    let results = match query
+        .build_query_as::<InventoryItem>()
+        .fetch_all(db_conn.as_mut())
+        .await
+    {
+        Ok(results) => results,
+        Err(e) => {
+            return handle_generic_inventory_error(e);
+        }
+    };
+
+    let mut groups: Vec<(Option<&str>, Vec<&InventoryItem>)> = vec![];
+    for result in &results {
+        let supplier = result.supplier.as_deref();
+        match groups.last_mut() {
+            Some((last, items)) if *last == supplier => items.push(result),
+            _ => groups.push((supplier, vec![result])),
+        }
+    }
+
+    let units = state.category_units();
+    let content = html! {
+        @if results.is_empty() {
+            p { "Nothing to reorder." }
+        }
+        @for (supplier, items) in &groups {
+            h4 { (supplier.unwrap_or("No supplier")) }
+            div class="overflow-auto" {
+                table class="striped" {
+                    (html_static_table_header(&columns))
+                    tbody {
+                        @for result in items {
+                            (html_table_row(result, &units, &columns))
+                        }
+                    }
+                }
+            }
+        }
+    };
+
+    Html(html_page("Reorder", &csrf_token, theme, content).into_string()).into_response()
+}
+
+// Modules of empty space around the QR code, so that scanners can find it
+const LABEL_QUIET_ZONE: usize = 2;
+
+// Label for a drawer, with a QR code of the part id (which the scan endpoint
+// understands) next to the MPN and value. Printed at 60 x 20 mm
+fn html_label(
+    item: &InventoryItem,
+    units: &CategoryUnits,
+) -> Result<Markup, qrcode::types::QrError> {
+    let code = QrCode::new(item.id.to_string())?;
+    let width = code.width();
+
+    let mut path = String::new();
+    for (i, color) in code.to_colors().iter().enumerate() {
+        if *color == Color::Dark {
+            let x = i % width + LABEL_QUIET_ZONE;
+            let y = i / width + LABEL_QUIET_ZONE;
+            write!(path, "M{},{}h1v1h-1z", x, y).unwrap();
+        }
+    }
+
+    // (Everything is measured in QR modules, the text goes to the right of the code)
+    let size = width + 2 * LABEL_QUIET_ZONE;
+    let line = size as f64 / 5.0;
+    let text_x = size;
+    let value = item
+        .value
+        .map(|x| format_value(units, &item.category, x).trim().to_string());
+    let details = match &item.footprint {
+        Some(footprint) => format!("{} {}", item.category, footprint),
+        None => item.category.clone(),
+    };
+
+    Ok(html! {
+        svg
+        xmlns="http://www.w3.org/2000/svg"
+        width="60mm"
+        height="20mm"
+        viewBox={"0 0 " (size * 3) " " (size)} {
+            rect width="100%" height="100%" fill="white" {}
+            path d=(path) fill="black" {}
+            g font-family="monospace" font-size=(line) fill="black" {
+                text x=(text_x) y=(line * 1.5) font-weight="bold" {
+                    (item.mpn.as_deref().unwrap_or("—"))
+                }
+                text x=(text_x) y=(line * 2.7) { (details) }
+                @if let Some(value) = value {
+                    text x=(text_x) y=(line * 3.9) { (value) }
+                }
+                text x=(text_x) y=(line * 4.6) font-size=(line * 0.6) { "#" (item.id) }
+            }
+        }
+    })
+}
+
+// (The id is followed by .svg, so that the label can be saved as a file)
+pub async fn label_handler(
+    State(state): State<AppState>,
+    Path(file): Path<String>,
+) -> impl IntoResponse {
+    let Some(id) = file
+        .strip_suffix(".svg")
+        .and_then(|x| x.parse::<i32>().ok())
+    else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    info!("Generating label for component {}", id);
+
+    let mut db_conn = match state.pool.acquire().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            return handle_generic_inventory_error(e);
+        }
+    };
+
+    let item = match query_item(id, &mut db_conn).await {
+        Ok(Some(item)) => item,
+        Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+        Err(e) => return handle_generic_inventory_error(e),
+    };
+
+    match html_label(&item, &state.category_units()) {
+        Ok(label) => (
+            [(header::CONTENT_TYPE, "image/svg+xml")],
+            label.into_string(),
+        )
+            .into_response(),
+        Err(e) => handle_generic_inventory_error(e),
+    }
+}
+
+// Sheet with the labels of every part matching the search, to print in one go
+pub async fn labels_print_page(
+    State(state): State<AppState>,
+    session: Session,
+    theme: Theme,
+    MultiForm(search): MultiForm<SearchForm>,
+) -> impl IntoResponse {
+    info!("Generating label sheet: {:?}", search);
+
+    let csrf_token = match csrf_token(&session).await {
+        Ok(token) => token,
+        Err(e) => return handle_generic_inventory_error(e),
+    };
+
+    let order = match search.sort_order() {
+        Ok(order) => order,
+        Err(msg) => return sort_error(&msg),
+    };
+
+    let mut db_conn = match state.pool.acquire().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            return handle_generic_inventory_error(e);
+        }
+    };
+
+    let results = match query_inventory(&search, order, false, &mut db_conn).await {
+        Ok(results) => results,
+        Err(e) => {
+            return handle_generic_inventory_error(e);
+        }
+    };
+
+    let labels = {
+        let units = state.category_units();
+        match results
+            .iter()
+            .map(|x| html_label(x, &units))
+            .collect::<Result<Vec<Markup>, _>>()
+        {
+            Ok(labels) => labels,
+            Err(e) => {
+                return handle_generic_inventory_error(e);
+            }
+        }
+    };
+
+    let content = html! {
+        style {
+            ".label-sheet { display: flex; flex-wrap: wrap; gap: 2mm; }"
+            ".label-sheet svg { border: 1px dashed #ccc; }"
+            "@media print { nav, .no-print { display: none; } article { box-shadow: none; padding: 0; } .label-sheet svg { border: none; } }"
+        }
+        div class="no-print" {
+            p { (labels.len()) " labels" }
+            button onclick="window.print()" { "Print" }
+        }
+        div class="label-sheet" {
+            @for label in &labels {
+                (label)
+            }
+        }
+    };
+
+    Html(html_page("Labels", &csrf_token, theme, content).into_string()).into_response()
+}
+
+#[derive(Debug, sqlx::FromRow)]
+struct LocationStock {
+    location: Option<String>,
+    quantity: Option<i32>,
+    staged: Option<i32>,
+}
+
+async fn query_locations(
+    id: i32,
+    db_conn: &mut PgConnection,
+) -> Result<Vec<LocationStock>, sqlx::Error> {
+    let mut query = QueryBuilder::new(
+        "SELECT l.name AS location, s.quantity, s.staged FROM stock s LEFT JOIN locations l ON s.location_id = l.id WHERE s.part_id = ",
+    );
+    query.push_bind(id);
+    query.push(" ORDER BY s.quantity DESC NULLS LAST, s.id");
+    query
+        .build_query_as::<LocationStock>()
+        .fetch_all(&mut *db_conn)
+        .await
+}
+
+fn html_locations_table(locations: &[LocationStock]) -> Markup {
+    html! {
+        div class="overflow-auto" {
+            table class="striped" {
+                thead {
+                    tr {
+                        th scope="col" { "Location" }
+                        th scope="col" { "Qty." }
+                        th scope="col" { "Staged" }
+                    }
+                }
+                tbody {
+                    @for location in locations {
+                        tr {
+                            th scope="row" { (location.location.as_deref().unwrap_or("—")) }
+                            td {
+                                @if let Some(quantity) = location.quantity {
+                                    (quantity)
+                                } @else {
+                                    "—"
+                                }
+                            }
+                            td {
+                                @if let Some(staged) = location.staged {
+                                    (staged)
+                                } @else {
+                                    "—"
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub async fn locations_page(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+    session: Session,
+    theme: Theme,
+) -> impl IntoResponse {
+    info!("Performing location query for component {}", id);
+
+    let csrf_token = match csrf_token(&session).await {
+        Ok(token) => token,
+        Err(e) => return handle_generic_inventory_error(e),
+    };
+
+    let mut db_conn = match state.pool.acquire().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            return handle_generic_inventory_error(e);
+        }
+    };
+
+    let item = match query_item(id, &mut db_conn).await {
+        Ok(Some(item)) => item,
+        Ok(None) => {
+            return handle_generic_inventory_error(format!("No component with id {}", id));
+        }
+        Err(e) => {
+            return handle_generic_inventory_error(e);
+        }
+    };
+
+    let locations = match query_locations(id, &mut db_conn).await {
+        Ok(locations) => locations,
+        Err(e) => {
+            return handle_generic_inventory_error(e);
+        }
+    };
+
+    let content = html_locations_table(&locations);
+
+    let title = format!("Locations of {}", item.mpn.as_deref().unwrap_or("—"));
+    Html(html_page(&title, &csrf_token, theme, content).into_string()).into_response()
+}
+
+fn html_part_details(item: &InventoryItem, units: &CategoryUnits) -> Markup {
+    html! {
+        div class="overflow-auto" {
+            table class="striped" {
+                tbody {
+                    tr {
+                        th scope="row" { "Id" }
+                        td { (item.id) }
+                    }
+                    tr {
+                        th scope="row" { "MPN" }
+                        td { (item.mpn.as_deref().unwrap_or("—")) }
+                    }
+                    tr {
+                        th scope="row" { "Category" }
+                        td { (item.category) }
+                    }
+                    tr {
+                        th scope="row" { "Footprint" }
+                        td { (item.footprint.as_deref().unwrap_or("—")) }
+                    }
+                    tr {
+                        th scope="row" { "Value" }
+                        td {
+                            @if let Some(value) = item.value {
+                                (format_value(units, &item.category, value).trim_end())
+                            } @else {
+                                "—"
+                            }
+                        }
+                    }
+                    tr {
+                        th scope="row" { "Voltage rating" }
+                        td {
+                            @if let Some(rating) = item.voltage_rating {
+                                (format_rating(rating, "V").trim_end())
+                            } @else {
+                                "—"
+                            }
+                        }
+                    }
+                    tr {
+                        th scope="row" { "Power rating" }
+                        td {
+                            @if let Some(rating) = item.power_rating {
+                                (format_rating(rating, "W").trim_end())
+                            } @else {
+                                "—"
+                            }
+                        }
+                    }
+                    tr {
+                        th scope="row" { "Status" }
+                        td { (item.status.label()) }
+                    }
+                    tr {
+                        th scope="row" { "Tags" }
+                        td {
+                            @for tag in &item.tags {
+                                small class="tag" { (tag) }
+                                " "
+                            }
+                            @if item.tags.is_empty() {
+                                "—"
+                            }
+                        }
+                    }
+                    tr {
+                        th scope="row" { "Comments" }
+                        td { (item.comments.as_deref().unwrap_or("—")) }
+                    }
+                    tr {
+                        th scope="row" { "Quantity unit" }
+                        td { (item.qty_unit) }
+                    }
+                    tr {
+                        th scope="row" { "Reserved" }
+                        td { (item.reserved.unwrap_or(0)) }
+                    }
+                    tr {
+                        th scope="row" { "Reorder point" }
+                        td {
+                            @if let Some(reorder_point) = item.reorder_point {
+                                (reorder_point)
+                            } @else {
+                                "—"
+                            }
+                        }
+                    }
+                    tr {
+                        th scope="row" { "Unit price" }
+                        td {
+                            @if let Some(price) = item.unit_price {
+                                (format_price(price))
+                            } @else {
+                                "—"
+                            }
+                        }
+                    }
+                    tr {
+                        th scope="row" { "Added" }
+                        td { (format_time(&item.created_at)) }
+                    }
+                    tr {
+                        th scope="row" { "Last changed" }
+                        td { (format_time(&item.updated_at)) }
+                    }
+                    tr {
+                        th scope="row" { "Supplier" }
+                        td {
+                            @if let Some(url) = &item.supplier_url {
+                                a href=(url) target="_blank" rel="noopener noreferrer" {
+                                    (item.supplier.as_deref().unwrap_or(url))
+                                }
+                            } @else {
+                                (item.supplier.as_deref().unwrap_or("—"))
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Everything about a part in one page, so that it can be bookmarked
+pub async fn part_page(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+    session: Session,
+    theme: Theme,
+) -> impl IntoResponse {
+    info!("Generating page of component {}", id);
+
+    let csrf_token = match csrf_token(&session).await {
+        Ok(token) => token,
+        Err(e) => return handle_generic_inventory_error(e),
+    };
+
+    let mut db_conn = match state.pool.acquire().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            return handle_generic_inventory_error(e);
+        }
+    };
+
+    let item = match query_item(id, &mut db_conn).await {
+        Ok(Some(item)) => item,
+        Ok(None) => {
+            let content = html! {
+                p { "No part with id " (id) "." }
+                a href="/inventory" { "Back to the inventory" }
+            };
+            return (
+                StatusCode::NOT_FOUND,
+                Html(html_page("Part not found", &csrf_token, theme, content).into_string()),
+            )
+                .into_response();
+        }
+        Err(e) => {
+            return handle_generic_inventory_error(e);
+        }
+    };
+
+    let locations = match query_locations(id, &mut db_conn).await {
+        Ok(locations) => locations,
+        Err(e) => {
+            return handle_generic_inventory_error(e);
+        }
+    };
+
+    let history = match query_history(id, &mut db_conn).await {
+        Ok(history) => history,
+        Err(e) => {
+            return handle_generic_inventory_error(e);
+        }
+    };
+
+    let units = state.category_units();
+    let columns = Columns::default();
+    let content = html! {
+        // (The same row as in the inventory, for its stage and edit controls)
+        div class="overflow-auto" {
+            table class="striped" {
+                (html_static_table_header(&columns))
+                tbody {
+                    (html_table_row(&item, &units, &columns))
+                }
+            }
+        }
+        div id="stage-status" {}
+        h3 { "Details" }
+        (html_part_details(&item, &units))
+        h3 { "Locations" }
+        (html_locations_table(&locations))
+        h3 { "History" }
+        (html_history_table(&history))
+    };
+
+    let title = match &item.mpn {
+        Some(mpn) => mpn.clone(),
+        None => format!("Part #{}", item.id),
+    };
+    Html(html_page(&title, &csrf_token, theme, content).into_string()).into_response()
+}
+
+pub async fn query_item(
+    id: i32,
+    db_conn: &mut PgConnection,
+) -> Result<Option<InventoryItem>, sqlx::Error> {
+    let mut query = QueryBuilder::new("SELECT * FROM inventory WHERE id = ");
+    query.push_bind(id);
+    query
+        .build_query_as::<InventoryItem>()
+        .fetch_optional(&mut *db_conn)
+        .await
+}
+
+// A part may be stored in many locations, each with its own stock row. Changes
+// which don't specify a location go to the one with the most stock, which is
+// also the location shown for the part in the inventory view
+fn push_primary_stock_id(query: &mut QueryBuilder<Postgres>, id: i32) {
+    query.push("(SELECT id FROM stock WHERE part_id = ");
+    query.push_bind(id);
+    query.push(" ORDER BY quantity DESC NULLS LAST, id LIMIT 1)");
+}
+
+// Locks the part and its stock until the end of the transaction, so that the
+// changes logged between two query_item calls are exactly the ones we made
+async fn lock_item(id: i32, db_conn: &mut PgConnection) -> Result<(), sqlx::Error> {
+    let mut query = QueryBuilder::new("SELECT 1 FROM parts WHERE id = ");
+    query.push_bind(id);
+    query.push(" FOR UPDATE");
+    query.build().execute(&mut *db_conn).await?;
+
+    let mut query = QueryBuilder::new("SELECT 1 FROM stock WHERE part_id = ");
+    query.push_bind(id);
+    query.push(" FOR UPDATE");
+    query.build().execute(&mut *db_conn).await?;
+
+    Ok(())
+}
+
+pub enum StockMove {
+    Moved,
+    // The part is stocked in several other locations, so which one to move is
+    // up to the user (see locations_page)
+    Ambiguous,
+}
+
+// Moves the primary stock row of a (locked) part to the location. If the part is
+// stocked there already, it's merged into that row instead, as there may only be
+// one row for each location
+async fn move_stock(
+    id: i32,
+    location_id: i32,
+    db_conn: &mut PgConnection,
+) -> Result<StockMove, sqlx::Error> {
+    let mut query =
+        QueryBuilder::new("SELECT id, location_id, quantity, staged FROM stock WHERE part_id = ");
+    query.push_bind(id);
+    // (Primary first, see push_primary_stock_id)
+    query.push(" ORDER BY quantity DESC NULLS LAST, id");
+    let rows = query
+        .build_query_as::<(i32, Option<i32>, Option<i32>, Option<i32>)>()
+        .fetch_all(&mut *db_conn)
+        .await?;
+
+    let Some(&(primary_id, primary_location, quantity, staged)) = rows.first() else {
+        return Ok(StockMove::Moved);
+    };
+    if primary_location == Some(location_id) {
+        return Ok(StockMove::Moved);
+    }
+
+    if let Some(&(target_id, ..)) = rows.iter().find(|x| x.1 == Some(location_id)) {
+        let mut query = QueryBuilder::new("UPDATE stock SET quantity = COALESCE(quantity, 0) + ");
+        query.push_bind(quantity.unwrap_or(0));
+        query.push(", staged = ");
+        if let Some(staged) = staged {
+            query.push("COALESCE(staged, 0) + ");
+            query.push_bind(staged);
+        } else {
+            query.push("staged");
+        }
+        query.push(", updated_at = NOW() WHERE id = ");
+        query.push_bind(target_id);
+        query.build().execute(&mut *db_conn).await?;
+
+        let mut query = QueryBuilder::new("DELETE FROM stock WHERE id = ");
+        query.push_bind(primary_id);
+        query.build().execute(&mut *db_conn).await?;
+    } else if rows.len() == 1 {
+        let mut query = QueryBuilder::new("UPDATE stock SET location_id = ");
+        query.push_bind(location_id);
+        query.push(", updated_at = NOW() WHERE id = ");
+        query.push_bind(primary_id);
+        query.build().execute(&mut *db_conn).await?;
+    } else {
+        return Ok(StockMove::Ambiguous);
+    }
+
+    Ok(StockMove::Moved)
+}
+
+fn diff_items(old: &InventoryItem, new: &InventoryItem) -> Vec<StockLogEntry> {
+    let mut entries = vec![];
+    let id = new.id;
+
+    let mut text = |field: &'static str, old: &Option<String>, new: &Option<String>| {
+        if old != new {
+            entries.push(StockLogEntry::text(id, field, old.clone(), new.clone()));
+        }
+    };
+    text("mpn", &old.mpn, &new.mpn);
+    text(
+        "category",
+        &Some(old.category.clone()),
+        &Some(new.category.clone()),
+    );
+    text("footprint", &old.footprint, &new.footprint);
+    text(
+        "value",
+        &old.value.map(|x| x.to_string()),
+        &new.value.map(|x| x.to_string()),
+    );
+    text(
+        "voltage_rating",
+        &old.voltage_rating.map(|x| x.to_string()),
+        &new.voltage_rating.map(|x| x.to_string()),
+    );
+    text(
+        "power_rating",
+        &old.power_rating.map(|x| x.to_string()),
+        &new.power_rating.map(|x| x.to_string()),
+    );
+    text("location", &old.location, &new.location);
+    text("comments", &old.comments, &new.comments);
+    text(
+        "reorder_point",
+        &old.reorder_point.map(|x| x.to_string()),
+        &new.reorder_point.map(|x| x.to_string()),
+    );
+    text(
+        "unit_price",
+        &old.unit_price.map(|x| x.to_string()),
+        &new.unit_price.map(|x| x.to_string()),
+    );
+    text(
+        "tags",
+        &Some(old.tags.join(", ")),
+        &Some(new.tags.join(", ")),
+    );
+    text("supplier", &old.supplier, &new.supplier);
+    text("supplier_url", &old.supplier_url, &new.supplier_url);
+    text(
+        "status",
+        &Some(old.status.label().to_string()),
+        &Some(new.status.label().to_string()),
+    );
+    text(
+        "qty_unit",
+        &Some(old.qty_unit.clone()),
+        &Some(new.qty_unit.clone()),
+    );
+
+    if old.quantity != new.quantity {
+        entries.push(StockLogEntry::count(
+            id,
+            "quantity",
+            old.quantity,
+            new.quantity,
+        ));
+    }
+    if old.staged != new.staged {
+        entries.push(StockLogEntry::count(id, "staged", old.staged, new.staged));
+    }
+
+    entries
+}
+
+// Returns the id of the row with the given name on a table with an unique "name"
+// column (categories, footprints, locations), creating it if needed
+async fn get_or_insert_name(
+    table: &str,
+    name: &str,
+    db_conn: &mut PgConnection,
+) -> Result<i32, sqlx::Error> {
+    let mut query = QueryBuilder::new("INSERT INTO ");
+    query.push(table);
+    query.push(" (name) VALUES (");
+    query.push_bind(name);
+    // (DO UPDATE so that RETURNING also yields already existing rows)
+    query.push(") ON CONFLICT (name) DO UPDATE SET name = EXCLUDED.name RETURNING id");
+
+    query
+        .build_query_scalar::<i32>()
+        .fetch_one(&mut *db_conn)
+        .await
+}
+
+// Trims and collapses runs of whitespace, the form categories are stored in
+pub fn normalize_name(name: &str) -> String {
+    name.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+// Like get_or_insert_name, but reusing a category which only differs in case
+// or whitespace, so that e.g. "resistor" doesn't end up next to "Resistor"
+async fn get_or_insert_category(
+    name: &str,
+    db_conn: &mut PgConnection,
+) -> Result<i32, sqlx::Error> {
+    let name = normalize_name(name);
+
+    let mut query = QueryBuilder::new("SELECT id FROM categories WHERE LOWER(name) = LOWER(");
+    query.push_bind(&name);
+    query.push(") ORDER BY id LIMIT 1");
+    let existing = query
+        .build_query_scalar::<i32>()
+        .fetch_optional(&mut *db_conn)
+        .await?;
+
+    match existing {
+        Some(id) => Ok(id),
+        None => get_or_insert_name("categories", &name, db_conn).await,
+    }
+}
+
+// Number of single character insertions, deletions or substitutions between
+// the two, ignoring case
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.to_lowercase().chars().collect();
+    let b: Vec<char> = b.to_lowercase().chars().collect();
+
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, x) in a.iter().enumerate() {
+        let mut row = vec![i + 1];
+        for (j, y) in b.iter().enumerate() {
+            let substitution = prev[j] + usize::from(x != y);
+            row.push(substitution.min(prev[j + 1] + 1).min(row[j] + 1));
+        }
+        prev = row;
+    }
+    prev[b.len()]
+}
+
+// A problem with the footprint if known footprints are set and it isn't one
+// of them, suggesting the closest one
+async fn check_known_footprint(
+    footprint: &str,
+    db_conn: &mut PgConnection,
+) -> Result<Option<String>, sqlx::Error> {
+    let known = QueryBuilder::new("SELECT name FROM known_footprints")
+        .build_query_scalar::<String>()
+        .fetch_all(&mut *db_conn)
+        .await?;
+
+    if known.is_empty() || known.iter().any(|x| x == footprint) {
+        return Ok(None);
+    }
+
+    // (Only if close, as the nearest of a few unrelated ones isn't helpful)
+    let closest = known
+        .iter()
+        .map(|x| (edit_distance(x, footprint), x))
+        .min()
+        .filter(|(distance, _)| *distance <= footprint.chars().count() / 2)
+        .map(|(_, x)| x);
+    Ok(Some(match closest {
+        Some(closest) => format!(
+            "Unknown footprint \"{}\", did you mean \"{}\"?",
+            footprint, closest
+        ),
+        None => format!("Unknown footprint \"{}\".", footprint),
+    }))
+}
+
+// Returns a list of problems with the item, empty if it can be inserted
+// (Empty fields are left out, so they're fine)
+fn check_parses<T>(
+    errors: &mut Vec<String>,
+    name: &str,
+    value: Option<&str>,
+    parse: impl Fn(&str) -> Option<T>,
+) {
+    if let Some(value) = value.and_then(non_empty)
+        && parse(value).is_none()
+    {
+        errors.push(format!("Unable to parse {} \"{}\".", name, value));
+    }
+}
+
+fn validate_item(item: &NewItemForm) -> Vec<String> {
+    let mut errors = vec![];
+
+    if non_empty(&item.category).is_none() {
+        errors.push(String::from("A category is required."));
+    }
+
+    check_parses(
+        &mut errors,
+        "value",
+        Some(&item.value),
+        parse_multiple_value,
+    );
+    check_parses(
+        &mut errors,
+        "voltage rating",
+        Some(&item.voltage_rating),
+        parse_multiple_value,
+    );
+    check_parses(
+        &mut errors,
+        "power rating",
+        Some(&item.power_rating),
+        parse_multiple_value,
+    );
+    check_parses(&mut errors, "quantity", Some(&item.quantity), |x| {
+        x.parse::<i32>().ok()
+    });
+    check_parses(
+        &mut errors,
+        "unit price",
+        Some(&item.unit_price),
+        parse_price,
+    );
+
+    errors
+}
+
+// Like validate_item, as update_item leaves out whatever doesn't parse
+fn validate_edit(item: &EditItemForm) -> Vec<String> {
+    let mut errors = vec![];
+
+    check_parses(
+        &mut errors,
+        "value",
+        item.value.as_deref(),
+        parse_multiple_value,
+    );
+    check_parses(
+        &mut errors,
+        "voltage rating",
+        item.voltage_rating.as_deref(),
+        parse_multiple_value,
+    );
+    check_parses(
+        &mut errors,
+        "power rating",
+        item.power_rating.as_deref(),
+        parse_multiple_value,
+    );
+    check_parses(
+        &mut errors,
+        "reorder point",
+        item.reorder_point.as_deref(),
+        |x| x.parse::<i32>().ok(),
+    );
+    check_parses(
+        &mut errors,
+        "unit price",
+        item.unit_price.as_deref(),
+        parse_price,
+    );
+    check_parses(&mut errors, "status", item.status.as_deref(), |x| {
+        x.parse::<PartStatus>().ok()
+    });
+
+    if let Some(url) = item.supplier_url.as_deref().and_then(non_empty)
+        && !valid_url(url)
+    {
+        errors.push(format!("Invalid supplier URL {}", url));
+    }
+
+    errors
+}
+
+async fn insert_item(
+    item: &NewItemForm,
+    user_id: Option<i32>,
+    db_conn: &mut PgConnection,
+) -> Result<InventoryItem, sqlx::Error> {
+    let mut tx = db_conn.begin().await?;
+
+    let category_id = get_or_insert_category(&item.category, &mut tx).await?;
+    let footprint_id = match non_empty(&item.footprint) {
+        Some(footprint) => Some(get_or_insert_name("footprints", footprint, &mut tx).await?),
+        None => None,
+    };
+    let location_id = match non_empty(&item.location) {
+        Some(location) => Some(get_or_insert_name("locations", location, &mut tx).await?),
+        None => None,
+    };
+
+    let mut query = QueryBuilder::new(
+        "INSERT INTO parts (category_id, footprint_id, mpn, value, volt_rating, watt_rating, comments, unit_price) VALUES (",
+    );
+    let mut values = query.separated(", ");
+    values.push_bind(category_id);
+    values.push_bind(footprint_id);
+    values.push_bind(non_empty(&item.mpn));
+    values.push_bind(non_empty(&item.value).and_then(parse_multiple_value));
+    values.push_bind(non_empty(&item.voltage_rating).and_then(parse_multiple_value));
+    values.push_bind(non_empty(&item.power_rating).and_then(parse_multiple_value));
+    values.push_bind(non_empty(&item.comments));
+    values.push_bind(non_empty(&item.unit_price).and_then(parse_price));
+    query.push(") RETURNING id");
+
+    let id = query
+        .build_query_scalar::<i32>()
+        .fetch_one(&mut *tx)
+        .await?;
+
+    let quantity = non_empty(&item.quantity)
+        .and_then(|x| x.parse::<i32>().ok())
+        .unwrap_or(0);
+    let mut query =
+        QueryBuilder::new("INSERT INTO stock (part_id, location_id, quantity) VALUES (");
+    let mut values = query.separated(", ");
+    values.push_bind(id);
+    values.push_bind(location_id);
+    values.push_bind(quantity);
+    query.push(")");
+    query.build().execute(&mut *tx).await?;
+
+    insert_log(
+        &[
+            StockLogEntry::text(id, "created", None, non_empty(&item.mpn).map(String::from)),
+            StockLogEntry::count(id, "quantity", None, Some(quantity)),
+        ],
+        user_id,
+        &mut tx,
+    )
+    .await?;
+
+    let result = query_item(id, &mut tx)
+        .await?
+        .ok_or(sqlx::Error::RowNotFound)?;
+
+    tx.commit().await?;
+
+    Ok(result)
+}
+
+pub async fn add_handler(
+    columns: Columns,
+    State(state): State<AppState>,
+    Extension(CurrentUser(user_id)): Extension<CurrentUser>,
+    Form(item): Form<NewItemForm>,
+) -> impl IntoResponse {
+    info!("Adding component: {:?}", item);
+
+    let errors = validate_item(&item);
+    if !errors.is_empty() {
+        let mut headers = HeaderMap::new();
+        headers.insert("HX-Retarget", "#add-error".parse().unwrap());
+        headers.insert("HX-Reswap", "innerHTML".parse().unwrap());
+        let response = html! {
+            article {
+                @for error in &errors {
+                    p { (error) }
+                }
+            }
+        };
+        return (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            headers,
+            Html(response.into_string()),
+        )
+            .into_response();
+    }
+
+    let mut db_conn = match state.pool.acquire().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            return handle_generic_inventory_error(e);
+        }
+    };
+
+    if let Some(footprint) = non_empty(&item.footprint) {
+        match check_known_footprint(footprint, &mut db_conn).await {
+            Ok(None) => {}
+            Ok(Some(error)) => {
+                let mut headers = HeaderMap::new();
+                headers.insert("HX-Retarget", "#add-error".parse().unwrap());
+                headers.insert("HX-Reswap", "innerHTML".parse().unwrap());
+                let response = html! {
+                    article {
+                        p { (error) }
+                    }
+                };
+                return (
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    headers,
+                    Html(response.into_string()),
+                )
+                    .into_response();
+            }
+            Err(e) => return handle_generic_inventory_error(e),
+        }
+    }
+
+    match insert_item(&item, Some(user_id), &mut db_conn).await {
+        Ok(result) => {
+            state.list_cache.invalidate();
+            state.notify_change(Some(result.id));
+            let mut headers = HeaderMap::new();
+            headers.insert("HX-Trigger", "inventoryUpdated".parse().unwrap());
+            (
+                headers,
+                Html(html_table_row(&result, &state.category_units(), &columns).into_string()),
+            )
+                .into_response()
+        }
+        Err(e) if is_mpn_conflict(&e) => {
+            mpn_conflict_response(item.mpn.trim(), "#add-error", &mut db_conn).await
+        }
+        Err(e) => handle_generic_inventory_error(e),
+    }
+}
+
+struct ImportRow {
+    line: u64,
+    item: Option<NewItemForm>,
+    errors: Vec<String>,
+}
+
+fn parse_import_csv(data: &[u8]) -> Vec<ImportRow> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(data);
+
+    reader
+        .deserialize::<NewItemForm>()
+        .enumerate()
+        .map(|(idx, row)| {
+            // (+2 as the header is the first line)
+            let line = idx as u64 + 2;
+            match row {
+                Ok(item) => ImportRow {
+                    line,
+                    errors: validate_item(&item),
+                    item: Some(item),
+                },
+                Err(e) => ImportRow {
+                    line,
+                    item: None,
+                    errors: vec![format!("Malformed row: {}", e)],
+                },
+            }
+        })
+        .collect()
+}
+
+async fn import_items(
+    rows: &[ImportRow],
+    user_id: Option<i32>,
+    db_conn: &mut PoolConnection<Postgres>,
+) -> Result<usize, sqlx::Error> {
+    // A single transaction, so that a failing row aborts the whole import
+    let mut tx = db_conn.begin().await?;
+
+    for item in rows.iter().filter_map(|x| x.item.as_ref()) {
+        insert_item(item, user_id, &mut tx).await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(rows.len())
+}
+
+pub async fn import_handler(
+    State(state): State<AppState>,
+    Extension(CurrentUser(user_id)): Extension<CurrentUser>,
+    mut multipart: Multipart,
+) -> impl IntoResponse {
+    let mut csv_data = None;
+    let mut dry_run = false;
+
+    loop {
+        match multipart.next_field().await {
+            Ok(Some(field)) => match field.name() {
+                Some("file") => match field.bytes().await {
+                    Ok(bytes) => csv_data = Some(bytes),
+                    Err(e) => return handle_generic_inventory_error(e),
+                },
+                Some("dry_run") => {
+                    dry_run = field.text().await.is_ok_and(|x| x == "true");
+                }
+                _ => {}
+            },
+            Ok(None) => break,
+            Err(e) => return handle_generic_inventory_error(e),
+        }
+    }
+
+    let Some(csv_data) = csv_data else {
+        return handle_generic_inventory_error("No file uploaded for import");
+    };
+
+    info!(
+        "Importing {} bytes of CSV, dry run: {}",
+        csv_data.len(),
+        dry_run
+    );
+
+    let rows = parse_import_csv(&csv_data);
+    let num_errors = rows.iter().filter(|x| !x.errors.is_empty()).count();
+
+    if dry_run || num_errors > 0 {
+        return Html(html_import_report(&rows, dry_run, &state.category_units()).into_string())
+            .into_response();
+    }
+
+    let mut db_conn = match state.pool.acquire().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            return handle_generic_inventory_error(e);
+        }
+    };
+
+    match import_items(&rows, Some(user_id), &mut db_conn).await {
+        Ok(num) => {
+            state.list_cache.invalidate();
+            state.notify_change(None);
+            let mut headers = HeaderMap::new();
+            headers.insert("HX-Trigger", "inventoryUpdated".parse().unwrap());
+            let response = html! {
+                article {
+                    "Imported " (num) " parts."
+                }
+            };
+            (headers, Html(response.into_string())).into_response()
+        }
+        Err(e) => handle_generic_inventory_error(e),
+    }
+}
+
+enum EditOutcome {
+    Updated(Box<InventoryItem>),
+    NotFound,
+    // Somebody else saved the part since the form was loaded
+    Conflict,
+    // The location can't be changed here, see StockMove::Ambiguous
+    SeveralLocations,
+}
+
+async fn update_item(
+    id: i32,
+    item: &EditItemForm,
+    user_id: Option<i32>,
+    db_conn: &mut PoolConnection<Postgres>,
+) -> Result<EditOutcome, sqlx::Error> {
+    let mut tx = db_conn.begin().await?;
+
+    lock_item(id, &mut tx).await?;
+    let Some(old) = query_item(id, &mut tx).await? else {
+        return Ok(EditOutcome::NotFound);
+    };
+
+    let mut query = QueryBuilder::new("UPDATE parts SET ");
+    let mut sets = query.separated(", ");
+    sets.push("version = version + 1");
+
+    if let Some(category) = item.category.as_deref().and_then(non_empty) {
+        let category_id = get_or_insert_category(category, &mut tx).await?;
+        sets.push("category_id = ");
+        sets.push_bind_unseparated(category_id);
+    }
+
+    if let Some(footprint) = item.footprint.as_deref().and_then(non_empty) {
+        let footprint_id = get_or_insert_name("footprints", footprint, &mut tx).await?;
+        sets.push("footprint_id = ");
+        sets.push_bind_unseparated(footprint_id);
+    }
+
+    if let Some(mpn) = item.mpn.as_deref().and_then(non_empty) {
+        sets.push("mpn = ");
+        sets.push_bind_unseparated(mpn);
+    }
+
+    // (What doesn't parse was rejected by edit_handler, see validate_edit)
+    if let Some(value) = item
+        .value
+        .as_deref()
+        .and_then(non_empty)
+        .and_then(parse_multiple_value)
+    {
+        sets.push("value = ");
+        sets.push_bind_unseparated(value);
+    }
+
+    if let Some(rating) = item
+        .voltage_rating
+        .as_deref()
+        .and_then(non_empty)
+        .and_then(parse_multiple_value)
+    {
+        sets.push("volt_rating = ");
+        sets.push_bind_unseparated(rating);
+    }
+
+    if let Some(rating) = item
+        .power_rating
+        .as_deref()
+        .and_then(non_empty)
+        .and_then(parse_multiple_value)
+    {
+        sets.push("watt_rating = ");
+        sets.push_bind_unseparated(rating);
+    }
+
+    if let Some(comments) = item.comments.as_deref().and_then(non_empty) {
+        sets.push("comments = ");
+        sets.push_bind_unseparated(comments);
+    }
+
+    if let Some(reorder_point) = item
+        .reorder_point
+        .as_deref()
+        .and_then(non_empty)
+        .and_then(|x| x.parse::<i32>().ok())
+    {
+        sets.push("reorder_point = ");
+        sets.push_bind_unseparated(reorder_point);
+    }
+
+    if let Some(price) = item
+        .unit_price
+        .as_deref()
+        .and_then(non_empty)
+        .and_then(parse_price)
+    {
+        sets.push("unit_price = ");
+        sets.push_bind_unseparated(price);
+    }
+
+    if let Some(tags) = item.tags.as_deref().and_then(non_empty) {
+        sets.push("tags = ");
+        sets.push_bind_unseparated(parse_tags(tags));
+    }
+
+    if let Some(supplier) = item.supplier.as_deref().and_then(non_empty) {
+        sets.push("supplier = ");
+        sets.push_bind_unseparated(supplier);
+    }
+
+    if let Some(qty_unit) = item.qty_unit.as_deref().and_then(non_empty) {
+        sets.push("qty_unit = ");
+        sets.push_bind_unseparated(qty_unit);
+    }
+
+    if let Some(url) = item.supplier_url.as_deref().and_then(non_empty) {
+        sets.push("supplier_url = ");
+        sets.push_bind_unseparated(url);
+    }
+
+    if let Some(status) = item
+        .status
+        .as_deref()
+        .and_then(|x| x.parse::<PartStatus>().ok())
+    {
+        sets.push("status = ");
+        sets.push_bind_unseparated(status);
+    }
+
+    query.push(" WHERE id = ");
+    query.push_bind(id);
+    if let Some(version) = item.version {
+        query.push(" AND version = ");
+        query.push_bind(version);
+    }
+    // (Dropping the transaction rolls back whatever was inserted above)
+    if query.build().execute(&mut *tx).await?.rows_affected() == 0 {
+        return Ok(EditOutcome::Conflict);
+    }
+
+    if let Some(location) = item.location.as_deref().and_then(non_empty) {
+        let location_id = get_or_insert_name("locations", location, &mut tx).await?;
+        if let StockMove::Ambiguous = move_stock(id, location_id, &mut tx).await? {
+            return Ok(EditOutcome::SeveralLocations);
+        }
+    }
+
+    let Some(new) = query_item(id, &mut tx).await? else {
+        return Ok(EditOutcome::NotFound);
+    };
+    insert_log(&diff_items(&old, &new), user_id, &mut tx).await?;
+
+    tx.commit().await?;
+
+    Ok(EditOutcome::Updated(Box::new(new)))
+}
+
+pub async fn edit_handler(
+    State(state): State<AppState>,
+    Extension(CurrentUser(user_id)): Extension<CurrentUser>,
+    columns: Columns,
+    Path(id): Path<i32>,
+    Form(item): Form<EditItemForm>,
+) -> impl IntoResponse {
+    info!("Editing component {}: {:?}", id, item);
+
+    let errors = validate_edit(&item);
+    if !errors.is_empty() {
+        let mut headers = HeaderMap::new();
+        headers.insert("HX-Retarget", "#stage-status".parse().unwrap());
+        headers.insert("HX-Reswap", "innerHTML".parse().unwrap());
+        let response = html! {
+            @for error in &errors {
+                p style="color: red;" { (error) }
+            }
+        };
+        return (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            headers,
+            Html(response.into_string()),
+        )
+            .into_response();
+    }
+
+    let mut db_conn = match state.pool.acquire().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            return handle_generic_inventory_error(e);
+        }
+    };
+
+    if let Some(footprint) = item.footprint.as_deref().and_then(non_empty) {
+        let error = match check_known_footprint(footprint, &mut db_conn).await {
+            Ok(error) => error,
+            Err(e) => return handle_generic_inventory_error(e),
+        };
+        // (A part may keep a footprint from before they were set, or else it
+        // couldn't be edited at all until renamed)
+        let unchanged = match query_item(id, &mut db_conn).await {
+            Ok(current) => current.is_some_and(|x| x.footprint.as_deref() == Some(footprint)),
+            Err(e) => return handle_generic_inventory_error(e),
+        };
+        if let Some(error) = error
+            && !unchanged
+        {
+            let mut headers = HeaderMap::new();
+            headers.insert("HX-Retarget", "#stage-status".parse().unwrap());
+            headers.insert("HX-Reswap", "innerHTML".parse().unwrap());
+            let response = html! {
+                p style="color: red;" { (error) }
+            };
+            return (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                headers,
+                Html(response.into_string()),
+            )
+                .into_response();
+        }
+    }
+
+    match update_item(id, &item, Some(user_id), &mut db_conn).await {
+        Ok(EditOutcome::Updated(result)) => {
+            state.list_cache.invalidate();
+            state.notify_change(Some(id));
+            let mut headers = HeaderMap::new();
+            headers.insert("HX-Trigger", "inventoryUpdated".parse().unwrap());
+            (
+                headers,
+                Html(html_table_row(&result, &state.category_units(), &columns).into_string()),
+            )
+                .into_response()
+        }
+        Ok(EditOutcome::NotFound) => {
+            handle_generic_inventory_error(format!("No component with id {}", id))
+        }
+        Ok(EditOutcome::Conflict) => {
+            info!("Component {} was edited meanwhile", id);
+            // (The form is kept, so that the changes can be copied over)
+            let mut headers = HeaderMap::new();
+            headers.insert("HX-Retarget", "#stage-status".parse().unwrap());
+            headers.insert("HX-Reswap", "innerHTML".parse().unwrap());
+            let response = html! {
+                p style="color: red;" {
+                    "Someone else edited this part meanwhile, reload to see their changes."
+                }
+            };
+            (StatusCode::CONFLICT, headers, Html(response.into_string())).into_response()
+        }
+        Ok(EditOutcome::SeveralLocations) => {
+            let mut headers = HeaderMap::new();
+            headers.insert("HX-Retarget", "#stage-status".parse().unwrap());
+            headers.insert("HX-Reswap", "innerHTML".parse().unwrap());
+            let response = html! {
+                p style="color: red;" {
+                    "This part is stocked in several locations, move its stock from the "
+                    a href={"/locations/" (id)} { "locations page" }
+                    " instead."
+                }
+            };
+            (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                headers,
+                Html(response.into_string()),
+            )
+                .into_response()
+        }
+        Err(e) if is_mpn_conflict(&e) => {
+            let mpn = item.mpn.as_deref().unwrap_or_default().trim();
+            mpn_conflict_response(mpn, "#stage-status", &mut db_conn).await
+        }
+        Err(e) => handle_generic_inventory_error(e),
+    }
+}
+
+// Changes of a bulk edit, already checked
+pub enum BulkChange {
+    Category(String),
+    Footprint(Option<String>),
+    Location(String),
+    Status(PartStatus),
+}
+
+impl BulkChange {
+    fn parse(field: &str, value: &str) -> Result<BulkChange, String> {
+        let value = value.trim();
+        match field {
+            "category" => match non_empty(value) {
+                Some(category) => Ok(BulkChange::Category(category.to_string())),
+                None => Err(String::from("A category is required.")),
+            },
+            // (Empty to remove the footprint)
+            "footprint" => Ok(BulkChange::Footprint(non_empty(value).map(String::from))),
+            "location" => match non_empty(value) {
+                Some(location) => Ok(BulkChange::Location(location.to_string())),
+                None => Err(String::from("A location is required.")),
+            },
+            "status" => value
+                .parse::<PartStatus>()
+                .map(BulkChange::Status)
+                .map_err(|_| format!("Invalid status {}", value)),
+            _ => Err(format!("Field {} can't be bulk edited", field)),
+        }
+    }
+}
+
+pub async fn query_items(
+    ids: &[i32],
+    db_conn: &mut PgConnection,
+) -> Result<Vec<InventoryItem>, sqlx::Error> {
+    let mut query = QueryBuilder::new("SELECT * FROM inventory WHERE id = ANY(");
+    query.push_bind(ids);
+    query.push(") ORDER BY id");
+    query
+        .build_query_as::<InventoryItem>()
+        .fetch_all(&mut *db_conn)
+        .await
+}
+
+// Moves the stock of a part into another, adding it up where both have some in
+// the same location, and deletes the merged part. Its history, projects,
+// alternates and image go to the kept part, which also takes any details it is
+// missing. Returns the kept part, or None if either of them doesn't exist
+pub async fn merge_parts(
+    keep: i32,
+    merged: i32,
+    user_id: Option<i32>,
+    db_conn: &mut PgConnection,
+) -> Result<Option<InventoryItem>, sqlx::Error> {
+    let mut tx = db_conn.begin().await?;
+
+    // (Locked in order of id, so that concurrent merges can't deadlock)
+    let ids = [keep.min(merged), keep.max(merged)];
+    let mut query =
+        QueryBuilder::new("SELECT id FROM parts WHERE deleted_at IS NULL AND id = ANY(");
+    query.push_bind(&ids[..]);
+    query.push(") ORDER BY id FOR UPDATE");
+    let found = query
+        .build_query_scalar::<i32>()
+        .fetch_all(&mut *tx)
+        .await?;
+    if keep == merged || found.len() != 2 {
+        return Ok(None);
+    }
+
+    let mut query = QueryBuilder::new("SELECT 1 FROM stock WHERE part_id = ANY(");
+    query.push_bind(&ids[..]);
+    query.push(") ORDER BY id FOR UPDATE");
+    query.build().execute(&mut *tx).await?;
+
+    let (Some(old), Some(other)) = (
+        query_item(keep, &mut tx).await?,
+        query_item(merged, &mut tx).await?,
+    ) else {
+        return Ok(None);
+    };
+
+    let mut query =
+        QueryBuilder::new("SELECT id, location_id, quantity, staged FROM stock WHERE part_id = ");
+    query.push_bind(merged);
+    query.push(" ORDER BY id");
+    let rows = query
+        .build_query_as::<(i32, Option<i32>, Option<i32>, Option<i32>)>()
+        .fetch_all(&mut *tx)
+        .await?;
+
+    for (stock_id, location_id, quantity, staged) in rows {
+        let mut query = QueryBuilder::new("UPDATE stock SET quantity = COALESCE(quantity, 0) + ");
+        query.push_bind(quantity.unwrap_or(0));
+        if let Some(staged) = staged.filter(|x| *x > 0) {
+            query.push(", staged = COALESCE(staged, 0) + ");
+            query.push_bind(staged);
+        }
+        // (A single row, as there may be many without a location)
+        query.push(" WHERE id = (SELECT id FROM stock WHERE part_id = ");
+        query.push_bind(keep);
+        query.push(" AND location_id IS NOT DISTINCT FROM ");
+        query.push_bind(location_id);
+        query.push(" ORDER BY id LIMIT 1)");
+        let added = query.build().execute(&mut *tx).await?.rows_affected() > 0;
+
+        // (Otherwise the kept part has nothing there yet, so the row is moved)
+        let mut query = if added {
+            QueryBuilder::new("DELETE FROM stock WHERE id = ")
+        } else {
+            let mut query = QueryBuilder::new("UPDATE stock SET part_id = ");
+            query.push_bind(keep);
+            query.push(" WHERE id = ");
+            query
+        };
+        query.push_bind(stock_id);
+        query.build().execute(&mut *tx).await?;
+    }
+
+    // Projects using both keep the kept part's qty_per_board, and reserve what
+    // was reserved of both
+    let mut query = QueryBuilder::new(
+        "UPDATE project_parts k SET reserved = k.reserved + m.reserved FROM project_parts m \
+         WHERE k.project_id = m.project_id AND k.part_id = ",
+    );
+    query.push_bind(keep);
+    query.push(" AND m.part_id = ");
+    query.push_bind(merged);
+    query.build().execute(&mut *tx).await?;
+
+    let mut query = QueryBuilder::new("DELETE FROM project_parts m WHERE m.part_id = ");
+    query.push_bind(merged);
+    query.push(" AND EXISTS (SELECT 1 FROM project_parts k WHERE k.project_id = m.project_id AND k.part_id = ");
+    query.push_bind(keep);
+    query.push(")");
+    query.build().execute(&mut *tx).await?;
+
+    let mut query = QueryBuilder::new("UPDATE project_parts SET part_id = ");
+    query.push_bind(keep);
+    query.push(" WHERE part_id = ");
+    query.push_bind(merged);
+    query.build().execute(&mut *tx).await?;
+
+    // (Pairs are stored with the lower id first, see alternates.rs)
+    let mut query =
+        QueryBuilder::new("INSERT INTO alternates (part_a, part_b) SELECT LEAST(other, ");
+    query.push_bind(keep);
+    query.push("), GREATEST(other, ");
+    query.push_bind(keep);
+    query.push(") FROM (SELECT CASE WHEN part_a = ");
+    query.push_bind(merged);
+    query.push(" THEN part_b ELSE part_a END AS other FROM alternates WHERE ");
+    query.push_bind(merged);
+    query.push(" IN (part_a, part_b)) x WHERE other <> ");
+    query.push_bind(keep);
+    query.push(" ON CONFLICT DO NOTHING");
+    query.build().execute(&mut *tx).await?;
+
+    let mut query = QueryBuilder::new("UPDATE part_image SET part_id = ");
+    query.push_bind(keep);
+    query.push(" WHERE part_id = ");
+    query.push_bind(merged);
+    query.push(" AND NOT EXISTS (SELECT 1 FROM part_image WHERE part_id = ");
+    query.push_bind(keep);
+    query.push(")");
+    query.build().execute(&mut *tx).await?;
+
+    let mut query = QueryBuilder::new("UPDATE stock_log SET part_id = ");
+    query.push_bind(keep);
+    query.push(" WHERE part_id = ");
+    query.push_bind(merged);
+    query.build().execute(&mut *tx).await?;
+
+    // (The version is bumped for every change, see update_item)
+    let mut query = QueryBuilder::new(
+        "UPDATE parts k SET version = k.version + 1, \
+         footprint_id = COALESCE(k.footprint_id, m.footprint_id), \
+         value = COALESCE(k.value, m.value), \
+         volt_rating = COALESCE(k.volt_rating, m.volt_rating), \
+         watt_rating = COALESCE(k.watt_rating, m.watt_rating), \
+         comments = COALESCE(k.comments, m.comments), \
+         reorder_point = COALESCE(k.reorder_point, m.reorder_point), \
+         unit_price = COALESCE(k.unit_price, m.unit_price), \
+         tags = CASE WHEN k.tags = '{}' THEN m.tags ELSE k.tags END, \
+         supplier = COALESCE(k.supplier, m.supplier), \
+         supplier_url = COALESCE(k.supplier_url, m.supplier_url) \
+         FROM parts m WHERE k.id = ",
+    );
+    query.push_bind(keep);
+    query.push(" AND m.id = ");
+    query.push_bind(merged);
+    query.build().execute(&mut *tx).await?;
+
+    // (Whatever else referenced it is gone by now, so nothing cascades)
+    let mut query = QueryBuilder::new("DELETE FROM parts WHERE id = ");
+    query.push_bind(merged);
+    query.build().execute(&mut *tx).await?;
+
+    // (Only once deleted, as MPNs are unique)
+    if old.mpn.is_none()
+        && let Some(mpn) = &other.mpn
+    {
+        let mut query = QueryBuilder::new("UPDATE parts SET mpn = ");
+        query.push_bind(mpn);
+        query.push(" WHERE id = ");
+        query.push_bind(keep);
+        query.build().execute(&mut *tx).await?;
+    }
+
+    let Some(new) = query_item(keep, &mut tx).await? else {
+        return Ok(None);
+    };
+    let mut entries = diff_items(&old, &new);
+    entries.push(StockLogEntry::text(
+        keep,
+        "merged_from",
+        None,
+        Some(match &other.mpn {
+            Some(mpn) => format!("#{} {}", merged, mpn),
+            None => format!("#{}", merged),
+        }),
+    ));
+    insert_log(&entries, user_id, &mut tx).await?;
+
+    tx.commit().await?;
+
+    Ok(Some(new))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MergeForm {
+    keep_id: i32,
+    merge_id: i32,
+}
+
+pub async fn merge_handler(
+    State(state): State<AppState>,
+    Extension(CurrentUser(user_id)): Extension<CurrentUser>,
+    headers: HeaderMap,
+    columns: Columns,
+    Form(form): Form<MergeForm>,
+) -> impl IntoResponse {
+    info!("Merging component {} into {}", form.merge_id, form.keep_id);
+
+    if form.keep_id == form.merge_id {
+        let mut headers = HeaderMap::new();
+        headers.insert("HX-Retarget", "#stage-status".parse().unwrap());
+        headers.insert("HX-Reswap", "innerHTML".parse().unwrap());
+        let response = html! {
+            p style="color: red;" { "A part can't be merged into itself." }
+        };
+        return (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            headers,
+            Html(response.into_string()),
+        )
+            .into_response();
+    }
+
+    let mut db_conn = match state.pool.acquire().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            return handle_generic_inventory_error(e);
+        }
+    };
+
+    match merge_parts(form.keep_id, form.merge_id, Some(user_id), &mut db_conn).await {
+        Ok(Some(result)) => {
+            state.list_cache.invalidate();
+            state.notify_change(Some(form.keep_id));
+            state.notify_change(Some(form.merge_id));
+
+            if wants_json(&headers) {
+                return Json(result).into_response();
+            }
+
+            let mut headers = HeaderMap::new();
+            headers.insert("HX-Trigger", "inventoryUpdated".parse().unwrap());
+            (
+                headers,
+                Html(html_table_row(&result, &state.category_units(), &columns).into_string()),
+            )
+                .into_response()
+        }
+        Ok(None) => handle_generic_inventory_error(format!(
+            "No components with ids {} and {}",
+            form.keep_id, form.merge_id
+        )),
+        Err(e) => handle_generic_inventory_error(e),
+    }
+}
+
+// Returns the number of parts changed. Everything is changed with one UPDATE,
+// the parts are only queried before and after to log the changes
+pub struct BulkOutcome {
+    pub updated: u64,
+    // Parts left as they were, see StockMove::Ambiguous
+    pub skipped: Vec<InventoryItem>,
+}
+
+pub async fn bulk_update_items(
+    ids: &[i32],
+    change: &BulkChange,
+    user_id: Option<i32>,
+    db_conn: &mut PgConnection,
+) -> Result<BulkOutcome, sqlx::Error> {
+    let mut tx = db_conn.begin().await?;
+
+    let mut query = QueryBuilder::new("SELECT 1 FROM parts WHERE deleted_at IS NULL AND id = ANY(");
+    query.push_bind(ids);
+    query.push(") FOR UPDATE");
+    query.build().execute(&mut *tx).await?;
+
+    let mut query = QueryBuilder::new("SELECT 1 FROM stock WHERE part_id = ANY(");
+    query.push_bind(ids);
+    query.push(") FOR UPDATE");
+    query.build().execute(&mut *tx).await?;
+
+    let old = query_items(ids, &mut tx).await?;
+
+    // (Moved first, so that the skipped parts keep their version too)
+    let mut skipped = vec![];
+    if let BulkChange::Location(location) = change {
+        let location_id = get_or_insert_name("locations", location, &mut tx).await?;
+        for item in &old {
+            if let StockMove::Ambiguous = move_stock(item.id, location_id, &mut tx).await? {
+                skipped.push(item.id);
+            }
+        }
+    }
+
+    // (The version is bumped for every change, see update_item)
+    let mut query = QueryBuilder::new("UPDATE parts SET version = version + 1");
+    match change {
+        BulkChange::Category(category) => {
+            let category_id = get_or_insert_category(category, &mut tx).await?;
+            query.push(", category_id = ");
+            query.push_bind(category_id);
+        }
+        BulkChange::Footprint(Some(footprint)) => {
+            let footprint_id = get_or_insert_name("footprints", footprint, &mut tx).await?;
+            query.push(", footprint_id = ");
+            query.push_bind(footprint_id);
+        }
+        BulkChange::Footprint(None) => {
+            query.push(", footprint_id = NULL");
+        }
+        BulkChange::Status(status) => {
+            query.push(", status = ");
+            query.push_bind(*status);
+        }
+        BulkChange::Location(_) => {}
+    }
+    query.push(" WHERE deleted_at IS NULL AND id = ANY(");
+    query.push_bind(ids);
+    query.push(") AND NOT id = ANY(");
+    query.push_bind(&skipped);
+    query.push(")");
+    let updated = query.build().execute(&mut *tx).await?.rows_affected();
+
+    let new = query_items(ids, &mut tx).await?;
+    let entries: Vec<StockLogEntry> = old
+        .iter()
+        .zip(&new)
+        .flat_map(|(old, new)| diff_items(old, new))
+        .collect();
+    insert_log(&entries, user_id, &mut tx).await?;
+
+    tx.commit().await?;
+
+    let skipped = old
+        .into_iter()
+        .filter(|x| skipped.contains(&x.id))
+        .collect();
+    Ok(BulkOutcome { updated, skipped })
+}
+
+pub async fn bulk_edit_handler(
+    State(state): State<AppState>,
+    Extension(CurrentUser(user_id)): Extension<CurrentUser>,
+    MultiForm(form): MultiForm<BulkEditForm>,
+) -> impl IntoResponse {
+    info!("Bulk editing components: {:?}", form);
+
+    let mut headers = HeaderMap::new();
+    headers.insert("HX-Retarget", "#stage-status".parse().unwrap());
+    headers.insert("HX-Reswap", "innerHTML".parse().unwrap());
+
+    let change = match BulkChange::parse(&form.field, &form.value) {
+        Ok(change) => change,
+        Err(msg) => {
+            let response = html! {
+                p style="color: red;" { (msg) }
+            };
+            return (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                headers,
+                Html(response.into_string()),
+            )
+                .into_response();
+        }
+    };
+
+    if form.ids.is_empty() {
+        let response = html! {
+            p style="color: red;" { "Select some parts first." }
+        };
+        return (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            headers,
+            Html(response.into_string()),
+        )
+            .into_response();
+    }
+
+    let mut db_conn = match state.pool.acquire().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            return handle_generic_inventory_error(e);
+        }
+    };
+
+    match bulk_update_items(&form.ids, &change, Some(user_id), &mut db_conn).await {
+        Ok(outcome) => {
+            state.list_cache.invalidate();
+            state.notify_change(None);
+            headers.insert("HX-Trigger", "inventoryUpdated".parse().unwrap());
+            let response = html! {
+                p { "Updated " (outcome.updated) " parts." }
+                @if !outcome.skipped.is_empty() {
+                    p style="color: red;" {
+                        "Skipped, as they are stocked in several locations: "
+                        @for (i, item) in outcome.skipped.iter().enumerate() {
+                            @if i > 0 { ", " }
+                            a href={"/locations/" (item.id)} {
+                                (item.mpn.as_deref().unwrap_or("—")) " (#" (item.id) ")"
+                            }
+                        }
+                    }
+                }
+            };
+            (headers, Html(response.into_string())).into_response()
+        }
+        Err(e) => handle_generic_inventory_error(e),
+    }
+}
+
+pub async fn edit_form_handler(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+) -> impl IntoResponse {
+    info!("Generating edit form for component {}", id);
+
+    let mut db_conn = match state.pool.acquire().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            return handle_generic_inventory_error(e);
+        }
+    };
+
+    match query_item(id, &mut db_conn).await {
+        Ok(Some(result)) => {
+            Html(html_edit_row(&result, &state.category_units()).into_string()).into_response()
+        }
+        Ok(None) => handle_generic_inventory_error(format!("No component with id {}", id)),
+        Err(e) => handle_generic_inventory_error(e),
+    }
+}
+
+pub async fn item_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    columns: Columns,
+    Path(id): Path<i32>,
+) -> impl IntoResponse {
+    info!("Querying component {}", id);
+
+    let mut db_conn = match state.pool.acquire().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            return handle_generic_inventory_error(e);
+        }
+    };
+
+    match query_item(id, &mut db_conn).await {
+        Ok(Some(item)) if wants_json(&headers) => Json(item).into_response(),
+        Ok(Some(item)) => {
+            Html(html_table_row(&item, &state.category_units(), &columns).into_string())
+                .into_response()
+        }
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => handle_generic_inventory_error(e),
+    }
+}
+
+// Scanned labels either carry the part id (as printed by us) or the MPN (as on
+// the manufacturer's reels and bags)
+async fn query_scanned_item(
+    code: &str,
+    db_conn: &mut PgConnection,
+) -> Result<Option<InventoryItem>, sqlx::Error> {
+    let mut query = QueryBuilder::new("SELECT * FROM inventory WHERE deleted_at IS NULL AND ");
+    if code.chars().all(|x| x.is_ascii_digit())
+        && let Ok(id) = code.parse::<i32>()
+    {
+        query.push("id = ");
+        query.push_bind(id);
+    } else {
+        query.push("mpn = ");
+        query.push_bind(code);
+    }
+    query
+        .build_query_as::<InventoryItem>()
+        .fetch_optional(&mut *db_conn)
+        .await
+}
+
+pub async fn scan_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    columns: Columns,
+    Path(code): Path<String>,
+) -> impl IntoResponse {
+    let code = code.trim();
+    info!("Looking up scanned code {:?}", code);
+
+    let mut db_conn = match state.pool.acquire().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            return handle_generic_inventory_error(e);
+        }
+    };
+
+    let item = match query_scanned_item(code, &mut db_conn).await {
+        Ok(item) => item,
+        Err(e) => {
+            return handle_generic_inventory_error(e);
+        }
+    };
+
+    if wants_json(&headers) {
+        return match item {
+            Some(item) => Json(item).into_response(),
+            None => StatusCode::NOT_FOUND.into_response(),
+        };
+    }
+
+    // (Replaces the results, as the row's element ids must be unique in the page)
+    let response = html! {
+        table class="striped" {
+            (html_static_table_header(&columns))
+            tbody id="results-body" {
+                @if let Some(item) = &item {
+                    (html_table_row(item, &state.category_units(), &columns))
+                } @else {
+                    tr {
+                        td colspan="11" {
+                            "No part matches \"" (code) "\". "
+                            button
+                            class="secondary"
+                            data-mpn=(code)
+                            onclick="createScanned(this)" {
+                                "Create it?"
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    };
+
+    Html(response.into_string()).into_response()
+}
+
+// Without a location, quantity is the total across all locations, and the
+// primary location is adjusted to match it (as far as it can go without
+// becoming negative)
+async fn set_quantity(
+    id: i32,
+    quantity: i32,
+    location: Option<&str>,
+    user_id: Option<i32>,
+    db_conn: &mut PoolConnection<Postgres>,
+) -> Result<Option<InventoryItem>, sqlx::Error> {
+    let mut tx = db_conn.begin().await?;
+
+    lock_item(id, &mut tx).await?;
+    let Some(old) = query_item(id, &mut tx).await? else {
+        return Ok(None);
+    };
+
+    if let Some(location) = location {
+        let location_id = get_or_insert_name("locations", location, &mut tx).await?;
+        let mut query =
+            QueryBuilder::new("INSERT INTO stock (part_id, location_id, quantity) VALUES (");
+        let mut values = query.separated(", ");
+        values.push_bind(id);
+        values.push_bind(location_id);
+        values.push_bind(quantity);
+        query.push(
+            ") ON CONFLICT (part_id, location_id) DO UPDATE SET quantity = EXCLUDED.quantity",
+        );
+        query.build().execute(&mut *tx).await?;
+    } else {
+        let mut query = QueryBuilder::new("UPDATE stock SET quantity = GREATEST(");
+        query.push_bind(quantity);
+        query.push(" - (SELECT COALESCE(SUM(quantity), 0) FROM stock WHERE part_id = ");
+        query.push_bind(id);
+        query.push(" AND id <> ");
+        push_primary_stock_id(&mut query, id);
+        query.push("), 0) WHERE id = ");
+        push_primary_stock_id(&mut query, id);
+        let result = query.build().execute(&mut *tx).await?;
+
+        // (The part had no stock at all yet)
+        if result.rows_affected() == 0 {
+            let mut query = QueryBuilder::new("INSERT INTO stock (part_id, quantity) VALUES (");
+            query.push_bind(id);
+            query.push(", ");
+            query.push_bind(quantity);
+            query.push(")");
+            query.build().execute(&mut *tx).await?;
+        }
+    }
+
+    // Staged parts can't be more than there are in stock (the stock_clamp_staged
+    // trigger also does this, for changes made elsewhere)
+    let mut query = QueryBuilder::new("UPDATE stock SET staged = quantity WHERE part_id = ");
+    query.push_bind(id);
+    query.push(" AND staged > quantity");
+    query.build().execute(&mut *tx).await?;
+
+    let result = query_item(id, &mut tx).await?;
+    if let Some(new) = &result {
+        insert_log(&diff_items(&old, new), user_id, &mut tx).await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(result)
+}
+
+pub async fn set_quantity_handler(
+    State(state): State<AppState>,
+    Extension(CurrentUser(user_id)): Extension<CurrentUser>,
+    columns: Columns,
+    Path(id): Path<i32>,
+    Form(form): Form<SetQuantityForm>,
+) -> impl IntoResponse {
+    info!("Setting quantity of component {} to {}", id, form.quantity);
+
+    let Some(quantity) = i32::try_from(form.quantity).ok().filter(|x| *x >= 0) else {
+        let mut headers = HeaderMap::new();
+        headers.insert("HX-Retarget", "#stage-status".parse().unwrap());
+        headers.insert("HX-Reswap", "innerHTML".parse().unwrap());
+        return (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            headers,
+            Html(format!("Invalid quantity {}", form.quantity)),
+        )
+            .into_response();
+    };
+
+    let mut db_conn = match state.pool.acquire().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            return handle_generic_inventory_error(e);
+        }
+    };
+
+    let location = form.location.as_deref().and_then(non_empty);
+    match set_quantity(id, quantity, location, Some(user_id), &mut db_conn).await {
+        Ok(Some(result)) => {
+            state.notify_change(Some(id));
+            let mut headers = HeaderMap::new();
+            headers.insert("HX-Trigger", "stageUpdated".parse().unwrap());
+            (
+                headers,
+                Html(html_table_row(&result, &state.category_units(), &columns).into_string()),
+            )
+                .into_response()
+        }
+        Ok(None) => handle_generic_inventory_error(format!("No component with id {}", id)),
+        Err(e) => handle_generic_inventory_error(e),
+    }
+}
+
+// (Only logged if it actually changed, e.g. not when restoring twice)
+async fn set_deleted(
+    id: i32,
+    deleted: bool,
+    user_id: Option<i32>,
+    db_conn: &mut PoolConnection<Postgres>,
+) -> Result<(), sqlx::Error> {
+    let mut tx = db_conn.begin().await?;
+
+    let mut query = QueryBuilder::new("UPDATE parts SET deleted_at = ");
+    if deleted {
+        query.push("NOW()");
+    } else {
+        query.push("NULL");
+    }
+    query.push(" WHERE id = ");
+    query.push_bind(id);
+    query.push(if deleted {
+        " AND deleted_at IS NULL"
+    } else {
+        " AND deleted_at IS NOT NULL"
+    });
+    let changed = query.build().execute(&mut *tx).await?.rows_affected() > 0;
+
+    if changed {
+        let field = if deleted { "deleted" } else { "restored" };
+        insert_log(
+            &[StockLogEntry::text(id, field, None, None)],
+            user_id,
+            &mut tx,
+        )
+        .await?;
+    }
+
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn delete_handler(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+    Extension(CurrentUser(user_id)): Extension<CurrentUser>,
+) -> impl IntoResponse {
+    info!("Deleting component {}", id);
+
+    let mut db_conn = match state.pool.acquire().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            return handle_generic_inventory_error(e);
+        }
+    };
+
+    match set_deleted(id, true, Some(user_id), &mut db_conn).await {
+        Ok(()) => {
+            state.list_cache.invalidate();
+            state.notify_change(Some(id));
+            // (inventoryDeleted carries the id, so the page may offer to undo)
+            let mut headers = HeaderMap::new();
+            headers.insert(
+                "HX-Trigger",
+                format!(
+                    "{{\"inventoryUpdated\": \"\", \"inventoryDeleted\": \"{}\"}}",
+                    id
+                )
+                .parse()
+                .unwrap(),
+            );
+            (headers, Html(String::new())).into_response()
+        }
+        Err(e) => handle_generic_inventory_error(e),
+    }
+}
+
+pub async fn restore_handler(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+    Extension(CurrentUser(user_id)): Extension<CurrentUser>,
+) -> impl IntoResponse {
+    info!("Restoring component {}", id);
+
+    let mut db_conn = match state.pool.acquire().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            return handle_generic_inventory_error(e);
+        }
+    };
+
+    match set_deleted(id, false, Some(user_id), &mut db_conn).await {
+        Ok(()) => {
+            state.list_cache.invalidate();
+            state.notify_change(Some(id));
+            let mut headers = HeaderMap::new();
+            headers.insert("HX-Trigger", "inventoryUpdated".parse().unwrap());
+            (headers, Html(String::new())).into_response()
+        }
+        Err(e) => handle_generic_inventory_error(e),
+    }
+}
+
+pub enum StageOutcome {
+    Staged {
+        staged: i32,
+        available: i32,
+        qty_unit: String,
+        // How many were actually staged (negative if unstaged), which is less
+        // than asked for if there weren't as many
+        delta: i32,
+    },
+    // Everything in stock is staged already, so nothing was added (and none
+    // is available)
+    AtMax {
+        staged: i32,
+        qty_unit: String,
+    },
+    // (Or the part doesn't exist)
+    NoStock,
+}
+
+impl StageOutcome {
+    pub fn delta(&self) -> i32 {
+        match self {
+            StageOutcome::Staged { delta, .. } => *delta,
+            _ => 0,
+        }
+    }
+}
+
+// Within a transaction of its own, or of the caller if it's in one. Stock
+// reserved for projects isn't staged, but for the given project's own
+pub async fn update_stage(
+    id: i32,
+    number: i32,
+    project_id: Option<i32>,
+    user_id: Option<i32>,
+    db_conn: &mut PgConnection,
+) -> Result<StageOutcome, sqlx::Error> {
+    let mut tx = db_conn.begin().await?;
+
+    lock_item(id, &mut tx).await?;
+    let Some(old) = query_item(id, &mut tx).await? else {
+        return Ok(StageOutcome::NoStock);
+    };
+
+    // Parts are pulled from the locations with the most available stock first,
+    // and returned to the ones with the most staged, as many locations as needed
+    let mut query = QueryBuilder::new("SELECT id, quantity, COALESCE(staged, 0) FROM stock");
+    query.push(" WHERE quantity IS NOT NULL AND part_id = ");
+    query.push_bind(id);
+    if number > 0 {
+        query.push(" ORDER BY quantity - COALESCE(staged, 0) DESC, id");
+    } else {
+        query.push(" ORDER BY COALESCE(staged, 0) DESC, id");
+    }
+    let rows = query
+        .build_query_as::<(i32, i32, i32)>()
+        .fetch_all(&mut *tx)
+        .await?;
+    if rows.is_empty() {
+        return Ok(StageOutcome::NoStock);
+    }
+
+    // (Reservations only change with the part locked, see projects.rs)
+    let mut query = QueryBuilder::new(
+        "SELECT COALESCE(SUM(reserved), 0)::INTEGER FROM project_parts WHERE part_id = ",
+    );
+    query.push_bind(id);
+    if let Some(project_id) = project_id {
+        query.push(" AND project_id <> ");
+        query.push_bind(project_id);
+    }
+    let reserved = query
+        .build_query_scalar::<i32>()
+        .fetch_one(&mut *tx)
+        .await?;
+
+    let wanted = if number > 0 {
+        let unreserved = rows.iter().map(|(_, q, s)| q - s).sum::<i32>() - reserved;
+        number.min(unreserved.max(0))
+    } else {
+        number
+    };
+
+    // Clamped so that staged stays between 0 and quantity
+    let mut remaining = wanted;
+    for (stock_id, quantity, staged) in rows {
+        let change = if remaining > 0 {
+            remaining.min(quantity - staged)
+        } else {
+            remaining.max(-staged)
+        };
+        if change == 0 {
+            continue;
+        }
+        remaining -= change;
+
+        let mut query = QueryBuilder::new("UPDATE stock SET staged = ");
+        query.push_bind(staged + change);
+        query.push(" WHERE id = ");
+        query.push_bind(stock_id);
+        query.build().execute(&mut *tx).await?;
+    }
+    let delta = wanted - remaining;
+
+    let new = query_item(id, &mut tx).await?;
+    let staged = new.as_ref().map(|x| x.staged.unwrap_or(0));
+
+    if delta != 0 {
+        insert_log(
+            &[StockLogEntry::count(id, "staged", old.staged, staged)],
+            user_id,
+            &mut tx,
+        )
+        .await?;
+    }
+
+    tx.commit().await?;
+
+    let staged = staged.unwrap_or(0);
+    let qty_unit = old.qty_unit.clone();
+    if number > 0 && delta == 0 {
+        Ok(StageOutcome::AtMax { staged, qty_unit })
+    } else {
+        let available = new.map_or(0, |x| x.available());
+        Ok(StageOutcome::Staged {
+            staged,
+            available,
+            qty_unit,
+            delta,
+        })
+    }
+}
+
+// Commits the stage of every part, or only of the given one. Returns how many
+// parts were committed (each may be staged from several locations)
+async fn confirm_stage(
+    pool: &Pool<Postgres>,
+    part_id: Option<i32>,
+    user_id: Option<i32>,
+) -> Result<usize, sqlx::Error> {
+    // (If anything fails, dropping the transaction rolls it back)
+    let mut tx = pool.begin().await?;
+
+    // Only touch rows with something staged, so the rest can never be modified
+    let mut query = QueryBuilder::new("SELECT id, part_id, quantity, staged FROM stock");
+    query.push(" WHERE staged IS NOT NULL AND staged > 0 AND staged <= quantity");
+    if let Some(part_id) = part_id {
+        query.push(" AND part_id = ");
+        query.push_bind(part_id);
+    }
+    query.push(" FOR UPDATE");
+    let rows = query
+        .build_query_as::<(i32, i32, i32, i32)>()
+        .fetch_all(&mut *tx)
+        .await?;
+
+    if rows.is_empty() {
+        return Ok(0);
+    }
+
+    let mut query = QueryBuilder::new("UPDATE stock SET");
+    query.push(" quantity = quantity - COALESCE(staged, 0),");
+    query.push(" staged = NULL");
+    query.push(" WHERE id = ANY(");
+    query.push_bind(rows.iter().map(|x| x.0).collect::<Vec<i32>>());
+    query.push(")");
+
+    query.build().execute(&mut *tx).await?;
+
+    let entries: Vec<StockLogEntry> = rows
+        .iter()
+        .flat_map(|&(_, part_id, quantity, staged)| {
+            [
+                StockLogEntry::count(part_id, "quantity", Some(quantity), Some(quantity - staged)),
+                StockLogEntry::count(part_id, "staged", Some(staged), None),
+            ]
+        })
+        .collect();
+    insert_log(&entries, user_id, &mut tx).await?;
+
+    tx.commit().await?;
+
+    let parts: HashSet<i32> = rows.iter().map(|x| x.1).collect();
+    Ok(parts.len())
+}
+
+pub async fn confirm_stage_handler(
+    State(state): State<AppState>,
+    Extension(CurrentUser(user_id)): Extension<CurrentUser>,
+) -> impl IntoResponse {
+    info!("Confirming stage");
+
+    match confirm_stage(&state.pool, None, Some(user_id)).await {
+        Ok(num) => {
+            info!("Committed {} parts", num);
+            metrics::counter!("staging_operations_total", "operation" => "confirm").increment(1);
+            state.notify_change(None);
+            let mut headers = HeaderMap::new();
+            headers.insert("HX-Trigger", "inventoryUpdated".parse().unwrap());
+            (headers, Html(format!("Committed {} parts", num))).into_response()
+        }
+        Err(e) => handle_generic_inventory_error(e),
+    }
+}
+
+pub async fn confirm_part_handler(
+    State(state): State<AppState>,
+    Extension(CurrentUser(user_id)): Extension<CurrentUser>,
+    columns: Columns,
+    Path(id): Path<i32>,
+) -> impl IntoResponse {
+    info!("Confirming stage of component {}", id);
+
+    if let Err(e) = confirm_stage(&state.pool, Some(id), Some(user_id)).await {
+        return handle_generic_inventory_error(e);
+    }
+    metrics::counter!("staging_operations_total", "operation" => "confirm").increment(1);
+    state.notify_change(Some(id));
+
+    let mut db_conn = match state.pool.acquire().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            return handle_generic_inventory_error(e);
+        }
+    };
+
+    match query_item(id, &mut db_conn).await {
+        Ok(Some(result)) => {
+            let mut headers = HeaderMap::new();
+            headers.insert("HX-Trigger", "stageUpdated".parse().unwrap());
+            (
+                headers,
+                Html(html_table_row(&result, &state.category_units(), &columns).into_string()),
+            )
+                .into_response()
+        }
+        Ok(None) => handle_generic_inventory_error(format!("No component with id {}", id)),
+        Err(e) => handle_generic_inventory_error(e),
+    }
+}
+
+// Clears the stage of every part, returning how many parts had something staged
+async fn unstage_all(pool: &Pool<Postgres>, user_id: Option<i32>) -> Result<usize, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let rows = QueryBuilder::new("SELECT id, part_id, staged FROM stock")
+        .push(" WHERE staged IS NOT NULL FOR UPDATE")
+        .build_query_as::<(i32, i32, i32)>()
+        .fetch_all(&mut *tx)
+        .await?;
+
+    if rows.is_empty() {
+        return Ok(0);
+    }
+
+    let mut query = QueryBuilder::new("UPDATE stock SET staged = NULL WHERE id = ANY(");
+    query.push_bind(rows.iter().map(|x| x.0).collect::<Vec<i32>>());
+    query.push(")");
+    query.build().execute(&mut *tx).await?;
+
+    // (Rows with nothing staged are cleared too, but there's nothing to log)
+    let entries: Vec<StockLogEntry> = rows
+        .iter()
+        .filter(|x| x.2 > 0)
+        .map(|&(_, part_id, staged)| StockLogEntry::count(part_id, "staged", Some(staged), None))
+        .collect();
+    insert_log(&entries, user_id, &mut tx).await?;
+
+    tx.commit().await?;
+
+    let parts: HashSet<i32> = entries.iter().map(|x| x.part_id).collect();
+    Ok(parts.len())
+}
+
+pub async fn unstage_all_handler(
+    State(state): State<AppState>,
+    Extension(CurrentUser(user_id)): Extension<CurrentUser>,
+) -> impl IntoResponse {
+    info!("Clearing stage");
+
+    match unstage_all(&state.pool, Some(user_id)).await {
+        Ok(num) => {
+            metrics::counter!("staging_operations_total", "operation" => "clear").increment(1);
+            state.notify_change(None);
+            let mut headers = HeaderMap::new();
+            headers.insert("HX-Trigger", "inventoryUpdated".parse().unwrap());
+            (headers, Html(format!("Cleared {} parts", num))).into_response()
+        }
+        Err(e) => handle_generic_inventory_error(e),
+    }
+}
+
+pub async fn stage_summary_handler(State(state): State<AppState>) -> impl IntoResponse {
+    info!("Performing stage summary query");
+
+    let mut db_conn = match state.pool.acquire().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            return handle_generic_inventory_error(e);
+        }
+    };
+
+    let mut query =
+        QueryBuilder::new("SELECT COUNT(DISTINCT part_id), COALESCE(SUM(staged), 0) FROM stock");
+    query.push(" WHERE staged > 0");
 
-    let mut db_conn = match state.pool.acquire().await {
-        Ok(conn) => conn,
+    let (parts, units) = match query
+        .build_query_as::<(i64, i64)>()
+        .fetch_one(db_conn.as_mut())
+        .await
+    {
+        Ok(result) => result,
         Err(e) => {
-            return (HeaderMap::new(), handle_generic_inventory_error(e));
+            return handle_generic_inventory_error(e);
         }
     };
 
-    let mut query = QueryBuilder::new("UPDATE stock SET");
-    query.push(" quantity = quantity - COALESCE(staged, 0),");
-    query.push(" staged = NULL");
-    query.push(" WHERE staged <= quantity");
+    Html(
+        html! {
+            @if parts > 0 {
+                (parts) " parts / " (units) " units staged"
+            }
+        }
+        .into_string(),
+    )
+    .into_response()
+}
 
-    match query.build().execute(db_conn.as_mut()).await {
-        Ok(_) => {
-            let mut headers = HeaderMap::new();
-            headers.insert("HX-Trigger", "inventoryUpdated".parse().unwrap());
-            (headers, Html(String::from("OK")))
+// Replaces the staged amount of the row, so the failure shows next to the part
+fn stage_error_response(id: i32, e: sqlx::Error) -> axum::response::Response {
+    error!("Error while staging component {}: {}", id, e);
+    let response = html! {
+        span id={"staged-" (id)} style="color: red;" title="Staging failed, try again later." {
+            "( FAILED )"
         }
-        Err(e) => (HeaderMap::new(), handle_generic_inventory_error(e)),
-    }
+    };
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Html(response.into_string()),
+    )
+        .into_response()
 }
 
 pub async fn staging_handler(
     State(state): State<AppState>,
+    Extension(CurrentUser(user_id)): Extension<CurrentUser>,
     Path(id): Path<i32>,
+    Form(stage): Form<StageForm>,
 ) -> impl IntoResponse {
-    info!("Staging component {}", id);
+    let amount = stage.amount();
+    info!("Staging {} of component {}", amount, id);
 
     let mut db_conn = match state.pool.acquire().await {
         Ok(conn) => conn,
@@ -399,14 +4390,26 @@ pub async fn staging_handler(
         }
     };
 
-    Html(html_stage(id, update_stage(id, 1, &mut db_conn).await).into_string())
+    let staged = match update_stage(id, amount, None, Some(user_id), &mut db_conn).await {
+        Ok(staged) => staged,
+        Err(e) => return stage_error_response(id, e),
+    };
+    metrics::counter!("staging_operations_total", "operation" => "stage").increment(1);
+
+    state.notify_change(Some(id));
+    let mut headers = HeaderMap::new();
+    headers.insert("HX-Trigger", "stageUpdated".parse().unwrap());
+    (headers, Html(html_stage_outcome(id, staged).into_string())).into_response()
 }
 
 pub async fn unstaging_handler(
     State(state): State<AppState>,
+    Extension(CurrentUser(user_id)): Extension<CurrentUser>,
     Path(id): Path<i32>,
+    Form(stage): Form<StageForm>,
 ) -> impl IntoResponse {
-    info!("Unstaging component {}", id);
+    let amount = stage.amount();
+    info!("Unstaging {} of component {}", amount, id);
 
     let mut db_conn = match state.pool.acquire().await {
         Ok(conn) => conn,
@@ -415,34 +4418,35 @@ pub async fn unstaging_handler(
         }
     };
 
-    Html(html_stage(id, update_stage(id, -1, &mut db_conn).await).into_string())
+    let staged = match update_stage(id, -amount, None, Some(user_id), &mut db_conn).await {
+        Ok(staged) => staged,
+        Err(e) => return stage_error_response(id, e),
+    };
+    metrics::counter!("staging_operations_total", "operation" => "unstage").increment(1);
+
+    state.notify_change(Some(id));
+    let mut headers = HeaderMap::new();
+    headers.insert("HX-Trigger", "stageUpdated".parse().unwrap());
+    (headers, Html(html_stage_outcome(id, staged).into_string())).into_response()
 }
 
-pub async fn download_backup_handler() -> impl IntoResponse {
+pub async fn download_backup_handler(State(state): State<AppState>) -> impl IntoResponse {
     info!("Generating database backup");
+    let config = &state.config;
     let output = tokio::process::Command::new("pg_dump")
-        .env("PGPASSWORD", dotenvy::var("DB_PASSWORD").unwrap().as_str())
-        .args(&[
+        .env("PGPASSWORD", config.db_password.as_str())
+        .args([
             "-h",
-            dotenvy::var("DB_HOST").unwrap().as_str(),
+            config.db_host.as_str(),
             "-U",
-            dotenvy::var("DB_USER").unwrap().as_str(),
+            config.db_user.as_str(),
             "-d",
-            dotenvy::var("DB_NAME").unwrap().as_str(),
+            config.db_name.as_str(),
             "-p",
-            dotenvy::var("DB_PORT")
-                .unwrap_or(String::from("5432"))
-                .as_str(),
-            "-t",
-            "parts",
-            "-t",
-            "stock",
-            "-t",
-            "locations",
-            "-t",
-            "categories",
-            "-t",
-            "footprints",
+            config.db_port.unwrap_or(5432).to_string().as_str(),
+            // (Every table, including _sqlx_migrations so that a restored database
+            // isn't migrated again. Sessions live in a schema of their own)
+            "--schema=public",
             "--clean",
             "--if-exists",
             "--inserts",
@@ -463,7 +4467,7 @@ pub async fn download_backup_handler() -> impl IntoResponse {
                     .unwrap(),
             );
 
-            (headers, sql.to_string())
+            (headers, sql.to_string()).into_response()
         }
         Ok(out) => {
             error!(
@@ -473,62 +4477,250 @@ pub async fn download_backup_handler() -> impl IntoResponse {
             );
 
             headers.insert(header::CONTENT_TYPE, "text/plain".parse().unwrap());
-            (headers, "Backup failed".to_string())
+            (StatusCode::INTERNAL_SERVER_ERROR, headers, "Backup failed").into_response()
         }
         Err(e) => {
             error!("Backup failed: {}", e);
             headers.insert(header::CONTENT_TYPE, "text/plain".parse().unwrap());
-            (headers, "Backup failed".to_string())
+            (StatusCode::INTERNAL_SERVER_ERROR, headers, "Backup failed").into_response()
         }
     }
 }
 
-fn html_stage(id: i32, number: Option<i32>) -> Markup {
+fn html_stage(id: i32, number: Option<i32>, qty_unit: &str, hint: Option<&str>) -> Markup {
     html!(
         span id={"staged-" (id)} style="color: red;" {
             @if let Some(staged) = number {
                 @if staged > 0 {
-                    "(" (staged) ")"
+                    "(" (staged) " " (qty_unit) ")"
+                    // (Commits only this part, replacing its row)
+                    " "
+                    a
+                    href="#"
+                    title="Commit the staged parts"
+                    hx-post={"/api/inventory/confirm/" (id)}
+                    hx-target="closest tr"
+                    hx-swap="outerHTML" {
+                        "✓"
+                    }
                 } @else if staged < 0 {
                     "( ERROR )"
                 }
             }
+            @if let Some(hint) = hint {
+                " "
+                small style="color: var(--pico-muted-color);" { (hint) }
+            }
+        }
+    )
+}
+
+// Out of band after staging, which only swaps the staged amount otherwise
+fn html_available(id: i32, available: i32, oob: bool) -> Markup {
+    html!(
+        span id={"available-" (id)} hx-swap-oob=[oob.then_some("true")] {
+            (available)
+        }
+    )
+}
+
+// After staging, so that a click which changed nothing doesn't look lost
+fn html_stage_outcome(id: i32, outcome: StageOutcome) -> Markup {
+    match outcome {
+        StageOutcome::Staged {
+            staged,
+            available,
+            qty_unit,
+            ..
+        } => html!((html_stage(id, Some(staged), &qty_unit, None))(
+            html_available(id, available, true)
+        )),
+        StageOutcome::AtMax { staged, qty_unit } => {
+            html!((html_stage(id, Some(staged), &qty_unit, Some("at max")))(
+                html_available(id, 0, true)
+            ))
+        }
+        StageOutcome::NoStock => html_stage(id, None, "", Some("no stock")),
+    }
+}
+
+// Out of band for later pages, which add to the rows shown
+fn html_results_count(shown: usize, total: i64, oob: bool) -> Markup {
+    html!(
+        span id="results-count" hx-swap-oob=[oob.then_some("true")] {
+            @if total == 0 {
+                "No results"
+            } @else {
+                "Showing 1–" (shown) " of " (total)
+            }
+        }
+    )
+}
+
+// Loads the next page once scrolled into view, unless this one wasn't full
+fn html_more_row(search: &SearchForm, num_results: usize) -> Markup {
+    let page_size = search.page_size();
+    let page_vals = format!(
+        "{{\"page\": {}, \"page_size\": {}}}",
+        search.page() + 1,
+        page_size
+    );
+
+    html!(
+        @if num_results >= page_size as usize {
+            tr
+            id="results-more"
+            hx-get="/api/inventory/search"
+            hx-include="#search-form"
+            hx-vals=(page_vals)
+            hx-trigger="revealed"
+            hx-target="this"
+            hx-swap="outerHTML" {
+                td colspan="11" aria-busy="true" {
+                    "Loading more results..."
+                }
+            }
+        }
+    )
+}
+
+fn html_import_report(rows: &[ImportRow], dry_run: bool, units: &CategoryUnits) -> Markup {
+    let num_errors = rows.iter().filter(|x| !x.errors.is_empty()).count();
+
+    html!(
+        article {
+            @if num_errors > 0 {
+                p style="color: red;" {
+                    (num_errors) " rows have errors, nothing was imported."
+                }
+            } @else if dry_run {
+                p {
+                    (rows.len()) " rows would be imported."
+                }
+            }
+            div class="overflow-auto" {
+                table class="striped" {
+                    thead {
+                        tr {
+                            th scope="col" { "Line" }
+                            th scope="col" { "MPN" }
+                            th scope="col" { "Category" }
+                            th scope="col" { "Footprint" }
+                            th scope="col" { "Value" }
+                            th scope="col" { "Location" }
+                            th scope="col" { "Qty." }
+                            th scope="col" { "Comments" }
+                            th scope="col" { "Errors" }
+                        }
+                    }
+                    tbody {
+                        @for row in rows {
+                            tr {
+                                th scope="row" { (row.line) }
+                                @if let Some(item) = &row.item {
+                                    td { (item.mpn) }
+                                    td { (item.category) }
+                                    td { (item.footprint) }
+                                    td style="white-space: pre;" {
+                                        @if let Some(value) = parse_multiple_value(&item.value) {
+                                            (format_value(units, &item.category, value))
+                                        } @else {
+                                            (item.value)
+                                        }
+                                    }
+                                    td { (item.location) }
+                                    td { (item.quantity) }
+                                    td { (item.comments) }
+                                } @else {
+                                    td colspan="7" { "—" }
+                                }
+                                td style="color: red;" {
+                                    @for error in &row.errors {
+                                        (error) br;
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
         }
     )
 }
 
-pub fn html_table_header_row(id: &'static str, content: &'static str, sort: &String) -> Markup {
+pub fn html_table_header_row(
+    column: SortColumn,
+    content: &str,
+    (sort, dir): (SortColumn, SortDir),
+) -> Markup {
+    let id = column.as_str();
     let style_str = format!(
         "cursor: pointer; {}",
-        if sort == id {
+        if sort == column {
             ""
         } else {
             "font-weight: normal"
         }
     );
+    let aria_sort = match (sort == column, dir) {
+        (false, _) => "none",
+        (true, SortDir::Asc) => "ascending",
+        (true, SortDir::Desc) => "descending",
+    };
 
+    // The button is inside, as a header with role="button" would no longer be
+    // announced as a column header (nor its aria-sort)
     html! {
         th
             id={"sort-" (id)}
             onclick={"sortBy('" (id) "')"}
             style=(style_str)
             scope="col"
+            aria-sort=(aria_sort)
             {
-                (content)
+                span
+                role="button"
+                tabindex="0"
+                aria-label={"Sort by " (content)}
+                onkeydown={"if (event.key === 'Enter' || event.key === ' ') { event.preventDefault(); sortBy('" (id) "'); }"} {
+                    (content)
+                }
             }
     }
 }
 
-pub fn html_table_header(sort: &String) -> Markup {
+pub fn html_table_header(sort: (SortColumn, SortDir), columns: &Columns) -> Markup {
     html!(
     thead {
         tr {
-            (html_table_header_row("mpn", "MPN", sort))
-            (html_table_header_row("category", "Category", sort))
-            (html_table_header_row("footprint", "Footprint", sort))
-            (html_table_header_row("comments", "Comments", sort))
-            (html_table_header_row("value", "Value", sort))
-            (html_table_header_row("quantity", "Qty.", sort))
+            (html_table_header_row(SortColumn::Mpn, "MPN", sort))
+            (html_table_header_row(SortColumn::Category, "Category", sort))
+            @if columns.footprint {
+                (html_table_header_row(SortColumn::Footprint, "Footprint", sort))
+            }
+            @if columns.comments {
+                (html_table_header_row(SortColumn::Comments, "Comments", sort))
+            }
+            @if columns.value {
+                (html_table_header_row(SortColumn::Value, "Value", sort))
+            }
+            @if columns.voltage_rating {
+                (html_table_header_row(SortColumn::VoltageRating, "Voltage", sort))
+            }
+            @if columns.power_rating {
+                (html_table_header_row(SortColumn::PowerRating, "Power", sort))
+            }
+            @if columns.location {
+                (html_table_header_row(SortColumn::Location, "Location", sort))
+            }
+            @if columns.quantity {
+                (html_table_header_row(SortColumn::Quantity, "Avail. / Qty.", sort))
+            }
+            @if columns.stock_value {
+                th style="font-weight: normal" scope="col" {
+                    "Stock value"
+                }
+            }
             th style="font-weight: normal" {
                 "Action"
             }
@@ -536,69 +4728,866 @@ pub fn html_table_header(sort: &String) -> Markup {
     })
 }
 
-pub fn html_table_row(result: &InventoryItem) -> Markup {
-    const STAGING_BUTTON_STYLE: &'static str =
+// Header for tables which can't be sorted
+pub fn html_static_table_header(columns: &Columns) -> Markup {
+    html!(
+    thead {
+        tr {
+            th scope="col" { "MPN" }
+            th scope="col" { "Category" }
+            @if columns.footprint { th scope="col" { "Footprint" } }
+            @if columns.comments { th scope="col" { "Comments" } }
+            @if columns.value { th scope="col" { "Value" } }
+            @if columns.voltage_rating { th scope="col" { "Voltage" } }
+            @if columns.power_rating { th scope="col" { "Power" } }
+            @if columns.location { th scope="col" { "Location" } }
+            @if columns.quantity { th scope="col" { "Avail. / Qty." } }
+            @if columns.stock_value { th scope="col" { "Stock value" } }
+            th scope="col" { "Action" }
+        }
+    })
+}
+
+pub fn html_table_row(result: &InventoryItem, units: &CategoryUnits, columns: &Columns) -> Markup {
+    const STAGING_BUTTON_STYLE: &str =
         "padding: 0rem; width: 1.5rem; height: 1.5rem; vertical-align: middle;";
+    const STAGING_AMOUNT_STYLE: &str =
+        "padding: 0rem 0.25rem; margin: 0; width: 4rem; height: 1.5rem; vertical-align: middle;";
+    const VALUE_STYLE: &str =
+        "text-align: right; font-family: monospace; font-size: 1.3em; white-space: pre; width: 1%";
+
+    // (For the labels of the buttons, which only show a symbol)
+    let name = match &result.mpn {
+        Some(mpn) => mpn.clone(),
+        None => format!("part #{}", result.id),
+    };
 
     html!(
-        tr {
+        // (Focusable, so that it can be staged from the keyboard, see inventory.html)
+        tr
+        id={"part-" (result.id)}
+        class=[(result.status == PartStatus::Obsolete).then_some("obsolete")]
+        tabindex="0" {
             th scope="row" {
-                @if let Some(mpn) = &result.mpn {
-                    (mpn)
-                } @else {
-                    "—"
+                @if result.has_image {
+                    a href={"/api/inventory/image/" (result.id)} target="_blank" {
+                        img
+                        src={"/api/inventory/image/" (result.id)}
+                        alt="Photo"
+                        loading="lazy"
+                        style="height: 2rem; margin-right: 0.5rem; vertical-align: middle;";
+                    }
+                }
+                // (Only shown in the inventory table, see style.css)
+                input
+                type="checkbox"
+                class="bulk-select"
+                form="bulk-form"
+                name="ids"
+                value=(result.id)
+                aria-label={"Select " (name)};
+                a href={"/part/" (result.id)} {
+                    @if let Some(mpn) = &result.mpn {
+                        (mpn)
+                    } @else {
+                        "—"
+                    }
+                }
+                // (Most parts are active, so only the others are marked)
+                @if result.status != PartStatus::Active {
+                    " "
+                    small class={"status status-" (result.status.as_str())} {
+                        (result.status.label())
+                    }
+                }
+                // (The staging buttons still need somewhere to show the amount)
+                @if !columns.quantity {
+                    " "
+                    (html_stage(result.id, result.staged, &result.qty_unit, None))
                 }
             }
             td {
                 (result.category)
             }
-            td {
-                @if let Some(footprint) = &result.footprint {
-                    (footprint)
-                } @else {
-                    "—"
+            @if columns.footprint {
+                td {
+                    @if let Some(footprint) = &result.footprint {
+                        (footprint)
+                    } @else {
+                        "—"
+                    }
                 }
             }
-            td {
-                @if let Some(comments) = &result.comments {
-                    (comments)
-                } @ else {
-                    "—"
+            @if columns.comments {
+                td {
+                    @if let Some(comments) = &result.comments {
+                        (comments)
+                    } @ else {
+                        "—"
+                    }
+                    @for tag in &result.tags {
+                        " "
+                        small class="tag" { (tag) }
+                    }
                 }
             }
-            td style="text-align: right; font-family: monospace; font-size: 1.3em; white-space: pre; width: 1%" {
-                @if let Some(value) = result.value {
-                    (format_value(&result.category, value))
-                } @else {
-                    "—"
+            @if columns.value {
+                td style=(VALUE_STYLE) {
+                    @if let Some(value) = result.value {
+                        (format_value(units, &result.category, value))
+                    } @else {
+                        "—"
+                    }
                 }
             }
-            td {
-                @if let Some(quantity) = result.quantity {
-                    (quantity)
-                } @else {
-                    "—"
+            @if columns.voltage_rating {
+                td style=(VALUE_STYLE) {
+                    @if let Some(rating) = result.voltage_rating {
+                        (format_rating(rating, "V"))
+                    } @else {
+                        "—"
+                    }
+                }
+            }
+            @if columns.power_rating {
+                td style=(VALUE_STYLE) {
+                    @if let Some(rating) = result.power_rating {
+                        (format_rating(rating, "W"))
+                    } @else {
+                        "—"
+                    }
+                }
+            }
+            @if columns.location {
+                td {
+                    @if let Some(location) = &result.location {
+                        (location)
+                    } @else {
+                        "—"
+                    }
+                }
+            }
+            @if columns.quantity {
+                td {
+                    @if let Some(quantity) = result.quantity {
+                        (html_available(result.id, result.available(), false))
+                        " / "
+                        @if let Some(shortfall) = result.shortfall() {
+                            mark title={"Below reorder point, short by " (shortfall)} {
+                                (quantity)
+                            }
+                        } @else {
+                            (quantity)
+                        }
+                        " " (result.qty_unit)
+                    } @else {
+                        "—"
+                    }
+                    " "
+                    (html_stage(result.id, result.staged, &result.qty_unit, None))
+                    @if let Some(reserved) = result.reserved.filter(|x| *x > 0) {
+                        " "
+                        span style="color: var(--pico-muted-color);" title="Reserved for projects" { "[" (reserved) "]" }
+                    }
+                    @if let Some(url) = &result.supplier_url {
+                        " "
+                        a
+                        href=(url)
+                        target="_blank"
+                        rel="noopener noreferrer"
+                        title=[result.supplier.as_ref().map(|x| format!("Buy from {}", x))] {
+                            "Buy"
+                        }
+                    }
+                }
+            }
+            @if columns.stock_value {
+                td style=(VALUE_STYLE) {
+                    @if let (Some(value), Some(price)) = (result.stock_value(), result.unit_price) {
+                        span title={(format_price(price)) " each"} { (format_price(value)) }
+                    } @else {
+                        "—"
+                    }
                 }
-                " "
-                (html_stage(result.id, result.staged))
             }
             td {
                 div style="display:inline-flex; gap: 0.5rem;" {
+                    input
+                    type="number"
+                    id={"stage-amount-" (result.id)}
+                    name="amount"
+                    value="1"
+                    min="1"
+                    aria-label={"Amount of " (name) " to stage"}
+                    style=(STAGING_AMOUNT_STYLE);
                     button
                     style=(STAGING_BUTTON_STYLE)
+                    aria-label={"Stage " (name)}
                     hx-post={"/api/inventory/stage/" (result.id)}
+                    hx-include={"#stage-amount-" (result.id)}
                     hx-target={"#staged-" (result.id)}
                     hx-swap="outerHTML" {
                         "+"
                     }
                     button style=(STAGING_BUTTON_STYLE)
+                    aria-label={"Unstage " (name)}
                     hx-post={"/api/inventory/unstage/" (result.id)}
+                    hx-include={"#stage-amount-" (result.id)}
                     hx-target={"#staged-" (result.id)}
                     hx-swap="outerHTML" {
                         "-"
                     }
+                    button style=(STAGING_BUTTON_STYLE)
+                    class="secondary"
+                    title="Alternates"
+                    aria-label={"Alternates of " (name)}
+                    hx-get={"/api/inventory/alternates/" (result.id)}
+                    hx-target="closest tr"
+                    hx-swap="afterend" {
+                        "⇄"
+                    }
+                    button style=(STAGING_BUTTON_STYLE)
+                    aria-label={"Edit " (name)}
+                    hx-get={"/api/inventory/edit-form/" (result.id)}
+                    hx-target="closest tr"
+                    hx-swap="outerHTML" {
+                        "✎"
+                    }
+                    button style=(STAGING_BUTTON_STYLE)
+                    class="secondary"
+                    aria-label={"Delete " (name)}
+                    hx-delete={"/api/inventory/delete/" (result.id)}
+                    hx-target="closest tr"
+                    hx-swap="outerHTML" {
+                        "×"
+                    }
+                }
+            }
+        }
+    )
+}
+
+// Fields of the add form in inventory.html, which a supplier lookup fills in
+pub fn html_add_fields(item: &NewItemForm, note: Option<&str>) -> Markup {
+    html! {
+        div id="add-fields" {
+            div class="grid" {
+                div role="group" {
+                    input type="text" name="mpn" placeholder="MPN" aria-label="MPN" value=(item.mpn);
+                    button type="button" class="secondary" onclick="lookupPart()" { "Lookup" }
+                }
+                input type="text" name="category" placeholder="Category" aria-label="Category" value=(item.category) required;
+                input type="text" name="footprint" placeholder="Footprint" aria-label="Footprint" value=(item.footprint);
+            }
+            div class="grid" {
+                input type="text" name="value" placeholder="Value (4.7k, 100n...)" aria-label="Value" value=(item.value);
+                input type="text" name="location" placeholder="Location" aria-label="Location" value=(item.location);
+                input type="number" name="quantity" placeholder="Quantity" aria-label="Quantity" min="0" value=(item.quantity);
+            }
+            div class="grid" {
+                input type="text" name="voltage_rating" placeholder="Voltage rating (50, 6.3...)" aria-label="Voltage rating" value=(item.voltage_rating);
+                input type="text" name="power_rating" placeholder="Power rating (0.25, 100m...)" aria-label="Power rating" value=(item.power_rating);
+                input type="text" name="unit_price" placeholder="Unit price" aria-label="Unit price" value=(item.unit_price);
+            }
+            input type="text" name="comments" placeholder="Comments" aria-label="Comments" value=(item.comments);
+            @if let Some(note) = note {
+                p { small { (note) } }
+            }
+        }
+    }
+}
+
+pub fn html_edit_row(result: &InventoryItem, units: &CategoryUnits) -> Markup {
+    // Edit using the same multiplier notation the search understands
+    let value = result.value.map(|value| {
+        if category_unit(units, &result.category).1.is_some() {
+            format_mult_value(value).trim().to_string()
+        } else {
+            value.to_string()
+        }
+    });
+    let voltage_rating = result
+        .voltage_rating
+        .map(|x| format_mult_value(x).trim().to_string());
+    let power_rating = result
+        .power_rating
+        .map(|x| format_mult_value(x).trim().to_string());
+
+    html!(
+        tr {
+            th scope="row" {
+                input type="hidden" name="version" value=(result.version);
+                input type="text" name="mpn" placeholder="MPN" value=[&result.mpn];
+                select name="status" aria-label="Status" {
+                    @for status in PartStatus::ALL {
+                        option value=(status.as_str()) selected[status == result.status] {
+                            (status.label())
+                        }
+                    }
+                }
+            }
+            td {
+                input type="text" name="category" placeholder="Category" value=(result.category);
+            }
+            td {
+                input type="text" name="footprint" placeholder="Footprint" value=[&result.footprint];
+            }
+            td {
+                input type="text" name="comments" placeholder="Comments" value=[&result.comments];
+                input
+                type="text"
+                name="tags"
+                placeholder="Tags (RoHS, low-ESR...)"
+                aria-label="Tags"
+                list="tag-list"
+                value=(result.tags.join(", "));
+            }
+            td {
+                input type="text" name="value" placeholder="Value" value=[value];
+            }
+            td {
+                input type="text" name="voltage_rating" placeholder="Voltage" value=[voltage_rating];
+            }
+            td {
+                input type="text" name="power_rating" placeholder="Power" value=[power_rating];
+            }
+            td {
+                input type="text" name="location" placeholder="Location" value=[&result.location];
+                a href={"/locations/" (result.id)} { "All locations" }
+            }
+            td {
+                div role="group" {
+                    input
+                    type="file"
+                    id={"image-" (result.id)}
+                    name="image"
+                    accept="image/png,image/jpeg"
+                    aria-label="Photo";
+                    button
+                    class="secondary"
+                    hx-post={"/api/inventory/image/" (result.id)}
+                    hx-encoding="multipart/form-data"
+                    hx-include={"#image-" (result.id)}
+                    hx-target="closest tr"
+                    hx-swap="outerHTML" {
+                        "Upload"
+                    }
+                }
+                div role="group" {
+                    input
+                    type="number"
+                    id={"set-quantity-" (result.id)}
+                    name="quantity"
+                    placeholder="Quantity"
+                    min="0"
+                    value=[result.quantity];
+                    button
+                    class="secondary"
+                    hx-post={"/api/inventory/set-quantity/" (result.id)}
+                    hx-include={"#set-quantity-" (result.id)}
+                    hx-target="closest tr"
+                    hx-swap="outerHTML" {
+                        "Set"
+                    }
+                }
+                input
+                type="number"
+                name="reorder_point"
+                placeholder="Reorder point"
+                aria-label="Reorder point"
+                min="0"
+                value=[result.reorder_point];
+                input
+                type="text"
+                name="qty_unit"
+                placeholder="Quantity unit (pcs, m, reel...)"
+                aria-label="Quantity unit"
+                value=(result.qty_unit);
+                input
+                type="text"
+                name="supplier"
+                placeholder="Supplier"
+                aria-label="Supplier"
+                value=[&result.supplier];
+                input
+                type="url"
+                name="supplier_url"
+                placeholder="Supplier URL"
+                aria-label="Supplier URL"
+                value=[&result.supplier_url];
+            }
+            td {
+                input
+                type="text"
+                name="unit_price"
+                placeholder="Unit price"
+                aria-label="Unit price"
+                value=[result.unit_price];
+            }
+            td {
+                div style="display:inline-flex; gap: 0.5rem;" {
+                    button
+                    hx-put={"/api/inventory/edit/" (result.id)}
+                    hx-include="closest tr"
+                    hx-target="closest tr"
+                    hx-swap="outerHTML" {
+                        "Save"
+                    }
+                    button class="secondary"
+                    onclick="htmx.trigger('#search-form', 'change')" {
+                        "Cancel"
+                    }
                 }
             }
         }
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use sqlx::PgPool;
+
+    use super::*;
+
+    fn dec(v: &str) -> Decimal {
+        v.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_prefixes_followed_by_units() {
+        assert_eq!(parse_multiple_value("4.7uF"), Some(dec("0.0000047")));
+        assert_eq!(parse_multiple_value("10kΩ"), Some(dec("10000")));
+        assert_eq!(parse_multiple_value("100n"), Some(dec("0.0000001")));
+        assert_eq!(parse_multiple_value("2M2"), Some(dec("2200000")));
+        assert_eq!(parse_multiple_value("470"), Some(dec("470")));
+        assert_eq!(si_multiplier('F'), None);
+    }
+
+    #[test]
+    fn parses_rkm_values() {
+        assert_eq!(parse_rkm_value("4R7"), Some(dec("4.7")));
+        assert_eq!(parse_rkm_value("4K7"), Some(dec("4700")));
+        assert_eq!(parse_rkm_value("1u5"), Some(dec("0.0000015")));
+        assert_eq!(parse_rkm_value("2M2"), Some(dec("2200000")));
+        assert_eq!(parse_rkm_value("0R5"), Some(dec("0.5")));
+        // (Not RKM, the multiplier has to be between digits)
+        assert_eq!(parse_rkm_value("4k"), None);
+        assert_eq!(parse_rkm_value("R5"), None);
+        assert_eq!(parse_multiple_value("4K7"), Some(dec("4700")));
+    }
+
+    #[test]
+    fn rejects_values_out_of_range() {
+        assert_eq!(parse_decimal("1e99"), None);
+        assert_eq!(parse_multiple_value("1e99"), None);
+        assert_eq!(parse_decimal("inf"), None);
+        assert_eq!(parse_multiple_value("inf"), None);
+        assert_eq!(parse_multiple_value("NaN"), None);
+        // (Fits, but not once multiplied)
+        assert_eq!(parse_multiple_value("10000000000000000000000G"), None);
+        assert_eq!(apply_multiplier(Decimal::MAX, pow10(3)), None);
+        // (Nor when rounded down to zero)
+        assert_eq!(apply_multiplier(dec("1e-20"), pow10(-12)), None);
+        assert_eq!(
+            apply_multiplier(Decimal::ZERO, pow10(-12)),
+            Some(Decimal::ZERO)
+        );
+    }
+
+    #[test]
+    fn formats_zero_and_tiny_values() {
+        assert_eq!(format_mult_value(Decimal::ZERO), "0  ");
+        assert_eq!(format_mult_value(dec("0.0")), "0  ");
+        // (Below the smallest prefix, but not zero)
+        assert_eq!(format_mult_value(dec("0.000000000000001")), "0.00 p");
+        // (Too small for a Decimal, so it can't be stored in the first place. The
+        // smallest one that can is still not zero)
+        assert_eq!(parse_multiple_value("1e-30"), None);
+        assert_eq!(format_mult_value(Decimal::new(1, 28)), "0.00 p");
+        assert_eq!(format_mult_value(dec("4700")), "4.70 k");
+    }
+
+    #[test]
+    fn formats_negative_values() {
+        assert_eq!(format_mult_value(dec("-4700")), "-4.70 k");
+        assert_eq!(format_mult_value(dec("-0.0000047")), "-4.70 µ");
+        assert_eq!(format_mult_value(dec("-0.5")), "-500.00 m");
+        assert_eq!(
+            format_prefixed_value(dec("-4700"), &[(0, " ")]),
+            "-4700.00  "
+        );
+    }
+
+    #[test]
+    fn escapes_like_wildcards() {
+        assert_eq!(escape_like("B3_shelf"), "B3\\_shelf");
+        assert_eq!(escape_like("100%"), "100\\%");
+        assert_eq!(escape_like("a\\b"), "a\\\\b");
+        assert_eq!(escape_like("A1-2"), "A1-2");
+    }
+
+    // (Set up from the migrations in a new database, as given by DATABASE_URL)
+    async fn insert_part(stock: &[(i32, Option<i32>)], pool: &PgPool) -> i32 {
+        let category_id: i32 = sqlx::query_scalar(
+            "INSERT INTO categories (name) VALUES ('Resistor')
+            ON CONFLICT (name) DO UPDATE SET name = EXCLUDED.name RETURNING id",
+        )
+        .fetch_one(pool)
+        .await
+        .unwrap();
+        let id: i32 =
+            sqlx::query_scalar("INSERT INTO parts (category_id) VALUES ($1) RETURNING id")
+                .bind(category_id)
+                .fetch_one(pool)
+                .await
+                .unwrap();
+        for (i, (quantity, staged)) in (0..).zip(stock) {
+            let location_id: i32 = sqlx::query_scalar(
+                "INSERT INTO locations (name) VALUES ($1)
+                ON CONFLICT (name) DO UPDATE SET name = EXCLUDED.name RETURNING id",
+            )
+            .bind(format!("L{}", i))
+            .fetch_one(pool)
+            .await
+            .unwrap();
+            sqlx::query(
+                "INSERT INTO stock (part_id, location_id, quantity, staged) VALUES ($1, $2, $3, $4)",
+            )
+            .bind(id)
+            .bind(location_id)
+            .bind(quantity)
+            .bind(staged)
+            .execute(pool)
+            .await
+            .unwrap();
+        }
+        id
+    }
+
+    // Quantity and staged amount of each location of the part
+    async fn stock_by_location(id: i32, pool: &PgPool) -> Vec<(i32, Option<i32>)> {
+        sqlx::query_as("SELECT quantity, staged FROM stock WHERE part_id = $1 ORDER BY location_id")
+            .bind(id)
+            .fetch_all(pool)
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn validates_edited_fields() {
+        let edit =
+            |query: &str| validate_edit(&serde_html_form::from_str::<EditItemForm>(query).unwrap());
+        assert!(edit("value=4k7&reorder_point=10&unit_price=0.05&status=active").is_empty());
+        // (Empty fields are left as they are)
+        assert!(edit("value=&reorder_point=").is_empty());
+        assert_eq!(
+            edit("value=4x7&reorder_point=ten"),
+            [
+                "Unable to parse value \"4x7\".",
+                "Unable to parse reorder point \"ten\"."
+            ]
+        );
+        assert_eq!(edit("unit_price=cheap").len(), 1);
+    }
+
+    #[sqlx::test]
+    #[ignore = "needs a database, set DATABASE_URL"]
+    async fn lowering_quantity_clamps_staged(pool: PgPool) {
+        let id = insert_part(&[(10, Some(8)), (4, None)], &pool).await;
+
+        let mut db_conn = pool.acquire().await.unwrap();
+        set_quantity(id, 5, Some("L0"), None, &mut db_conn)
+            .await
+            .unwrap();
+        assert_eq!(
+            stock_by_location(id, &pool).await,
+            [(5, Some(5)), (4, None)]
+        );
+
+        // (Also when changed elsewhere, through the trigger, which leaves NULLs alone)
+        sqlx::query("UPDATE stock SET quantity = 2 WHERE part_id = $1")
+            .bind(id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert_eq!(
+            stock_by_location(id, &pool).await,
+            [(2, Some(2)), (2, None)]
+        );
+    }
+
+    #[sqlx::test]
+    #[ignore = "needs a database, set DATABASE_URL"]
+    async fn confirm_stage_only_touches_staged_rows(pool: PgPool) {
+        let id = insert_part(&[(10, None), (10, Some(0)), (10, Some(3))], &pool).await;
+        let other = insert_part(&[(5, Some(5))], &pool).await;
+        let multiple = insert_part(&[(4, Some(1)), (4, Some(2))], &pool).await;
+
+        assert_eq!(confirm_stage(&pool, Some(id), None).await.unwrap(), 1);
+        assert_eq!(
+            stock_by_location(id, &pool).await,
+            [(10, None), (10, Some(0)), (7, None)]
+        );
+        assert_eq!(stock_by_location(other, &pool).await, [(5, Some(5))]);
+
+        // (Counting parts, not the locations they were staged from)
+        assert_eq!(confirm_stage(&pool, None, None).await.unwrap(), 2);
+        assert_eq!(stock_by_location(other, &pool).await, [(0, None)]);
+        assert_eq!(
+            stock_by_location(multiple, &pool).await,
+            [(3, None), (2, None)]
+        );
+        assert_eq!(
+            stock_by_location(id, &pool).await,
+            [(10, None), (10, Some(0)), (7, None)]
+        );
+    }
+
+    #[sqlx::test]
+    #[ignore = "needs a database, set DATABASE_URL"]
+    async fn unstage_all_logs_what_was_staged(pool: PgPool) {
+        let id = insert_part(&[(10, Some(0)), (10, Some(3)), (10, Some(2))], &pool).await;
+
+        assert_eq!(unstage_all(&pool, None).await.unwrap(), 1);
+        assert_eq!(
+            stock_by_location(id, &pool).await,
+            [(10, None), (10, None), (10, None)]
+        );
+
+        let log: Vec<(String, Option<i32>)> =
+            sqlx::query_as("SELECT field, delta FROM stock_log WHERE part_id = $1 ORDER BY id")
+                .bind(id)
+                .fetch_all(&pool)
+                .await
+                .unwrap();
+        assert_eq!(
+            log,
+            [
+                (String::from("staged"), Some(-3)),
+                (String::from("staged"), Some(-2))
+            ]
+        );
+    }
+
+    #[sqlx::test]
+    #[ignore = "needs a database, set DATABASE_URL"]
+    async fn delete_and_restore_are_logged(pool: PgPool) {
+        let id = insert_part(&[(10, None)], &pool).await;
+        let mut db_conn = pool.acquire().await.unwrap();
+
+        set_deleted(id, true, None, &mut db_conn).await.unwrap();
+        set_deleted(id, false, None, &mut db_conn).await.unwrap();
+        // (Nothing to restore anymore)
+        set_deleted(id, false, None, &mut db_conn).await.unwrap();
+
+        let log: Vec<String> =
+            sqlx::query_scalar("SELECT field FROM stock_log WHERE part_id = $1 ORDER BY id")
+                .bind(id)
+                .fetch_all(&pool)
+                .await
+                .unwrap();
+        assert_eq!(log, ["deleted", "restored"]);
+    }
+
+    #[sqlx::test]
+    #[ignore = "needs a database, set DATABASE_URL"]
+    async fn editing_location_merges_stock(pool: PgPool) {
+        let merged = insert_part(&[(10, Some(2)), (5, None)], &pool).await;
+        let ambiguous = insert_part(&[(1, None), (2, None)], &pool).await;
+        let mut db_conn = pool.acquire().await.unwrap();
+
+        let item: EditItemForm = serde_html_form::from_str("location=L1").unwrap();
+        let outcome = update_item(merged, &item, None, &mut db_conn)
+            .await
+            .unwrap();
+        assert!(matches!(outcome, EditOutcome::Updated(_)));
+        assert_eq!(stock_by_location(merged, &pool).await, [(15, Some(2))]);
+
+        // (Neither of its locations is the new one)
+        let item: EditItemForm = serde_html_form::from_str("location=L2").unwrap();
+        let outcome = update_item(ambiguous, &item, None, &mut db_conn)
+            .await
+            .unwrap();
+        assert!(matches!(outcome, EditOutcome::SeveralLocations));
+        assert_eq!(
+            stock_by_location(ambiguous, &pool).await,
+            [(1, None), (2, None)]
+        );
+    }
+
+    #[sqlx::test]
+    #[ignore = "needs a database, set DATABASE_URL"]
+    async fn bulk_moving_stock_merges_or_skips(pool: PgPool) {
+        let merged = insert_part(&[(10, Some(2)), (5, None)], &pool).await;
+        let moved = insert_part(&[(3, None)], &pool).await;
+        let ambiguous = insert_part(&[(1, None), (2, None)], &pool).await;
+        let mut db_conn = pool.acquire().await.unwrap();
+
+        let change = BulkChange::Location(String::from("L1"));
+        let outcome = bulk_update_items(&[merged, moved], &change, None, &mut db_conn)
+            .await
+            .unwrap();
+        assert_eq!(outcome.updated, 2);
+        assert!(outcome.skipped.is_empty());
+        assert_eq!(stock_by_location(merged, &pool).await, [(15, Some(2))]);
+        let item = query_item(moved, &mut db_conn).await.unwrap().unwrap();
+        assert_eq!(item.location.as_deref(), Some("L1"));
+
+        // (Neither of its locations is the new one)
+        let change = BulkChange::Location(String::from("L2"));
+        let outcome = bulk_update_items(&[ambiguous], &change, None, &mut db_conn)
+            .await
+            .unwrap();
+        assert_eq!(outcome.updated, 0);
+        assert_eq!(outcome.skipped.len(), 1);
+        assert_eq!(
+            stock_by_location(ambiguous, &pool).await,
+            [(1, None), (2, None)]
+        );
+    }
+
+    #[sqlx::test]
+    #[ignore = "needs a database, set DATABASE_URL"]
+    async fn merging_adds_up_stock_and_reservations(pool: PgPool) {
+        let keep = insert_part(&[(10, None)], &pool).await;
+        let merged = insert_part(&[(5, Some(1)), (3, None)], &pool).await;
+        for (part_id, quantity) in [(keep, 1), (keep, 2), (merged, 4)] {
+            sqlx::query("INSERT INTO stock (part_id, location_id, quantity) VALUES ($1, NULL, $2)")
+                .bind(part_id)
+                .bind(quantity)
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+        let projects: Vec<i32> =
+            sqlx::query_scalar("INSERT INTO projects (name) VALUES ('A'), ('B') RETURNING id")
+                .fetch_all(&pool)
+                .await
+                .unwrap();
+        for (project_id, part_id, qty_per_board, reserved) in [
+            (projects[0], keep, 2, 3),
+            (projects[0], merged, 5, 4),
+            (projects[1], merged, 1, 2),
+        ] {
+            sqlx::query(
+                "INSERT INTO project_parts (project_id, part_id, qty_per_board, reserved) \
+                 VALUES ($1, $2, $3, $4)",
+            )
+            .bind(project_id)
+            .bind(part_id)
+            .bind(qty_per_board)
+            .bind(reserved)
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+
+        let mut db_conn = pool.acquire().await.unwrap();
+        assert!(
+            merge_parts(keep, merged, None, &mut db_conn)
+                .await
+                .unwrap()
+                .is_some()
+        );
+
+        // (Only into the first of the rows without a location)
+        let stock: Vec<(Option<String>, i32, Option<i32>)> = sqlx::query_as(
+            "SELECT l.name, s.quantity, s.staged FROM stock s \
+             LEFT JOIN locations l ON s.location_id = l.id WHERE s.part_id = $1 \
+             ORDER BY l.name, s.id",
+        )
+        .bind(keep)
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+        assert_eq!(
+            stock,
+            [
+                (Some(String::from("L0")), 15, Some(1)),
+                (Some(String::from("L1")), 3, None),
+                (None, 5, None),
+                (None, 2, None)
+            ]
+        );
+
+        let reservations: Vec<(i32, i32, i32, i32)> = sqlx::query_as(
+            "SELECT project_id, part_id, qty_per_board, reserved FROM project_parts \
+             ORDER BY project_id",
+        )
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+        assert_eq!(
+            reservations,
+            [(projects[0], keep, 2, 7), (projects[1], keep, 1, 2)]
+        );
+    }
+
+    #[sqlx::test]
+    #[ignore = "needs a database, set DATABASE_URL"]
+    async fn reserved_stock_is_not_staged(pool: PgPool) {
+        let id = insert_part(&[(6, None), (4, None)], &pool).await;
+        let project_id: i32 =
+            sqlx::query_scalar("INSERT INTO projects (name) VALUES ('A') RETURNING id")
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        sqlx::query(
+            "INSERT INTO project_parts (project_id, part_id, qty_per_board, reserved) \
+             VALUES ($1, $2, 1, 7)",
+        )
+        .bind(project_id)
+        .bind(id)
+        .execute(&pool)
+        .await
+        .unwrap();
+        let mut db_conn = pool.acquire().await.unwrap();
+
+        // (Only the 3 which aren't reserved)
+        let outcome = update_stage(id, 5, None, None, &mut db_conn).await.unwrap();
+        assert_eq!(outcome.delta(), 3);
+        let outcome = update_stage(id, 1, None, None, &mut db_conn).await.unwrap();
+        assert!(matches!(outcome, StageOutcome::AtMax { staged: 3, .. }));
+        let item = query_item(id, &mut db_conn).await.unwrap().unwrap();
+        assert_eq!(item.available(), 0);
+
+        // (But the project may stage its own)
+        let outcome = update_stage(id, 7, Some(project_id), None, &mut db_conn)
+            .await
+            .unwrap();
+        assert_eq!(outcome.delta(), 7);
+    }
+
+    #[sqlx::test]
+    #[ignore = "needs a database, set DATABASE_URL"]
+    async fn stage_spreads_across_locations(pool: PgPool) {
+        let id = insert_part(&[(10, Some(3)), (5, None)], &pool).await;
+        let mut db_conn = pool.acquire().await.unwrap();
+
+        // (Most available first)
+        let outcome = update_stage(id, 10, None, None, &mut db_conn)
+            .await
+            .unwrap();
+        assert_eq!(outcome.delta(), 10);
+        assert_eq!(
+            stock_by_location(id, &pool).await,
+            [(10, Some(10)), (5, Some(3))]
+        );
+
+        // (Only as many as there are)
+        let outcome = update_stage(id, 5, None, None, &mut db_conn).await.unwrap();
+        assert_eq!(outcome.delta(), 2);
+        let outcome = update_stage(id, 1, None, None, &mut db_conn).await.unwrap();
+        assert!(matches!(outcome, StageOutcome::AtMax { staged: 15, .. }));
+
+        // (Most staged first)
+        let outcome = update_stage(id, -12, None, None, &mut db_conn)
+            .await
+            .unwrap();
+        assert_eq!(outcome.delta(), -12);
+        assert_eq!(
+            stock_by_location(id, &pool).await,
+            [(10, Some(0)), (5, Some(3))]
+        );
+    }
+}
@@ -0,0 +1,122 @@
+use std::convert::Infallible;
+
+use axum::{
+    extract::FromRequestParts,
+    http::{HeaderMap, header::COOKIE, request::Parts},
+};
+use maud::{DOCTYPE, Markup, html};
+
+use crate::auth::CSRF_HEADER;
+
+// Set by toggleTheme in res/theme.js
+const THEME_COOKIE: &str = "theme";
+
+// Without a theme picked, Pico follows the one of the system
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Theme {
+    #[default]
+    System,
+    Light,
+    Dark,
+}
+
+// Value of a cookie set by the page itself (so not signed like the session)
+pub fn cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|x| x.to_str().ok())
+        .flat_map(|x| x.split(';'))
+        .filter_map(|x| x.trim().split_once('='))
+        .find(|(x, _)| *x == name)
+        .map(|(_, value)| value)
+}
+
+// In the server's time zone
+pub fn format_time(time: &chrono::DateTime<chrono::Utc>) -> String {
+    time.with_timezone(&chrono::Local)
+        .format("%Y-%m-%d %H:%M:%S")
+        .to_string()
+}
+
+impl Theme {
+    pub fn from_headers(headers: &HeaderMap) -> Theme {
+        match cookie(headers, THEME_COOKIE) {
+            Some("light") => Theme::Light,
+            Some("dark") => Theme::Dark,
+            _ => Theme::System,
+        }
+    }
+
+    // Value for data-theme on <html>
+    pub fn as_attr(&self) -> Option<&'static str> {
+        match self {
+            Theme::System => None,
+            Theme::Light => Some("light"),
+            Theme::Dark => Some("dark"),
+        }
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for Theme {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Theme::from_headers(&parts.headers))
+    }
+}
+
+// Fills in the data-theme of the static pages in res/
+pub fn static_page(html: &str, theme: Theme) -> String {
+    let attr = match theme.as_attr() {
+        Some(theme) => format!(" data-theme=\"{}\"", theme),
+        None => String::new(),
+    };
+    html.replacen(
+        "<html lang=\"en\">",
+        &format!("<html lang=\"en\"{}>", attr),
+        1,
+    )
+}
+
+// Value for hx-headers, so that every HTMX request below it carries the token
+pub fn csrf_headers(csrf_token: &str) -> String {
+    format!("{{\"{}\": \"{}\"}}", CSRF_HEADER, csrf_token)
+}
+
+// Full page for the server-rendered views, matching the static pages in res/
+pub fn html_page(title: &str, csrf_token: &str, theme: Theme, content: Markup) -> Markup {
+    html! {
+        (DOCTYPE)
+        html lang="en" data-theme=[theme.as_attr()] {
+            head {
+                meta charset="UTF-8";
+                meta name="viewport" content="width=device-width, initial-scale=1.0";
+                meta name="htmx-config" content=r#"{"responseHandling": [{"code": "204", "swap": false}, {"code": "[23]..", "swap": true}, {"code": "422", "swap": true}, {"code": "409", "swap": true}, {"code": "500", "swap": true, "error": true}, {"code": "[45]..", "swap": false, "error": true}]}"#;
+                title { "Tatjam's station" }
+                link rel="stylesheet" href="https://cdn.jsdelivr.net/npm/@picocss/pico@2/css/pico.min.css";
+                link rel="stylesheet" href="/style.css";
+                script src="https://cdn.jsdelivr.net/npm/htmx.org@2.0.8/dist/htmx.min.js" {}
+                script src="/theme.js" {}
+            }
+            body hx-headers=(csrf_headers(csrf_token)) {
+                main class="container" {
+                    nav {
+                        ul {
+                            li { a href="/inventory" { "Inventory" } }
+                            li { strong { (title) } }
+                        }
+                        ul {
+                            li { a onclick="toggleTheme()" style="cursor: pointer;" title="Toggle dark mode" aria-label="Toggle dark mode" { "◐" } }
+                            li { a href="/account" { "Account" } }
+                            li { a hx-post="/logout" { "Logout" } }
+                        }
+                    }
+                    article {
+                        (content)
+                    }
+                }
+            }
+        }
+    }
+}
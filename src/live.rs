@@ -0,0 +1,101 @@
+use std::{convert::Infallible, time::Duration};
+
+use axum::{
+    extract::{
+        State,
+        ws::{Message, WebSocket, WebSocketUpgrade},
+    },
+    response::{
+        IntoResponse,
+        sse::{Event, KeepAlive, Sse},
+    },
+};
+use futures_util::StreamExt;
+use serde::Serialize;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio_stream::{
+    Stream,
+    wrappers::{BroadcastStream, errors::BroadcastStreamRecvError},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use crate::state::AppState;
+
+// Changes buffered for slow clients, past this they just get a single refresh
+const CHANGES_CAPACITY: usize = 64;
+
+// (Proxies tend to drop connections which stay quiet for a minute)
+const EVENTS_KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+// Sent whenever the stock changes, part_id is None if many parts may have
+#[derive(Debug, Clone, Serialize)]
+pub struct InventoryChange {
+    pub part_id: Option<i32>,
+}
+
+pub fn changes_channel() -> broadcast::Sender<InventoryChange> {
+    broadcast::channel(CHANGES_CAPACITY).0
+}
+
+async fn forward_changes(
+    mut socket: WebSocket,
+    mut changes: broadcast::Receiver<InventoryChange>,
+    shutdown: CancellationToken,
+) {
+    loop {
+        let change = tokio::select! {
+            change = changes.recv() => change,
+            _ = shutdown.cancelled() => {
+                let _ = socket.send(Message::Close(None)).await;
+                break;
+            }
+            // (Clients only ever close the socket, anything else is ignored)
+            msg = socket.recv() => match msg {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => continue,
+            },
+        };
+
+        let change = match change {
+            Ok(change) => change,
+            Err(RecvError::Lagged(_)) => InventoryChange { part_id: None },
+            Err(RecvError::Closed) => break,
+        };
+
+        let text = serde_json::to_string(&change).unwrap();
+        if socket.send(Message::Text(text.into())).await.is_err() {
+            break;
+        }
+    }
+}
+
+// Pushes every inventory change, so that open pages refresh their table
+pub async fn ws_handler(State(state): State<AppState>, ws: WebSocketUpgrade) -> impl IntoResponse {
+    info!("Opening live updates socket");
+
+    let changes = state.changes.subscribe();
+    ws.on_failed_upgrade(|e| warn!("Error while opening live updates socket: {}", e))
+        .on_upgrade(move |socket| forward_changes(socket, changes, state.shutdown))
+}
+
+// Same as the socket, as an event stream which HTMX's SSE extension understands
+pub async fn events_handler(
+    State(state): State<AppState>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    info!("Opening live updates event stream");
+
+    let changes = BroadcastStream::new(state.changes.subscribe())
+        .map(|change| {
+            let change = match change {
+                Ok(change) => change,
+                Err(BroadcastStreamRecvError::Lagged(_)) => InventoryChange { part_id: None },
+            };
+            let data = serde_json::to_string(&change).unwrap();
+            Ok(Event::default().event("inventoryChanged").data(data))
+        })
+        // (The channel never closes on its own)
+        .take_until(state.shutdown.cancelled_owned());
+
+    Sse::new(changes).keep_alive(KeepAlive::new().interval(EVENTS_KEEP_ALIVE))
+}
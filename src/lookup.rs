@@ -0,0 +1,197 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{Path, State},
+    response::{Html, IntoResponse},
+};
+use serde_json::Value;
+use tracing::{info, warn};
+
+use crate::{
+    config::Config,
+    inventory::{NewItemForm, html_add_fields},
+    state::AppState,
+};
+
+// (Supplier data hardly changes, and the public APIs are rate limited)
+const CACHE_TTL: Duration = Duration::from_secs(60 * 60 * 24);
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Fetches part data from a supplier to pre-fill the add form. The response is
+// parsed as from LCSC's part search, the URL (given as LOOKUP_URL) receives the
+// MPN as the keyword parameter
+pub struct PartLookup {
+    url: Option<String>,
+    api_key: Option<String>,
+    client: reqwest::Client,
+    // Also caches parts which weren't found, but not failed lookups
+    cache: Mutex<HashMap<String, (Instant, Option<NewItemForm>)>>,
+}
+
+// Maps the supplier's categories to the ones used in the inventory
+fn map_category(parent: &str, category: &str) -> String {
+    if parent.contains("Resistor") {
+        String::from("Resistor")
+    } else if category.contains("Ceramic") {
+        String::from("CapCeramic")
+    } else if category.contains("Electrolytic") {
+        String::from("CapElectro")
+    } else if parent.contains("Inductor") {
+        String::from("Inductor")
+    } else {
+        category.to_string()
+    }
+}
+
+// "10kΩ" -> "10k", as the add form expects the values without units
+fn strip_unit(value: &str) -> String {
+    value
+        .trim()
+        .trim_end_matches(['Ω', 'F', 'H', 'V', 'W'])
+        .trim()
+        .to_string()
+}
+
+fn parse_product(product: &Value, mpn: &str) -> NewItemForm {
+    let field = |name: &str| {
+        product
+            .get(name)
+            .and_then(|x| x.as_str())
+            .unwrap_or_default()
+            .trim()
+            .to_string()
+    };
+    let params: Vec<(&str, &str)> = product
+        .get("paramVOList")
+        .and_then(|x| x.as_array())
+        .map(|params| {
+            params
+                .iter()
+                .filter_map(|x| {
+                    Some((
+                        x.get("paramNameEn")?.as_str()?,
+                        x.get("paramValueEn")?.as_str()?,
+                    ))
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    let param = |matches: &dyn Fn(&str) -> bool| {
+        params
+            .iter()
+            .find(|(name, _)| matches(name))
+            .map(|(_, value)| strip_unit(value))
+            .unwrap_or_default()
+    };
+
+    let mut comments = field("productIntroEn");
+    let datasheet = field("pdfUrl");
+    if !datasheet.is_empty() {
+        comments = format!("{} (datasheet: {})", comments, datasheet)
+            .trim()
+            .to_string();
+    }
+
+    NewItemForm {
+        mpn: mpn.to_string(),
+        category: map_category(&field("parentCatalogName"), &field("catalogName")),
+        footprint: field("encapStandard"),
+        value: param(&|name| matches!(name, "Resistance" | "Capacitance" | "Inductance")),
+        voltage_rating: param(&|name| name.contains("Voltage")),
+        power_rating: param(&|name| name.contains("Power")),
+        comments,
+        ..Default::default()
+    }
+}
+
+impl PartLookup {
+    pub fn new(config: &Config) -> Self {
+        PartLookup {
+            url: config.lookup_url.clone(),
+            api_key: config.lookup_api_key.clone(),
+            client: reqwest::Client::builder()
+                .timeout(LOOKUP_TIMEOUT)
+                .build()
+                .expect("Failed to create HTTP client"),
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    async fn fetch(&self, url: &str, mpn: &str) -> Result<Option<NewItemForm>, reqwest::Error> {
+        let mut request = self.client.get(url).query(&[("keyword", mpn)]);
+        if let Some(api_key) = &self.api_key {
+            request = request.bearer_auth(api_key);
+        }
+        let response: Value = request.send().await?.error_for_status()?.json().await?;
+
+        let Some(products) = response
+            .pointer("/result/productSearchResultVO/productList")
+            .and_then(|x| x.as_array())
+        else {
+            return Ok(None);
+        };
+
+        // (The search is fuzzy, so prefer the exact part if it's listed)
+        let product = products
+            .iter()
+            .find(|x| {
+                x.get("productModel")
+                    .and_then(|x| x.as_str())
+                    .is_some_and(|x| x.eq_ignore_ascii_case(mpn))
+            })
+            .or(products.first());
+
+        Ok(product.map(|x| parse_product(x, mpn)))
+    }
+
+    // Returns None if nothing was found, the lookup failed, or it's not configured
+    pub async fn lookup(&self, mpn: &str) -> Option<NewItemForm> {
+        let url = self.url.as_deref()?;
+        let key = mpn.to_lowercase();
+
+        if let Some((time, result)) = self.cache.lock().unwrap().get(&key)
+            && time.elapsed() < CACHE_TTL
+        {
+            return result.clone();
+        }
+
+        match self.fetch(url, mpn).await {
+            Ok(result) => {
+                let mut cache = self.cache.lock().unwrap();
+                cache.retain(|_, (time, _)| time.elapsed() < CACHE_TTL);
+                cache.insert(key, (Instant::now(), result.clone()));
+                result
+            }
+            Err(e) => {
+                warn!("Error while looking up part {}: {}", mpn, e);
+                None
+            }
+        }
+    }
+}
+
+pub async fn lookup_handler(
+    State(state): State<AppState>,
+    Path(mpn): Path<String>,
+) -> impl IntoResponse {
+    let mpn = mpn.trim();
+    info!("Looking up part {}", mpn);
+
+    // (Without supplier data, the form is left for filling in by hand)
+    let response = match state.part_lookup.lookup(mpn).await {
+        Some(item) => html_add_fields(&item, None),
+        None => html_add_fields(
+            &NewItemForm {
+                mpn: mpn.to_string(),
+                ..Default::default()
+            },
+            Some("No supplier data found for this part."),
+        ),
+    };
+
+    Html(response.into_string())
+}
@@ -1,51 +1,193 @@
 // Copyright (C) 2026 tatjam
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+mod admin;
+mod alternates;
+mod api_keys;
+mod assets;
 mod auth;
+mod bom;
+mod config;
+mod dashboard;
+mod health;
+mod history;
+mod images;
 mod inventory;
+mod layout;
+mod live;
+mod lookup;
+mod monitoring;
+mod projects;
+mod searches;
 mod state;
+mod totp;
+
+use std::{net::SocketAddr, sync::LazyLock};
 
 use axum::{
     Router,
-    http::header,
+    http::HeaderMap,
     middleware::{self},
     response::{Html, IntoResponse, Redirect},
-    routing::{get, post},
+    routing::{delete, get, post, put},
+};
+use tokio_util::sync::CancellationToken;
+use tower::ServiceBuilder;
+use tower_http::{
+    compression::{CompressionLayer, predicate::DefaultPredicate},
+    request_id::{PropagateRequestIdLayer, SetRequestIdLayer},
+    trace::TraceLayer,
 };
-use dotenvy;
 use tower_sessions::Session;
-use tracing::info;
+use tracing::{error, info};
+use tracing_subscriber::EnvFilter;
 
-use crate::state::AppState;
+use crate::{assets::StaticAsset, config::Config, layout::Theme, state::AppState};
 
 const LOGIN_HTML: &str = include_str!("../res/login.html");
 const INVENTORY_HTML: &str = include_str!("../res/inventory.html");
-const STYLE_CSS: &str = include_str!("../res/style.css");
+
+// (Not versioned in the URL, so only cached for a while before revalidating)
+static STYLE_CSS: LazyLock<StaticAsset> =
+    LazyLock::new(|| StaticAsset::new(include_bytes!("../res/style.css"), "text/css", 60 * 60));
+static THEME_JS: LazyLock<StaticAsset> = LazyLock::new(|| {
+    StaticAsset::new(
+        include_bytes!("../res/theme.js"),
+        "text/javascript",
+        60 * 60,
+    )
+});
+// (Requested by browsers on every page, and hardly ever changes)
+static FAVICON: LazyLock<StaticAsset> = LazyLock::new(|| {
+    StaticAsset::new(
+        include_bytes!("../res/favicon.ico"),
+        "image/x-icon",
+        60 * 60 * 24 * 7,
+    )
+});
 
 #[tokio::main]
 async fn main() {
-    if dotenvy::var("LOG_PLAIN").is_ok() {
-        tracing_subscriber::fmt().with_ansi(false).init();
-    } else {
-        tracing_subscriber::fmt().with_ansi(true).init();
-    }
+    // (Everything may be set in station.toml instead. Loaded first, so that
+    // RUST_LOG may be set in .env too)
+    dotenvy::dotenv().ok();
 
-    dotenvy::dotenv().unwrap();
+    // (RUST_LOG may pick other levels, e.g. station::inventory=debug for SQL_DEBUG)
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let plain = dotenvy::var("LOG_PLAIN").is_ok();
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_ansi(!plain)
+        .init();
 
-    let shared_state = AppState::new().await;
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let host = config.host.clone();
+    let metrics_host = config.metrics_host.clone();
+
+    let shared_state = AppState::new(config).await;
 
     let open_routes = Router::new()
         .route("/", get(home_page))
+        .route("/login", get(login_page).post(auth::login_handler))
+        .route("/login/totp", post(totp::login_code_handler))
+        .route("/health", get(health::health_handler))
+        .route("/ready", get(health::ready_handler))
+        .route(
+            "/style.css",
+            get(|headers: HeaderMap| async move { STYLE_CSS.response(&headers) }),
+        )
         .route(
-            "/login",
-            get(|| html_page(LOGIN_HTML)).post(auth::login_handler),
+            "/theme.js",
+            get(|headers: HeaderMap| async move { THEME_JS.response(&headers) }),
         )
-        .route("/style.css", get(|| css_file(STYLE_CSS)));
+        .route(
+            "/favicon.ico",
+            get(|headers: HeaderMap| async move { FAVICON.response(&headers) }),
+        );
 
     let auth_routes = Router::new()
-        .route("/inventory", get(|| html_page(INVENTORY_HTML)))
+        .route("/inventory", get(inventory_page))
         .route("/logout", post(auth::logout_handler))
+        .route("/account", get(auth::account_page))
+        .route("/account/password", post(auth::password_change_handler))
+        .route(
+            "/account/api-keys",
+            get(api_keys::api_keys_page).post(api_keys::create_handler),
+        )
+        .route("/account/api-keys/{id}", delete(api_keys::revoke_handler))
+        .route("/account/2fa/enable", post(totp::enable_handler))
+        .route("/account/2fa/confirm", post(totp::confirm_handler))
+        .route("/account/2fa/disable", post(totp::disable_handler))
+        .route("/history/{id}", get(history::history_page))
+        .route("/dashboard", get(dashboard::dashboard_page))
+        .route("/reorder", get(inventory::reorder_page))
+        .route(
+            "/admin/normalize-categories",
+            get(admin::normalize_categories_page),
+        )
+        .route("/admin/footprints", get(admin::footprints_page))
+        .route("/admin/duplicates", get(admin::duplicates_page))
+        .route(
+            "/admin/duplicates/merge",
+            post(admin::merge_duplicates_handler),
+        )
+        .route(
+            "/admin/footprints/rename",
+            post(admin::rename_footprint_handler),
+        )
+        .route(
+            "/admin/footprints/known",
+            post(admin::known_footprint_handler),
+        )
+        .route("/part/{id}", get(inventory::part_page))
+        .route("/locations/{id}", get(inventory::locations_page))
+        .route(
+            "/projects",
+            get(projects::projects_page).post(projects::create_project_handler),
+        )
+        .route("/projects/{id}", get(projects::project_page))
+        .route(
+            "/projects/{id}/parts",
+            post(projects::add_project_part_handler),
+        )
+        .route(
+            "/projects/{id}/parts/{part_id}",
+            delete(projects::remove_project_part_handler),
+        )
+        .route("/projects/{id}/reserve", post(projects::reserve_handler))
+        .route("/projects/{id}/release", post(projects::release_handler))
+        .route(
+            "/projects/{id}/stage",
+            post(projects::stage_reserved_handler),
+        )
+        .route("/ws", get(live::ws_handler))
+        .route("/events", get(live::events_handler))
         .route("/api/inventory/search", get(inventory::search_handler))
+        .route(
+            "/api/searches",
+            get(searches::searches_handler).post(searches::save_handler),
+        )
+        .route("/api/searches/{id}", delete(searches::delete_handler))
+        .route("/api/tags", get(inventory::tag_list_handler))
+        .route("/api/inventory/suggest", get(inventory::suggest_handler))
+        .route(
+            "/api/inventory/valuation",
+            get(inventory::valuation_handler),
+        )
+        .route(
+            "/api/inventory/export.csv",
+            get(inventory::export_csv_handler),
+        )
+        .route(
+            "/api/inventory/export-kicad.csv",
+            get(inventory::export_kicad_handler),
+        )
         .route(
             "/api/inventory/categories",
             get(inventory::category_list_handler),
@@ -62,30 +204,152 @@ async fn main() {
             "/api/inventory/unstage/{id}",
             post(inventory::unstaging_handler),
         )
+        .route(
+            "/api/inventory/confirm/{id}",
+            post(inventory::confirm_part_handler),
+        )
         .route(
             "/api/inventory/confirm-stage",
             post(inventory::confirm_stage_handler),
         )
+        .route(
+            "/api/inventory/stage-summary",
+            get(inventory::stage_summary_handler),
+        )
+        .route(
+            "/api/inventory/unstage-all",
+            post(inventory::unstage_all_handler),
+        )
+        .route("/api/lookup/{mpn}", get(lookup::lookup_handler))
+        .route("/api/bom/import", post(bom::bom_import_handler))
+        .route("/api/bom/stage", post(bom::bom_stage_handler))
+        .route(
+            "/api/categories/unit",
+            post(inventory::category_unit_handler),
+        )
+        .route("/api/inventory/add", post(inventory::add_handler))
+        .route("/api/inventory/merge", post(inventory::merge_handler))
+        .route("/api/inventory/import", post(inventory::import_handler))
+        .route("/api/inventory/edit/{id}", put(inventory::edit_handler))
+        .route(
+            "/api/inventory/bulk-edit",
+            post(inventory::bulk_edit_handler),
+        )
+        .route(
+            "/api/inventory/set-quantity/{id}",
+            post(inventory::set_quantity_handler),
+        )
+        .route(
+            "/api/inventory/delete/{id}",
+            delete(inventory::delete_handler),
+        )
+        .route(
+            "/api/inventory/restore/{id}",
+            post(inventory::restore_handler),
+        )
+        .route(
+            "/api/inventory/image/{id}",
+            get(images::image_handler).post(images::upload_image_handler),
+        )
+        .route(
+            "/api/inventory/edit-form/{id}",
+            get(inventory::edit_form_handler),
+        )
+        .route("/api/inventory/item/{id}", get(inventory::item_handler))
+        .route("/api/inventory/scan/{code}", get(inventory::scan_handler))
+        .route(
+            "/api/inventory/alternates/{id}",
+            get(alternates::alternates_handler).post(alternates::link_handler),
+        )
+        .route(
+            "/api/inventory/alternates/{id}/{other}",
+            delete(alternates::unlink_handler),
+        )
+        .route("/api/inventory/label/{file}", get(inventory::label_handler))
+        .route("/labels/print", get(inventory::labels_print_page))
         .route(
             "/api/inventory/download-backup",
             get(inventory::download_backup_handler),
         )
-        .route_layer(middleware::from_fn(auth::auth_guard));
+        .route_layer(middleware::from_fn(auth::csrf_guard))
+        .route_layer(middleware::from_fn_with_state(
+            shared_state.clone(),
+            auth::auth_guard,
+        ));
 
     let session_layer = shared_state.setup_session_store().await;
 
+    // (Kept to close the pool once the server is done)
+    let pool = shared_state.pool.clone();
+    let shutdown = shared_state.shutdown.clone();
+
+    monitoring::setup_metrics(metrics_host.as_deref(), pool.clone()).await;
+
     let app = Router::new()
         .merge(open_routes)
         .merge(auth_routes)
+        .route_layer(middleware::from_fn(monitoring::track_requests))
         .with_state(shared_state)
-        .layer(session_layer);
+        .layer(session_layer)
+        // (The default predicate already skips images, which are compressed anyway,
+        // as well as tiny responses)
+        .layer(CompressionLayer::new().compress_when(DefaultPredicate::new()))
+        // (Outermost, so that everything below logs within the request's span. The
+        // id is sent back too, to find the logs of a failed request)
+        .layer(
+            ServiceBuilder::new()
+                .layer(SetRequestIdLayer::x_request_id(
+                    monitoring::MakeRandomRequestId,
+                ))
+                .layer(PropagateRequestIdLayer::x_request_id())
+                .layer(TraceLayer::new_for_http().make_span_with(monitoring::request_span)),
+        );
 
-    let host = dotenvy::var("HOST").unwrap();
     let listener = tokio::net::TcpListener::bind(host).await.unwrap();
 
     info!("Listening on {}", listener.local_addr().unwrap());
 
-    axum::serve(listener, app).await.unwrap();
+    // (The peer address is needed to rate-limit logins)
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(shutdown_signal(shutdown))
+    .await
+    .unwrap();
+
+    pool.close().await;
+    info!("Shut down");
+}
+
+// Resolves on Ctrl-C or SIGTERM (as sent by docker stop), after which axum stops
+// accepting connections and waits for in-flight requests to finish. The live
+// updates are ended too, as they never finish on their own
+async fn shutdown_signal(shutdown: CancellationToken) {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to listen for Ctrl-C");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+
+    info!("Shutting down, draining connections");
+    shutdown.cancel();
 }
 
 async fn home_page(session: Session) -> impl IntoResponse {
@@ -96,10 +360,16 @@ async fn home_page(session: Session) -> impl IntoResponse {
     }
 }
 
-async fn html_page(html: &'static str) -> impl IntoResponse {
-    Html(html)
+// (The static page only needs the CSRF token and theme filled in)
+async fn inventory_page(session: Session, theme: Theme) -> impl IntoResponse {
+    let csrf_token = match auth::csrf_token(&session).await {
+        Ok(token) => token,
+        Err(e) => return inventory::handle_generic_inventory_error(e),
+    };
+    let html = INVENTORY_HTML.replace("{{csrf_headers}}", &layout::csrf_headers(&csrf_token));
+    Html(layout::static_page(&html, theme)).into_response()
 }
 
-async fn css_file(css: &'static str) -> impl IntoResponse {
-    ([(header::CONTENT_TYPE, "text/css")], css)
+async fn login_page(theme: Theme) -> impl IntoResponse {
+    Html(layout::static_page(LOGIN_HTML, theme))
 }
@@ -0,0 +1,123 @@
+use std::time::{Duration, Instant};
+
+use axum::{
+    Router,
+    extract::{MatchedPath, Request, State},
+    http::HeaderValue,
+    middleware::Next,
+    response::IntoResponse,
+    routing::get,
+};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+use sqlx::{Pool, Postgres};
+use tower_http::request_id::{MakeRequestId, RequestId};
+use tracing::{Span, info, info_span};
+
+const DURATION_BUCKETS: &[f64] = &[
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+#[derive(Clone)]
+struct MetricsState {
+    handle: PrometheusHandle,
+    pool: Pool<Postgres>,
+}
+
+// Ids for requests which don't bring their own X-Request-Id (e.g. from a proxy)
+#[derive(Clone, Default)]
+pub struct MakeRandomRequestId;
+
+impl MakeRequestId for MakeRandomRequestId {
+    fn make_request_id<B>(&mut self, _request: &axum::http::Request<B>) -> Option<RequestId> {
+        let id = format!("{:016x}", rand::random::<u64>());
+        Some(RequestId::new(HeaderValue::from_str(&id).unwrap()))
+    }
+}
+
+// Span around each request, so that every log line of a handler carries the id
+pub fn request_span<B>(request: &axum::http::Request<B>) -> Span {
+    let id = request
+        .extensions()
+        .get::<RequestId>()
+        .and_then(|x| x.header_value().to_str().ok())
+        .unwrap_or_default();
+    info_span!(
+        "request",
+        id,
+        method = %request.method(),
+        path = request.uri().path(),
+    )
+}
+
+// Counts every request by route (not by the raw path, as ids would make too
+// many series) and records how long it took
+pub async fn track_requests(request: Request, next: Next) -> impl IntoResponse {
+    let start = Instant::now();
+    let method = request.method().to_string();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|x| x.as_str().to_string())
+        .unwrap_or(String::from("unmatched"));
+
+    let response = next.run(request).await;
+
+    let status = response.status().as_u16().to_string();
+    metrics::counter!(
+        "http_requests_total",
+        "method" => method.clone(),
+        "route" => route.clone(),
+        "status" => status
+    )
+    .increment(1);
+    metrics::histogram!(
+        "http_request_duration_seconds",
+        "method" => method,
+        "route" => route
+    )
+    .record(start.elapsed().as_secs_f64());
+
+    response
+}
+
+// (The pool gauges are only needed when scraping, so they are set right then)
+async fn metrics_handler(State(state): State<MetricsState>) -> impl IntoResponse {
+    metrics::gauge!("db_pool_connections").set(state.pool.size() as f64);
+    metrics::gauge!("db_pool_idle_connections").set(state.pool.num_idle() as f64);
+
+    state.handle.render()
+}
+
+// Only if METRICS_HOST is set, metrics are recorded and served on their own
+// listener, so that they are never exposed next to the public site
+pub async fn setup_metrics(host: Option<&str>, pool: Pool<Postgres>) {
+    let Some(host) = host else {
+        return;
+    };
+
+    let handle = PrometheusBuilder::new()
+        .set_buckets_for_metric(
+            Matcher::Full(String::from("http_request_duration_seconds")),
+            DURATION_BUCKETS,
+        )
+        .expect("Failed to set metric buckets")
+        .install_recorder()
+        .expect("Failed to install metrics recorder");
+
+    let upkeep_handle = handle.clone();
+    tokio::task::spawn(async move {
+        loop {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            upkeep_handle.run_upkeep();
+        }
+    });
+
+    let app = Router::new()
+        .route("/metrics", get(metrics_handler))
+        .with_state(MetricsState { handle, pool });
+
+    let listener = tokio::net::TcpListener::bind(host).await.unwrap();
+    info!("Serving metrics on {}", listener.local_addr().unwrap());
+
+    tokio::task::spawn(async move { axum::serve(listener, app).await.unwrap() });
+}
@@ -0,0 +1,673 @@
+use axum::{
+    Extension, Form,
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    response::{Html, IntoResponse},
+};
+use maud::{Markup, html};
+use serde::Deserialize;
+use sqlx::{Acquire, PgConnection, QueryBuilder};
+use tower_sessions::Session;
+use tracing::info;
+
+use crate::{
+    auth::{CurrentUser, csrf_token},
+    history::{StockLogEntry, insert_log},
+    inventory::{handle_generic_inventory_error, update_stage},
+    layout::{Theme, html_page},
+    state::AppState,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct NewProjectForm {
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ProjectPartForm {
+    // MPN or part id, like the scan endpoint
+    part: String,
+    qty_per_board: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReserveForm {
+    boards: String,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+struct ProjectRow {
+    id: i32,
+    name: String,
+    num_parts: i64,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+struct ProjectPart {
+    part_id: i32,
+    mpn: Option<String>,
+    qty_per_board: i32,
+    reserved: i32,
+    // Neither staged nor reserved by any project
+    available: i32,
+}
+
+fn project_error(msg: &str) -> axum::response::Response {
+    let response = html! {
+        p style="color: red;" { (msg) }
+    };
+    (
+        StatusCode::UNPROCESSABLE_ENTITY,
+        Html(response.into_string()),
+    )
+        .into_response()
+}
+
+async fn query_project_parts(
+    project_id: i32,
+    db_conn: &mut PgConnection,
+) -> Result<Vec<ProjectPart>, sqlx::Error> {
+    let mut query = QueryBuilder::new(
+        "SELECT pp.part_id, i.mpn, pp.qty_per_board, pp.reserved, \
+         COALESCE(i.quantity, 0) - COALESCE(i.staged, 0) - COALESCE(i.reserved, 0) AS available",
+    );
+    query.push(" FROM project_parts pp JOIN inventory i ON i.id = pp.part_id");
+    query.push(" WHERE pp.project_id = ");
+    query.push_bind(project_id);
+    query.push(" ORDER BY i.mpn, pp.part_id");
+    query
+        .build_query_as::<ProjectPart>()
+        .fetch_all(&mut *db_conn)
+        .await
+}
+
+// Locks the parts of the project, so that concurrent reservations of the same
+// part (even from other projects) see each other
+async fn lock_project_parts(
+    project_id: i32,
+    db_conn: &mut PgConnection,
+) -> Result<(), sqlx::Error> {
+    let mut query = QueryBuilder::new(
+        "SELECT 1 FROM parts WHERE id IN (SELECT part_id FROM project_parts WHERE project_id = ",
+    );
+    query.push_bind(project_id);
+    query.push(") FOR UPDATE");
+    query.build().execute(&mut *db_conn).await?;
+
+    Ok(())
+}
+
+async fn set_reserved(
+    project_id: i32,
+    part: &ProjectPart,
+    reserved: i32,
+    user_id: Option<i32>,
+    db_conn: &mut PgConnection,
+) -> Result<(), sqlx::Error> {
+    let mut query = QueryBuilder::new("UPDATE project_parts SET reserved = ");
+    query.push_bind(reserved);
+    query.push(" WHERE project_id = ");
+    query.push_bind(project_id);
+    query.push(" AND part_id = ");
+    query.push_bind(part.part_id);
+    query.build().execute(&mut *db_conn).await?;
+
+    insert_log(
+        &[StockLogEntry::count(
+            part.part_id,
+            "reserved",
+            Some(part.reserved),
+            Some(reserved),
+        )],
+        user_id,
+        &mut *db_conn,
+    )
+    .await
+}
+
+fn html_project_parts(project_id: i32, parts: &[ProjectPart], message: Option<Markup>) -> Markup {
+    html! {
+        @if let Some(message) = message {
+            article { (message) }
+        }
+        @if parts.is_empty() {
+            p { "The project has no parts yet." }
+        } @else {
+            div class="overflow-auto" {
+                table class="striped" {
+                    thead {
+                        tr {
+                            th scope="col" { "MPN" }
+                            th scope="col" { "Per board" }
+                            th scope="col" { "Reserved" }
+                            th scope="col" { "Available" }
+                            th scope="col" { "Action" }
+                        }
+                    }
+                    tbody {
+                        @for part in parts {
+                            tr {
+                                th scope="row" {
+                                    a href={"/part/" (part.part_id)} {
+                                        (part.mpn.as_deref().unwrap_or("—"))
+                                    }
+                                }
+                                td { (part.qty_per_board) }
+                                td { (part.reserved) }
+                                td {
+                                    @if part.available < part.qty_per_board {
+                                        mark title="Not enough for another board" { (part.available) }
+                                    } @else {
+                                        (part.available)
+                                    }
+                                }
+                                td {
+                                    button
+                                    class="secondary"
+                                    hx-delete={"/projects/" (project_id) "/parts/" (part.part_id)}
+                                    hx-target="#project-parts" {
+                                        "Remove"
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+async fn project_parts_response(
+    state: &AppState,
+    project_id: i32,
+    message: Option<Markup>,
+) -> axum::response::Response {
+    let mut db_conn = match state.pool.acquire().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            return handle_generic_inventory_error(e);
+        }
+    };
+
+    match query_project_parts(project_id, &mut db_conn).await {
+        Ok(parts) => {
+            let mut headers = HeaderMap::new();
+            headers.insert("HX-Trigger", "inventoryUpdated".parse().unwrap());
+            (
+                headers,
+                Html(html_project_parts(project_id, &parts, message).into_string()),
+            )
+                .into_response()
+        }
+        Err(e) => handle_generic_inventory_error(e),
+    }
+}
+
+pub async fn projects_page(
+    State(state): State<AppState>,
+    session: Session,
+    theme: Theme,
+) -> impl IntoResponse {
+    info!("Performing projects query");
+
+    let csrf_token = match csrf_token(&session).await {
+        Ok(token) => token,
+        Err(e) => return handle_generic_inventory_error(e),
+    };
+
+    let mut db_conn = match state.pool.acquire().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            return handle_generic_inventory_error(e);
+        }
+    };
+
+    let mut query = QueryBuilder::new("SELECT p.id, p.name, COUNT(pp.part_id) AS num_parts");
+    query.push(" FROM projects p LEFT JOIN project_parts pp ON pp.project_id = p.id");
+    query.push(" GROUP BY p.id ORDER BY p.name");
+    let projects = match query
+        .build_query_as::<ProjectRow>()
+        .fetch_all(db_conn.as_mut())
+        .await
+    {
+        Ok(projects) => projects,
+        Err(e) => {
+            return handle_generic_inventory_error(e);
+        }
+    };
+
+    let content = html! {
+        @if projects.is_empty() {
+            p { "No projects yet." }
+        } @else {
+            ul {
+                @for project in &projects {
+                    li {
+                        a href={"/projects/" (project.id)} { (project.name) }
+                        " (" (project.num_parts) " parts)"
+                    }
+                }
+            }
+        }
+        form hx-post="/projects" hx-target="#project-error" {
+            div role="group" {
+                input type="text" name="name" placeholder="Project name" aria-label="Project name" required;
+                button type="submit" { "Create Project" }
+            }
+        }
+        div id="project-error" {}
+    };
+
+    Html(html_page("Projects", &csrf_token, theme, content).into_string()).into_response()
+}
+
+pub async fn create_project_handler(
+    State(state): State<AppState>,
+    Form(form): Form<NewProjectForm>,
+) -> impl IntoResponse {
+    let name = form.name.trim();
+    if name.is_empty() {
+        return project_error("A name is required.");
+    }
+
+    info!("Creating project {}", name);
+
+    let mut query = QueryBuilder::new("INSERT INTO projects (name) VALUES (");
+    query.push_bind(name);
+    query.push(") ON CONFLICT (name) DO NOTHING RETURNING id");
+    match query
+        .build_query_scalar::<i32>()
+        .fetch_optional(&state.pool)
+        .await
+    {
+        Ok(Some(id)) => {
+            let mut headers = HeaderMap::new();
+            headers.insert("HX-Redirect", format!("/projects/{}", id).parse().unwrap());
+            (headers, "").into_response()
+        }
+        Ok(None) => project_error("A project with this name already exists."),
+        Err(e) => handle_generic_inventory_error(e),
+    }
+}
+
+pub async fn project_page(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+    session: Session,
+    theme: Theme,
+) -> impl IntoResponse {
+    info!("Performing project query for project {}", id);
+
+    let csrf_token = match csrf_token(&session).await {
+        Ok(token) => token,
+        Err(e) => return handle_generic_inventory_error(e),
+    };
+
+    let mut db_conn = match state.pool.acquire().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            return handle_generic_inventory_error(e);
+        }
+    };
+
+    let mut query = QueryBuilder::new("SELECT name FROM projects WHERE id = ");
+    query.push_bind(id);
+    let name = match query
+        .build_query_scalar::<String>()
+        .fetch_optional(db_conn.as_mut())
+        .await
+    {
+        Ok(Some(name)) => name,
+        Ok(None) => {
+            return handle_generic_inventory_error(format!("No project with id {}", id));
+        }
+        Err(e) => {
+            return handle_generic_inventory_error(e);
+        }
+    };
+
+    let parts = match query_project_parts(id, &mut db_conn).await {
+        Ok(parts) => parts,
+        Err(e) => {
+            return handle_generic_inventory_error(e);
+        }
+    };
+
+    let content = html! {
+        h3 { (name) }
+        form hx-post={"/projects/" (id) "/parts"} hx-target="#project-parts" {
+            div role="group" {
+                input type="text" name="part" placeholder="MPN or part id" aria-label="Part" required;
+                input type="number" name="qty_per_board" placeholder="Per board" aria-label="Quantity per board" min="1" required;
+                button type="submit" { "Add Part" }
+            }
+        }
+        form hx-post={"/projects/" (id) "/reserve"} hx-target="#project-parts" {
+            div role="group" {
+                input type="number" name="boards" value="1" min="1" aria-label="Boards";
+                button type="submit" { "Reserve for boards" }
+            }
+        }
+        div class="grid" {
+            button hx-post={"/projects/" (id) "/stage"} hx-target="#project-parts" {
+                "Stage Reserved"
+            }
+            button
+            class="secondary"
+            hx-post={"/projects/" (id) "/release"}
+            hx-target="#project-parts"
+            hx-confirm="Release all stock reserved for this project?" {
+                "Release Reserved"
+            }
+        }
+        div id="project-parts" {
+            (html_project_parts(id, &parts, None))
+        }
+    };
+
+    Html(html_page("Project", &csrf_token, theme, content).into_string()).into_response()
+}
+
+pub async fn add_project_part_handler(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+    Form(form): Form<ProjectPartForm>,
+) -> impl IntoResponse {
+    let Some(qty_per_board) = form
+        .qty_per_board
+        .trim()
+        .parse::<i32>()
+        .ok()
+        .filter(|x| *x > 0)
+    else {
+        return project_error("The quantity per board must be a positive number.");
+    };
+    let part = form.part.trim();
+
+    info!(
+        "Adding {} of part {} to project {}",
+        qty_per_board, part, id
+    );
+
+    let mut query =
+        QueryBuilder::new("SELECT id FROM inventory WHERE deleted_at IS NULL AND (mpn = ");
+    query.push_bind(part);
+    query.push(" OR id::TEXT = ");
+    query.push_bind(part);
+    query.push(") LIMIT 1");
+    let part_id = match query
+        .build_query_scalar::<i32>()
+        .fetch_optional(&state.pool)
+        .await
+    {
+        Ok(Some(part_id)) => part_id,
+        Ok(None) => return project_error(&format!("No part matches \"{}\".", part)),
+        Err(e) => return handle_generic_inventory_error(e),
+    };
+
+    let mut query = QueryBuilder::new(
+        "INSERT INTO project_parts (project_id, part_id, qty_per_board) VALUES (",
+    );
+    let mut values = query.separated(", ");
+    values.push_bind(id);
+    values.push_bind(part_id);
+    values.push_bind(qty_per_board);
+    query.push(
+        ") ON CONFLICT (project_id, part_id) DO UPDATE SET qty_per_board = EXCLUDED.qty_per_board",
+    );
+    if let Err(e) = query.build().execute(&state.pool).await {
+        return handle_generic_inventory_error(e);
+    }
+
+    project_parts_response(&state, id, None).await
+}
+
+async fn remove_project_part(
+    project_id: i32,
+    part_id: i32,
+    user_id: Option<i32>,
+    db_conn: &mut PgConnection,
+) -> Result<(), sqlx::Error> {
+    let mut tx = db_conn.begin().await?;
+
+    let mut query = QueryBuilder::new("DELETE FROM project_parts WHERE project_id = ");
+    query.push_bind(project_id);
+    query.push(" AND part_id = ");
+    query.push_bind(part_id);
+    query.push(" RETURNING reserved");
+    let reserved = query
+        .build_query_scalar::<i32>()
+        .fetch_optional(&mut *tx)
+        .await?;
+
+    // (Whatever was reserved is freed along with it)
+    if let Some(reserved) = reserved.filter(|x| *x > 0) {
+        insert_log(
+            &[StockLogEntry::count(
+                part_id,
+                "reserved",
+                Some(reserved),
+                Some(0),
+            )],
+            user_id,
+            &mut tx,
+        )
+        .await?;
+    }
+
+    tx.commit().await
+}
+
+pub async fn remove_project_part_handler(
+    State(state): State<AppState>,
+    Extension(CurrentUser(user_id)): Extension<CurrentUser>,
+    Path((id, part_id)): Path<(i32, i32)>,
+) -> impl IntoResponse {
+    info!("Removing part {} from project {}", part_id, id);
+
+    let mut db_conn = match state.pool.acquire().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            return handle_generic_inventory_error(e);
+        }
+    };
+
+    if let Err(e) = remove_project_part(id, part_id, Some(user_id), &mut db_conn).await {
+        return handle_generic_inventory_error(e);
+    }
+
+    drop(db_conn);
+    state.notify_change(None);
+    project_parts_response(&state, id, None).await
+}
+
+async fn reserve(
+    project_id: i32,
+    boards: i32,
+    user_id: Option<i32>,
+    db_conn: &mut PgConnection,
+) -> Result<Vec<(Option<String>, i32)>, sqlx::Error> {
+    let mut tx = db_conn.begin().await?;
+
+    lock_project_parts(project_id, &mut tx).await?;
+    let parts = query_project_parts(project_id, &mut tx).await?;
+
+    let mut shortages = vec![];
+    for part in &parts {
+        let wanted = part.qty_per_board.saturating_mul(boards);
+        let granted = wanted.min(part.available.max(0));
+        if granted < wanted {
+            shortages.push((part.mpn.clone(), wanted - granted));
+        }
+        if granted > 0 {
+            set_reserved(project_id, part, part.reserved + granted, user_id, &mut tx).await?;
+        }
+    }
+
+    tx.commit().await?;
+
+    Ok(shortages)
+}
+
+pub async fn reserve_handler(
+    State(state): State<AppState>,
+    Extension(CurrentUser(user_id)): Extension<CurrentUser>,
+    Path(id): Path<i32>,
+    Form(form): Form<ReserveForm>,
+) -> impl IntoResponse {
+    let Some(boards) = form.boards.trim().parse::<i32>().ok().filter(|x| *x > 0) else {
+        return project_error("The number of boards must be a positive number.");
+    };
+
+    info!("Reserving stock of project {} for {} boards", id, boards);
+
+    let mut db_conn = match state.pool.acquire().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            return handle_generic_inventory_error(e);
+        }
+    };
+
+    let shortages = match reserve(id, boards, Some(user_id), &mut db_conn).await {
+        Ok(shortages) => shortages,
+        Err(e) => return handle_generic_inventory_error(e),
+    };
+
+    let message = html! {
+        "Reserved stock for " (boards) " boards."
+        @if !shortages.is_empty() {
+            p style="color: red;" { "Not enough stock for:" }
+            ul {
+                @for (mpn, missing) in &shortages {
+                    li { (mpn.as_deref().unwrap_or("—")) ", short by " (missing) }
+                }
+            }
+        }
+    };
+    drop(db_conn);
+    state.notify_change(None);
+    project_parts_response(&state, id, Some(message)).await
+}
+
+// Frees the reserved stock, returning what was reserved of each part
+async fn release(
+    project_id: i32,
+    user_id: Option<i32>,
+    db_conn: &mut PgConnection,
+) -> Result<Vec<(i32, i32)>, sqlx::Error> {
+    let mut tx = db_conn.begin().await?;
+
+    lock_project_parts(project_id, &mut tx).await?;
+    let parts = query_project_parts(project_id, &mut tx).await?;
+
+    let mut released = vec![];
+    for part in parts.iter().filter(|x| x.reserved > 0) {
+        set_reserved(project_id, part, 0, user_id, &mut tx).await?;
+        released.push((part.part_id, part.reserved));
+    }
+
+    tx.commit().await?;
+
+    Ok(released)
+}
+
+pub async fn release_handler(
+    State(state): State<AppState>,
+    Extension(CurrentUser(user_id)): Extension<CurrentUser>,
+    Path(id): Path<i32>,
+) -> impl IntoResponse {
+    info!("Releasing reserved stock of project {}", id);
+
+    let mut db_conn = match state.pool.acquire().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            return handle_generic_inventory_error(e);
+        }
+    };
+
+    let released = match release(id, Some(user_id), &mut db_conn).await {
+        Ok(released) => released,
+        Err(e) => return handle_generic_inventory_error(e),
+    };
+
+    drop(db_conn);
+    let message = html! { "Released " (released.len()) " parts." };
+    state.notify_change(None);
+    project_parts_response(&state, id, Some(message)).await
+}
+
+// Moves the reserved stock into the stage, in a single transaction. What can't
+// be staged (as there isn't enough stock left) stays reserved, and is returned
+async fn stage_reserved(
+    project_id: i32,
+    user_id: Option<i32>,
+    db_conn: &mut PgConnection,
+) -> Result<(usize, Vec<(Option<String>, i32)>), sqlx::Error> {
+    let mut tx = db_conn.begin().await?;
+
+    lock_project_parts(project_id, &mut tx).await?;
+    let parts = query_project_parts(project_id, &mut tx).await?;
+
+    let mut num_staged = 0;
+    let mut shortages = vec![];
+    for part in parts.iter().filter(|x| x.reserved > 0) {
+        let staged = update_stage(
+            part.part_id,
+            part.reserved,
+            Some(project_id),
+            user_id,
+            &mut tx,
+        )
+        .await?
+        .delta();
+        if staged > 0 {
+            set_reserved(project_id, part, part.reserved - staged, user_id, &mut tx).await?;
+            num_staged += 1;
+        }
+        if staged < part.reserved {
+            shortages.push((part.mpn.clone(), part.reserved - staged));
+        }
+    }
+
+    tx.commit().await?;
+
+    Ok((num_staged, shortages))
+}
+
+// To be committed as any other stage
+pub async fn stage_reserved_handler(
+    State(state): State<AppState>,
+    Extension(CurrentUser(user_id)): Extension<CurrentUser>,
+    Path(id): Path<i32>,
+) -> impl IntoResponse {
+    info!("Staging reserved stock of project {}", id);
+
+    let mut db_conn = match state.pool.acquire().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            return handle_generic_inventory_error(e);
+        }
+    };
+
+    let (num_staged, shortages) = match stage_reserved(id, Some(user_id), &mut db_conn).await {
+        Ok(result) => result,
+        Err(e) => return handle_generic_inventory_error(e),
+    };
+    metrics::counter!("staging_operations_total", "operation" => "project").increment(1);
+
+    let message = html! {
+        "Staged " (num_staged) " parts, commit the stage once built."
+        @if !shortages.is_empty() {
+            p style="color: red;" { "Not enough stock left to stage, still reserved:" }
+            ul {
+                @for (mpn, missing) in &shortages {
+                    li { (mpn.as_deref().unwrap_or("—")) ", " (missing) }
+                }
+            }
+        }
+    };
+    drop(db_conn);
+    state.notify_change(None);
+    project_parts_response(&state, id, Some(message)).await
+}
@@ -0,0 +1,152 @@
+use axum::{
+    Extension, Form,
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    response::{Html, IntoResponse},
+};
+use maud::{Markup, html};
+use serde::Deserialize;
+use sqlx::QueryBuilder;
+use tracing::info;
+
+use crate::{
+    auth::CurrentUser,
+    inventory::{SearchForm, handle_generic_inventory_error},
+    state::AppState,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct SaveSearchForm {
+    name: String,
+    // Query string of the search form, as sent to the search endpoint
+    query: String,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+struct SavedSearch {
+    id: i32,
+    name: String,
+    query: String,
+}
+
+async fn query_searches(state: &AppState, user_id: i32) -> Result<Vec<SavedSearch>, sqlx::Error> {
+    let mut query =
+        QueryBuilder::new("SELECT id, name, query FROM saved_searches WHERE user_id = ");
+    query.push_bind(user_id);
+    query.push(" ORDER BY name, id");
+    query
+        .build_query_as::<SavedSearch>()
+        .fetch_all(&state.pool)
+        .await
+}
+
+// (Shown in the status area, so the list of searches stays in place)
+fn search_error(msg: &str) -> axum::response::Response {
+    let mut headers = HeaderMap::new();
+    headers.insert("HX-Retarget", "#stage-status".parse().unwrap());
+    headers.insert("HX-Reswap", "innerHTML".parse().unwrap());
+    let response = html! {
+        p style="color: red;" { (msg) }
+    };
+    (
+        StatusCode::UNPROCESSABLE_ENTITY,
+        headers,
+        Html(response.into_string()),
+    )
+        .into_response()
+}
+
+// Applying one fills in the search form (see applySearch in inventory.html), so
+// that the filters shown always match the results
+fn html_search_list(searches: &[SavedSearch]) -> Markup {
+    html! {
+        @for search in searches {
+            div role="group" style="display: inline-flex; width: auto; margin-right: 0.5rem;" {
+                button
+                class="secondary outline"
+                data-query=(search.query)
+                onclick="applySearch(this)" {
+                    (search.name)
+                }
+                button
+                class="secondary outline"
+                title="Delete"
+                hx-delete={"/api/searches/" (search.id)}
+                hx-target="#saved-searches"
+                hx-confirm={"Delete the saved search \"" (search.name) "\"?"} {
+                    "×"
+                }
+            }
+        }
+    }
+}
+
+fn search_list_response(
+    searches: Result<Vec<SavedSearch>, sqlx::Error>,
+) -> axum::response::Response {
+    match searches {
+        Ok(searches) => Html(html_search_list(&searches).into_string()).into_response(),
+        Err(e) => handle_generic_inventory_error(e),
+    }
+}
+
+pub async fn searches_handler(
+    State(state): State<AppState>,
+    Extension(CurrentUser(user_id)): Extension<CurrentUser>,
+) -> impl IntoResponse {
+    search_list_response(query_searches(&state, user_id).await)
+}
+
+pub async fn save_handler(
+    State(state): State<AppState>,
+    Extension(CurrentUser(user_id)): Extension<CurrentUser>,
+    Form(form): Form<SaveSearchForm>,
+) -> impl IntoResponse {
+    let name = form.name.trim();
+    if name.is_empty() {
+        return search_error("A name is required.");
+    }
+
+    // (Checked now, rather than failing every time it's applied)
+    match serde_html_form::from_str::<SearchForm>(&form.query) {
+        Ok(search) => {
+            if let Err(msg) = search.sort_order() {
+                return search_error(&msg);
+            }
+        }
+        Err(e) => return search_error(&format!("Invalid search: {}", e)),
+    }
+
+    info!("Saving search \"{}\" for user {}", name, user_id);
+
+    // (Saving with an existing name replaces it)
+    let mut query = QueryBuilder::new("INSERT INTO saved_searches (user_id, name, query) VALUES (");
+    let mut values = query.separated(", ");
+    values.push_bind(user_id);
+    values.push_bind(name);
+    values.push_bind(&form.query);
+    query.push(") ON CONFLICT (user_id, name) DO UPDATE SET query = EXCLUDED.query");
+    if let Err(e) = query.build().execute(&state.pool).await {
+        return handle_generic_inventory_error(e);
+    }
+
+    search_list_response(query_searches(&state, user_id).await)
+}
+
+pub async fn delete_handler(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+    Extension(CurrentUser(user_id)): Extension<CurrentUser>,
+) -> impl IntoResponse {
+    info!("Deleting saved search {} of user {}", id, user_id);
+
+    let mut query = QueryBuilder::new("DELETE FROM saved_searches WHERE id = ");
+    query.push_bind(id);
+    query.push(" AND user_id = ");
+    query.push_bind(user_id);
+    if let Err(e) = query.build().execute(&state.pool).await {
+        return handle_generic_inventory_error(e);
+    }
+
+    search_list_response(query_searches(&state, user_id).await)
+}
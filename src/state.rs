@@ -1,23 +1,74 @@
-use sqlx::{Pool, Postgres, postgres::PgPoolOptions};
+use std::sync::{Arc, RwLock, RwLockReadGuard};
+
+use sqlx::{Connection, Executor, PgConnection, Pool, Postgres, postgres::PgPoolOptions};
+use tokio::sync::broadcast;
+use tokio_util::sync::CancellationToken;
 use tower_sessions::{ExpiredDeletion, Expiry, SessionManagerLayer, cookie::time::Duration};
 use tower_sessions_sqlx_store::PostgresStore;
-use tracing::info;
+use tracing::{error, info};
+
+use crate::{
+    auth::{LoginLimiter, bootstrap_users, check_user_hashes},
+    config::Config,
+    inventory::{CategoryUnits, ListCache, load_category_units},
+    live::{InventoryChange, changes_channel},
+    lookup::PartLookup,
+};
 
 #[derive(Clone)]
 pub struct AppState {
-    pub password_hash: String,
+    pub config: Arc<Config>,
     pub pool: Pool<Postgres>,
+    // (Loaded at startup, and kept in sync with the DB when changed through the API)
+    pub category_units: Arc<RwLock<CategoryUnits>>,
+    pub login_limiter: Arc<LoginLimiter>,
+    pub part_lookup: Arc<PartLookup>,
+    pub changes: broadcast::Sender<InventoryChange>,
+    // Cancelled once the server starts shutting down, to end the live updates
+    // which would otherwise keep it waiting
+    pub shutdown: CancellationToken,
+    pub list_cache: Arc<ListCache>,
+}
+
+// Queries running for longer are cancelled, so they don't hold a connection
+// forever. Those which may rightly take long lift it, see no_statement_timeout
+pub fn with_statement_timeout(options: PgPoolOptions, timeout_secs: u64) -> PgPoolOptions {
+    let timeout_ms = timeout_secs * 1000;
+    options.after_connect(move |conn, _| {
+        Box::pin(async move {
+            // (SET can't take a bind parameter, but this is a number)
+            conn.execute(format!("SET statement_timeout = {}", timeout_ms).as_str())
+                .await?;
+            Ok(())
+        })
+    })
+}
+
+// Lifts the statement timeout within the transaction, e.g. for exports
+pub async fn no_statement_timeout(tx: &mut PgConnection) -> Result<(), sqlx::Error> {
+    tx.execute("SET LOCAL statement_timeout = 0").await?;
+    Ok(())
+}
+
+// On a connection of its own without the statement timeout, as rewriting a large
+// table may take a while. (Closed afterwards, instead of going back to the pool)
+async fn migrate(pool: &Pool<Postgres>) -> Result<(), sqlx::migrate::MigrateError> {
+    let mut conn = pool.acquire().await?.detach();
+    conn.execute("SET statement_timeout = 0").await?;
+    // (Embedded at compile time from migrations/)
+    let result = sqlx::migrate!().run(&mut conn).await;
+    conn.close().await?;
+    result
 }
 
 impl AppState {
+    // Don't hold across an await, the guard isn't Send
+    pub fn category_units(&self) -> RwLockReadGuard<'_, CategoryUnits> {
+        self.category_units.read().unwrap()
+    }
+
     pub async fn setup_session_store(&self) -> SessionManagerLayer<PostgresStore> {
-        let allow_insecure = match dotenvy::var("ALLOW_UNSECURE_COOKIE")
-            .unwrap_or(String::from("false"))
-            .as_str()
-        {
-            "true" => true,
-            _ => false,
-        };
+        let allow_insecure = self.config.allow_unsecure_cookie;
 
         let session_store = PostgresStore::new(self.pool.clone());
 
@@ -35,38 +86,120 @@ impl AppState {
         );
 
         SessionManagerLayer::new(session_store)
-            .with_secure(allow_insecure)
-            .with_same_site(tower_sessions::cookie::SameSite::Lax)
-            .with_expiry(Expiry::OnInactivity(Duration::seconds(60 * 60 * 24 * 7)))
-            .with_name("station_session")
+            .with_secure(!allow_insecure)
+            .with_same_site(self.config.session_samesite)
+            .with_expiry(Expiry::OnInactivity(Duration::seconds(
+                self.config.session_expiry_secs,
+            )))
+            .with_name(self.config.session_cookie_name.clone())
     }
 
-    pub async fn new() -> Self {
+    pub async fn new(config: Config) -> Self {
         let login_str = format!(
             "postgres://{}:{}@{}/{}",
-            dotenvy::var("DB_USER").unwrap(),
-            dotenvy::var("DB_PASSWORD").unwrap(),
-            dotenvy::var("DB_HOST").unwrap(),
-            dotenvy::var("DB_NAME").unwrap()
+            config.db_user, config.db_password, config.db_host, config.db_name
         );
 
         info!(
             "Connecting to DB postgres://xxx:xxx@{}/{}",
-            dotenvy::var("DB_HOST").unwrap(),
-            dotenvy::var("DB_NAME").unwrap()
+            config.db_host, config.db_name
         );
 
-        let pool = PgPoolOptions::new()
-            .max_connections(5)
+        let pool_options = PgPoolOptions::new()
+            .max_connections(config.db_max_connections)
+            .acquire_timeout(std::time::Duration::from_secs(
+                config.db_acquire_timeout_secs,
+            ))
+            .idle_timeout(
+                (config.db_idle_timeout_secs > 0)
+                    .then(|| std::time::Duration::from_secs(config.db_idle_timeout_secs)),
+            );
+        let pool = with_statement_timeout(pool_options, config.db_statement_timeout_secs)
             .connect(login_str.as_str())
             .await
             .expect("Failed to connect to Postgres");
 
-        let password_hash = dotenvy::var("LOGIN_PASSWORD").unwrap();
+        info!("Migrating inventory DB");
+
+        migrate(&pool)
+            .await
+            .expect("Failed to migrate inventory DB");
+
+        bootstrap_users(&pool, config.login_password.as_deref())
+            .await
+            .expect("Failed to create the first user");
+        if let Err(e) = check_user_hashes(&pool).await {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+
+        let category_units = load_category_units(&pool)
+            .await
+            .expect("Failed to load category units");
+
+        let list_cache = Arc::new(ListCache::default());
+        tokio::task::spawn(list_cache.clone().keep_refreshed(pool.clone()));
+        // (Once at startup too, as a restored backup doesn't refresh it)
+        list_cache.invalidate();
 
         AppState {
+            login_limiter: Arc::new(LoginLimiter::new(&config)),
+            part_lookup: Arc::new(PartLookup::new(&config)),
+            config: Arc::new(config),
             pool,
-            password_hash,
+            category_units: Arc::new(RwLock::new(category_units)),
+            changes: changes_channel(),
+            shutdown: CancellationToken::new(),
+            list_cache,
         }
     }
+
+    // Tells the live update clients, never waits for them
+    pub fn notify_change(&self, part_id: Option<i32>) {
+        // (Fails only if nobody is listening)
+        let _ = self.changes.send(InventoryChange { part_id });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::{Acquire, postgres::PgConnectOptions};
+
+    use super::*;
+
+    #[sqlx::test]
+    #[ignore = "needs a database, set DATABASE_URL"]
+    async fn statement_timeout_cancels_slow_queries(
+        pool_options: PgPoolOptions,
+        connect_options: PgConnectOptions,
+    ) {
+        let pool = with_statement_timeout(pool_options, 1)
+            .connect_with(connect_options)
+            .await
+            .unwrap();
+
+        let e = sqlx::query("SELECT pg_sleep(2)")
+            .execute(&pool)
+            .await
+            .unwrap_err();
+        // (query_canceled)
+        assert_eq!(
+            e.as_database_error().and_then(|x| x.code()).as_deref(),
+            Some("57014")
+        );
+
+        // (Unless lifted, only within the transaction)
+        let mut conn = pool.acquire().await.unwrap();
+        let mut tx = conn.begin().await.unwrap();
+        no_statement_timeout(&mut tx).await.unwrap();
+        sqlx::query("SELECT pg_sleep(2)")
+            .execute(&mut *tx)
+            .await
+            .unwrap();
+        tx.commit().await.unwrap();
+        sqlx::query("SELECT pg_sleep(2)")
+            .execute(&mut *conn)
+            .await
+            .unwrap_err();
+    }
 }
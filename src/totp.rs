@@ -0,0 +1,325 @@
+use std::net::SocketAddr;
+
+use axum::{
+    Form,
+    extract::{ConnectInfo, State},
+    http::{HeaderMap, StatusCode},
+    response::{Html, IntoResponse, Redirect},
+};
+use maud::{Markup, PreEscaped, html};
+use qrcode::{QrCode, render::svg};
+use serde::Deserialize;
+use sqlx::QueryBuilder;
+use totp_rs::{Algorithm, Secret, TOTP};
+use tower_sessions::Session;
+use tracing::{info, warn};
+
+use crate::{
+    auth::{current_user, finish_login, locked_out_response, wrong_credentials_response},
+    inventory::handle_generic_inventory_error,
+    state::AppState,
+};
+
+// Users which passed the password check, but still have to enter their code
+const PENDING_USER_SESSION_NAME: &str = "totp_pending_user_id";
+// Secret being set up, only stored for the user once a code is confirmed with it
+const NEW_SECRET_SESSION_NAME: &str = "totp_new_secret";
+
+const TOTP_ISSUER: &str = "station";
+
+#[derive(Deserialize)]
+pub struct TotpCodeForm {
+    pub code: String,
+}
+
+// 6 digits every 30 seconds, as expected by authenticator apps, accepting the
+// codes of the previous and next steps to allow for clock skew
+fn build_totp(secret: &str, username: &str) -> Option<TOTP> {
+    let secret = Secret::Encoded(secret.to_string()).to_bytes().ok()?;
+    TOTP::new(
+        Algorithm::SHA1,
+        6,
+        1,
+        30,
+        secret,
+        Some(String::from(TOTP_ISSUER)),
+        username.to_string(),
+    )
+    .ok()
+}
+
+fn check_code(secret: &str, username: &str, code: &str) -> bool {
+    build_totp(secret, username)
+        .is_some_and(|totp| totp.check_current(code.trim()).unwrap_or(false))
+}
+
+async fn query_user_totp(
+    state: &AppState,
+    user_id: i32,
+) -> Result<(String, Option<String>), sqlx::Error> {
+    let mut query = QueryBuilder::new("SELECT username, totp_secret FROM users WHERE id = ");
+    query.push_bind(user_id);
+    query
+        .build_query_as::<(String, Option<String>)>()
+        .fetch_one(&state.pool)
+        .await
+}
+
+async fn set_user_totp(
+    state: &AppState,
+    user_id: i32,
+    secret: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    let mut query = QueryBuilder::new("UPDATE users SET totp_secret = ");
+    query.push_bind(secret);
+    query.push(" WHERE id = ");
+    query.push_bind(user_id);
+    query.build().execute(&state.pool).await?;
+
+    Ok(())
+}
+
+fn totp_error(msg: &str) -> axum::response::Response {
+    let response = html! {
+        p style="color: red;" { (msg) }
+    };
+    (
+        StatusCode::UNPROCESSABLE_ENTITY,
+        Html(response.into_string()),
+    )
+        .into_response()
+}
+
+fn internal_error(e: sqlx::Error) -> axum::response::Response {
+    warn!("Error while processing two-factor authentication: {}", e);
+    (StatusCode::INTERNAL_SERVER_ERROR, "").into_response()
+}
+
+// Replaces the login form with the one for the code
+pub async fn ask_for_code(session: &Session, user_id: i32) -> axum::response::Response {
+    if let Err(e) = session.insert(PENDING_USER_SESSION_NAME, user_id).await {
+        return handle_generic_inventory_error(e);
+    }
+
+    let mut headers = HeaderMap::new();
+    headers.insert("HX-Retarget", "article".parse().unwrap());
+    headers.insert("HX-Reswap", "innerHTML".parse().unwrap());
+    let response = html! {
+        form hx-post="/login/totp" hx-target="#login-error" hx-swap="innerHTML" {
+            div {
+                label { "Authentication code" }
+                input
+                type="text"
+                name="code"
+                inputmode="numeric"
+                autocomplete="one-time-code"
+                pattern="[0-9]{6}"
+                autofocus
+                required;
+            }
+            button type="submit" { "Verify" }
+        }
+        div id="login-error" style="color: red; margin-top: 10px;" {}
+    };
+    (headers, Html(response.into_string())).into_response()
+}
+
+pub async fn login_code_handler(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    session: Session,
+    Form(form): Form<TotpCodeForm>,
+) -> impl IntoResponse {
+    // (Shares the limit with password attempts)
+    let ip = state.login_limiter.client_ip(addr, &headers);
+    if let Some(wait) = state.login_limiter.locked_for(ip) {
+        return locked_out_response(ip, wait);
+    }
+
+    let Ok(Some(user_id)) = session.get::<i32>(PENDING_USER_SESSION_NAME).await else {
+        let mut headers = HeaderMap::new();
+        headers.insert("HX-Redirect", "/login".parse().unwrap());
+        return (headers, "").into_response();
+    };
+
+    let (username, secret) = match query_user_totp(&state, user_id).await {
+        Ok(user) => user,
+        Err(e) => return internal_error(e),
+    };
+
+    // (Two-factor authentication may have been disabled meanwhile)
+    let valid = match secret {
+        Some(secret) => check_code(&secret, &username, &form.code),
+        None => true,
+    };
+
+    if valid {
+        if let Err(e) = session.remove::<i32>(PENDING_USER_SESSION_NAME).await {
+            return handle_generic_inventory_error(e);
+        }
+        finish_login(&state, &session, ip, user_id).await
+    } else {
+        state.login_limiter.record_failure(ip);
+        metrics::counter!("login_failures_total", "step" => "totp").increment(1);
+        wrong_credentials_response()
+    }
+}
+
+async fn totp_enabled(state: &AppState, user_id: i32) -> bool {
+    match query_user_totp(state, user_id).await {
+        Ok((_, secret)) => secret.is_some(),
+        Err(e) => {
+            warn!("Error while querying user: {}", e);
+            false
+        }
+    }
+}
+
+fn html_totp_status(enabled: bool) -> Markup {
+    html! {
+        @if enabled {
+            p { "Enabled, a code from your authenticator app is asked for when logging in." }
+            form hx-post="/account/2fa/disable" hx-target="#totp-setup" {
+                div role="group" {
+                    input
+                    type="text"
+                    name="code"
+                    inputmode="numeric"
+                    autocomplete="one-time-code"
+                    placeholder="Current code"
+                    required;
+                    button class="secondary" type="submit" { "Disable" }
+                }
+            }
+        } @else {
+            p { "Disabled." }
+            button hx-post="/account/2fa/enable" hx-target="#totp-setup" { "Enable" }
+        }
+    }
+}
+
+pub async fn html_account_section(state: &AppState, user_id: i32) -> Markup {
+    let enabled = totp_enabled(state, user_id).await;
+
+    html! {
+        h4 { "Two-factor authentication" }
+        div id="totp-setup" {
+            (html_totp_status(enabled))
+        }
+    }
+}
+
+pub async fn enable_handler(State(state): State<AppState>, session: Session) -> impl IntoResponse {
+    let Some(user_id) = current_user(&session).await else {
+        return Redirect::to("/login").into_response();
+    };
+
+    info!("Setting up two-factor authentication for user {}", user_id);
+
+    let username = match query_user_totp(&state, user_id).await {
+        Ok((username, _)) => username,
+        Err(e) => return internal_error(e),
+    };
+
+    let Secret::Encoded(secret) = Secret::generate_secret().to_encoded() else {
+        unreachable!("to_encoded always returns an encoded secret");
+    };
+    let Some(totp) = build_totp(&secret, &username) else {
+        // (The label of the provisioning URI can't contain ':')
+        return totp_error("Two-factor authentication can't be set up for this username.");
+    };
+
+    let url = totp.get_url();
+    let qr = match QrCode::new(&url) {
+        Ok(code) => code.render::<svg::Color>().min_dimensions(200, 200).build(),
+        Err(e) => {
+            warn!("Error while generating QR code: {}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "").into_response();
+        }
+    };
+
+    if let Err(e) = session.insert(NEW_SECRET_SESSION_NAME, &secret).await {
+        return handle_generic_inventory_error(e);
+    }
+
+    let response = html! {
+        p { "Scan the code with your authenticator app, or enter the secret by hand." }
+        (PreEscaped(qr))
+        p { code { (secret) } }
+        form hx-post="/account/2fa/confirm" hx-target="#totp-setup" {
+            div role="group" {
+                input
+                type="text"
+                name="code"
+                inputmode="numeric"
+                autocomplete="one-time-code"
+                placeholder="Code from the app"
+                required;
+                button type="submit" { "Confirm" }
+            }
+        }
+    };
+    Html(response.into_string()).into_response()
+}
+
+pub async fn confirm_handler(
+    State(state): State<AppState>,
+    session: Session,
+    Form(form): Form<TotpCodeForm>,
+) -> impl IntoResponse {
+    let Some(user_id) = current_user(&session).await else {
+        return Redirect::to("/login").into_response();
+    };
+
+    let Ok(Some(secret)) = session.get::<String>(NEW_SECRET_SESSION_NAME).await else {
+        return totp_error("Start setting up two-factor authentication first.");
+    };
+
+    let username = match query_user_totp(&state, user_id).await {
+        Ok((username, _)) => username,
+        Err(e) => return internal_error(e),
+    };
+
+    if !check_code(&secret, &username, &form.code) {
+        return totp_error("Wrong code, check the clock of your device and try again.");
+    }
+
+    if let Err(e) = set_user_totp(&state, user_id, Some(&secret)).await {
+        return internal_error(e);
+    }
+    if let Err(e) = session.remove::<String>(NEW_SECRET_SESSION_NAME).await {
+        return handle_generic_inventory_error(e);
+    }
+
+    info!("Enabled two-factor authentication for user {}", user_id);
+    Html(html_totp_status(true).into_string()).into_response()
+}
+
+pub async fn disable_handler(
+    State(state): State<AppState>,
+    session: Session,
+    Form(form): Form<TotpCodeForm>,
+) -> impl IntoResponse {
+    let Some(user_id) = current_user(&session).await else {
+        return Redirect::to("/login").into_response();
+    };
+
+    let (username, secret) = match query_user_totp(&state, user_id).await {
+        Ok(user) => user,
+        Err(e) => return internal_error(e),
+    };
+
+    if let Some(secret) = secret
+        && !check_code(&secret, &username, &form.code)
+    {
+        return totp_error("Wrong code.");
+    }
+
+    if let Err(e) = set_user_totp(&state, user_id, None).await {
+        return internal_error(e);
+    }
+
+    info!("Disabled two-factor authentication for user {}", user_id);
+    Html(html_totp_status(false).into_string()).into_response()
+}